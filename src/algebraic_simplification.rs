@@ -0,0 +1,397 @@
+use std::cmp::Ordering;
+use std::collections::HashSet;
+
+use petgraph::graph::{EdgeIndex, NodeIndex};
+use petgraph::visit::EdgeRef;
+use petgraph::Direction::{Incoming, Outgoing};
+
+use crate::constant_block::{get_constant_value, ConstantBlock};
+use crate::function::{find_line, get_operation, remove_instruction, replace_uses};
+use crate::instruction::Operation;
+use crate::live_analysis::BasicBlockGraph;
+use crate::program::Program;
+
+type LineNumber = isize;
+
+// folded constants have to fit a register and, like every constant line, cannot be negative
+const MAX_CONSTANT: isize = i32::MAX as isize;
+
+enum Simplification {
+    /// the instruction computes a value that already exists
+    Value(LineNumber),
+    Rewrite(Operation),
+    /// a comparison whose outcome is known, its branches are decided
+    Comparison(Ordering),
+    /// a comparison with the constant on the left, its branches are mirrored
+    SwapComparison,
+}
+
+struct Simplifier<'a> {
+    g: &'a mut BasicBlockGraph,
+    constants: &'a mut ConstantBlock,
+    // lines reassociation has read through, they may have no uses left
+    reassociated: HashSet<LineNumber>,
+}
+
+// the branch that is taken for b < a exactly when the given one is for a < b
+fn mirror_branch(operation: Operation) -> Operation {
+    match operation {
+        Operation::Blt(comparison, block) => Operation::Bgt(comparison, block),
+        Operation::Bgt(comparison, block) => Operation::Blt(comparison, block),
+        Operation::Ble(comparison, block) => Operation::Bge(comparison, block),
+        Operation::Bge(comparison, block) => Operation::Ble(comparison, block),
+        _ => operation,
+    }
+}
+
+impl Simplifier<'_> {
+    fn get_users(&self, line: LineNumber) -> Vec<(NodeIndex, usize)> {
+        self.g.node_indices()
+            .flat_map(|block| self.g[block].instructions.iter().enumerate()
+                .filter(move |(_, instruction)| instruction.operation.uses_line(line))
+                .map(move |(position, _)| (block, position)))
+            .collect()
+    }
+
+    fn remove(&mut self, line: LineNumber) {
+        let (block, position) = find_line(self.g, line).unwrap();
+        remove_instruction(self.g, block, position);
+    }
+
+    // The edge out of a decided branch's block that is no longer taken. None when both edges go
+    // to the same block, or when the block the dead edge goes to has phis over more than it and
+    // one other edge.
+    fn get_dead_edge(&self, block: NodeIndex, branch: Operation, taken: bool) -> Option<EdgeIndex> {
+        let target = NodeIndex::new(branch.get_branch_target()? as usize);
+        let edges: Vec<_> = self.g.edges_directed(block, Outgoing).collect();
+        if edges.len() != 2 || edges[0].target() == edges[1].target() {
+            return None;
+        }
+        let dead = edges.iter().find(|edge| (edge.target() == target) != taken)?;
+        let has_phis = self.g[dead.target()].instructions.iter().any(|instruction| matches!(instruction.operation, Operation::Phi(_, _)));
+        (!has_phis || self.g.edges_directed(dead.target(), Incoming).count() == 2).then_some(dead.id())
+    }
+
+    // takes the edge out of the graph, the phis of the block it went to become the operand of the other edge
+    fn remove_edge(&mut self, edge: EdgeIndex) {
+        let target = self.g.edge_endpoints(edge).unwrap().1;
+        let first_incoming = self.g.edges_directed(target, Incoming).map(|incoming| incoming.id()).min();
+        let phis: Vec<(LineNumber, LineNumber)> = self.g[target].instructions.iter()
+            .filter_map(|instruction| match instruction.operation {
+                Operation::Phi(left, right) => Some((instruction.get_line_number(), if first_incoming == Some(edge) { right } else { left })),
+                _ => None,
+            })
+            .collect();
+
+        // the other edges are added back in the same order, which is what phi operands are ordered by
+        let edges: Vec<_> = self.g.edge_references()
+            .filter(|other| other.id() != edge)
+            .map(|other| (other.source(), other.target(), *other.weight()))
+            .collect();
+        self.g.clear_edges();
+        for (source, target, weight) in edges {
+            self.g.add_edge(source, target, weight);
+        }
+
+        for (line, operand) in phis {
+            self.remove(line);
+            replace_uses(self.g, line, operand);
+        }
+    }
+
+    fn get_constant_line(&mut self, value: isize) -> Option<LineNumber> {
+        (0..=MAX_CONSTANT).contains(&value).then(|| self.constants.get_constant(value))
+    }
+
+    fn fold(&mut self, value: Option<isize>) -> Option<Simplification> {
+        value.and_then(|value| self.get_constant_line(value)).map(Simplification::Value)
+    }
+
+    // a line that adds or subtracts a constant, as the line it starts from and the signed offset
+    fn get_offset(&self, line: LineNumber) -> Option<(LineNumber, isize)> {
+        match get_operation(self.g, line)? {
+            Operation::Add(base, constant) if base > 0 => Some((base, get_constant_value(constant)?)),
+            Operation::Sub(base, constant) if base > 0 => Some((base, -get_constant_value(constant)?)),
+            _ => None,
+        }
+    }
+
+    // (base + c1) + c2 is base + (c1 + c2)
+    fn reassociate_offset(&mut self, line: LineNumber, offset: isize) -> Option<Simplification> {
+        let (base, inner_offset) = self.get_offset(line)?;
+        let offset = inner_offset.checked_add(offset)?;
+        let simplification = match offset.cmp(&0) {
+            Ordering::Equal => Simplification::Value(base),
+            Ordering::Greater => Simplification::Rewrite(Operation::Add(base, self.get_constant_line(offset)?)),
+            Ordering::Less => Simplification::Rewrite(Operation::Sub(base, self.get_constant_line(-offset)?)),
+        };
+        self.reassociated.insert(line);
+        Some(simplification)
+    }
+
+    // (base * c1) * c2 is base * (c1 * c2)
+    fn reassociate_factor(&mut self, line: LineNumber, factor: isize) -> Option<Simplification> {
+        let Operation::Mul(base, inner_factor) = get_operation(self.g, line)? else {
+            return None;
+        };
+        let factor = get_constant_value(inner_factor)?.checked_mul(factor)?;
+        if base <= 0 {
+            return None;
+        }
+        let simplification = Simplification::Rewrite(Operation::Mul(base, self.get_constant_line(factor)?));
+        self.reassociated.insert(line);
+        Some(simplification)
+    }
+
+    fn simplify(&mut self, operation: Operation) -> Option<Simplification> {
+        match operation {
+            Operation::Add(left, right) => match (get_constant_value(left), get_constant_value(right)) {
+                (Some(a), Some(b)) => self.fold(a.checked_add(b)),
+                (Some(_), None) => Some(Simplification::Rewrite(Operation::Add(right, left))),
+                (None, Some(0)) => Some(Simplification::Value(left)),
+                (None, Some(b)) => self.reassociate_offset(left, b),
+                (None, None) => None,
+            },
+            Operation::Sub(left, right) => match (get_constant_value(left), get_constant_value(right)) {
+                (Some(a), Some(b)) => self.fold(a.checked_sub(b)),
+                _ if left == right => Some(Simplification::Value(0)),
+                (None, Some(0)) => Some(Simplification::Value(left)),
+                (None, Some(b)) => self.reassociate_offset(left, -b),
+                _ => None,
+            },
+            Operation::Mul(left, right) => match (get_constant_value(left), get_constant_value(right)) {
+                (Some(a), Some(b)) => self.fold(a.checked_mul(b)),
+                (Some(_), None) => Some(Simplification::Rewrite(Operation::Mul(right, left))),
+                (None, Some(0)) => Some(Simplification::Value(0)),
+                (None, Some(1)) => Some(Simplification::Value(left)),
+                (None, Some(b)) => self.reassociate_factor(left, b),
+                (None, None) => None,
+            },
+            Operation::Div(left, right) => match (get_constant_value(left), get_constant_value(right)) {
+                (Some(a), Some(b)) if b != 0 => self.fold(Some(a / b)),
+                (None, Some(1)) => Some(Simplification::Value(left)),
+                _ => None,
+            },
+            Operation::Cmp(left, right) => match (get_constant_value(left), get_constant_value(right)) {
+                _ if left == right => Some(Simplification::Comparison(Ordering::Equal)),
+                (Some(a), Some(b)) => Some(Simplification::Comparison(a.cmp(&b))),
+                (Some(_), None) => Some(Simplification::SwapComparison),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    // A comparison can only be changed together with the branches that read it. A decided branch
+    // becomes a BRA or falls through, and the edge it no longer takes leaves the graph.
+    fn rewrite_comparison(&mut self, line: LineNumber, simplification: Simplification) -> bool {
+        let users = self.get_users(line);
+        if users.iter().any(|(block, position)| !self.g[*block].instructions[*position].operation.is_conditional_branch()) {
+            return false;
+        }
+
+        match simplification {
+            Simplification::Comparison(ordering) => {
+                let mut decided = Vec::new();
+                for (block, position) in users {
+                    let branch = self.g[block].instructions[position].operation;
+                    let taken = branch.is_taken(ordering).unwrap();
+                    let Some(dead_edge) = self.get_dead_edge(block, branch, taken) else {
+                        return false;
+                    };
+                    decided.push((block, position, taken, dead_edge));
+                }
+                let mut dead_edges = Vec::new();
+                for (block, position, taken, dead_edge) in decided {
+                    let branch = &mut self.g[block].instructions[position].operation;
+                    *branch = if taken { Operation::Bra(branch.get_branch_target().unwrap()) } else { Operation::Empty };
+                    dead_edges.push(dead_edge);
+                }
+                // removing an edge renumbers the ones after it, so the later ones go first
+                dead_edges.sort_by_key(|dead_edge| std::cmp::Reverse(*dead_edge));
+                for dead_edge in dead_edges {
+                    self.remove_edge(dead_edge);
+                }
+                self.remove(line);
+            },
+            Simplification::SwapComparison => {
+                for (block, position) in users {
+                    let branch = &mut self.g[block].instructions[position].operation;
+                    *branch = mirror_branch(*branch);
+                }
+                let (block, position) = find_line(self.g, line).unwrap();
+                if let Operation::Cmp(left, right) = self.g[block].instructions[position].operation {
+                    self.g[block].instructions[position].operation = Operation::Cmp(right, left);
+                }
+            },
+            _ => unreachable!(),
+        }
+        true
+    }
+
+    // one sweep over every instruction, returns how many were simplified
+    fn simplify_once(&mut self) -> usize {
+        let lines: Vec<LineNumber> = self.g.node_indices()
+            .flat_map(|block| self.g[block].instructions.iter().map(|instruction| instruction.get_line_number()).collect::<Vec<_>>())
+            .collect();
+
+        let mut simplified = 0;
+        for line in lines {
+            let Some(operation) = get_operation(self.g, line) else {
+                continue;
+            };
+            let Some(simplification) = self.simplify(operation) else {
+                continue;
+            };
+            let applied = match simplification {
+                Simplification::Value(value) => {
+                    self.remove(line);
+                    replace_uses(self.g, line, value);
+                    true
+                },
+                Simplification::Rewrite(operation) => {
+                    let (block, position) = find_line(self.g, line).unwrap();
+                    self.g[block].instructions[position].operation = operation;
+                    true
+                },
+                Simplification::Comparison(_) | Simplification::SwapComparison => self.rewrite_comparison(line, simplification),
+            };
+            simplified += applied as usize;
+        }
+        simplified
+    }
+
+    // arithmetic that was only there for an instruction that now skips over it
+    fn remove_unused_reassociated(&mut self) {
+        let mut lines: Vec<LineNumber> = self.reassociated.drain().collect();
+        lines.sort();
+        for line in lines.into_iter().rev() {
+            if find_line(self.g, line).is_some() && self.get_users(line).is_empty() {
+                self.remove(line);
+            }
+        }
+    }
+}
+
+/// Applies algebraic identities to the arithmetic of every function until none applies:
+/// x+0, x-0, x*1 and x/1 are x, x*0 and x-x are 0, constant operands are folded or moved to
+/// the right, chains of constant additions or multiplications are combined and comparisons
+/// with a known outcome decide their branches. A simplified instruction is removed and its
+/// uses read the value it was found to equal, which leaves equal expressions written the
+/// same way for CSE and constants in place for propagation. Returns how many instructions
+/// were simplified.
+pub fn simplify_algebra(program: &mut Program) -> usize {
+    let mut function_names: Vec<String> = program.functions.keys().cloned().collect();
+    function_names.sort();
+
+    let mut simplified = 0;
+    for function_name in function_names {
+        let function = program.functions.get_mut(&function_name).unwrap();
+        let mut simplifier = Simplifier { g: &mut function.bb_graph, constants: &mut program.constant_block, reassociated: HashSet::new() };
+        loop {
+            let count = simplifier.simplify_once();
+            if count == 0 {
+                break;
+            }
+            simplified += count;
+        }
+        simplifier.remove_unused_reassociated();
+    }
+    simplified
+}
+
+#[cfg(test)]
+mod algebraic_simplification_tests {
+    use super::*;
+    use crate::test_helpers::{emulate, get_program, interpret};
+
+    fn get_operations(program: &Program) -> Vec<Operation> {
+        program.get_fn("main").bb_graph.node_weights()
+            .flat_map(|block| &block.instructions)
+            .map(|instruction| instruction.operation)
+            .filter(|operation| !matches!(operation, Operation::Empty))
+            .collect()
+    }
+
+    #[test]
+    fn test_identities() {
+        let input = "main var a; { let a <- call InputNum(); call OutputNum(a + 0); call OutputNum(a * 1); call OutputNum(a * 0); call OutputNum(a - a); call OutputNum(a / 1) }.";
+        let mut program = get_program(input);
+        let expected = interpret(&program, "7");
+
+        assert!(simplify_algebra(&mut program) >= 5);
+        let operations = get_operations(&program);
+        assert!(operations.iter().all(|operation| !matches!(operation, Operation::Add(_, _) | Operation::Sub(_, _) | Operation::Mul(_, _) | Operation::Div(_, _))));
+        assert_eq!(operations[1..6], [Operation::Write(1), Operation::Write(1), Operation::Write(0), Operation::Write(0), Operation::Write(1)]);
+        assert_eq!(interpret(&program, "7"), expected);
+        assert_eq!(expected, "7 7 0 0 7 ");
+    }
+
+    #[test]
+    fn test_constant_chains() {
+        let input = "main var a, b; { let a <- call InputNum(); let b <- 2 + (a + 1); call OutputNum(b - 5); call OutputNum(3 * (a * 4)) }.";
+        let mut program = get_program(input);
+        let expected = interpret(&program, "10");
+
+        simplify_algebra(&mut program);
+        let arithmetic: Vec<Operation> = get_operations(&program).into_iter()
+            .filter(|operation| matches!(operation, Operation::Add(_, _) | Operation::Sub(_, _) | Operation::Mul(_, _)))
+            .collect();
+        assert_eq!(arithmetic, vec![Operation::Sub(1, -2), Operation::Mul(1, -12)]);
+        assert_eq!(interpret(&program, "10"), expected);
+        assert_eq!(expected, "8 120 ");
+    }
+
+    #[test]
+    fn test_comparisons() {
+        let input = "main var a; { let a <- call InputNum(); if a < a then call OutputNum(1) else call OutputNum(2) fi; if 3 < a then call OutputNum(3) fi; if 1 < 2 then call OutputNum(4) fi }.";
+        let mut program = get_program(input);
+        let expected: Vec<String> = ["1", "5"].iter().map(|input| interpret(&program, input)).collect();
+
+        simplify_algebra(&mut program);
+        let operations = get_operations(&program);
+        let comparisons: Vec<&Operation> = operations.iter().filter(|operation| matches!(operation, Operation::Cmp(_, _))).collect();
+        assert_eq!(comparisons, vec![&Operation::Cmp(1, -3)]);
+        // 3 < a branches away with bge, a > 3 with ble
+        let comparison_line = find_line(&program, Operation::Cmp(1, -3));
+        assert!(operations.iter().any(|operation| matches!(operation, Operation::Ble(line, _) if *line == comparison_line)));
+
+        assert_eq!(["1", "5"].iter().map(|input| interpret(&program, input)).collect::<Vec<_>>(), expected);
+        assert_eq!(expected, vec!["2 4 ", "2 3 4 "]);
+    }
+
+    #[test]
+    fn test_decided_branches_run() {
+        // a branch that is decided leaves only a BRA or a fall-through, the layout must not fall into the arm it skipped
+        let inputs = [
+            ("main var x, z; { let x <- call InputNum(); let z <- 0; if x == x then let z <- z + 1 else let z <- z + 2 fi; call OutputNum(z) }.", "1 "),
+            ("main var a, b; { let a <- call InputNum(); let b <- a + 4; if a == a then call OutputNum(b) fi; call OutputNum(a) }.", "9 5 "),
+            ("main var a; { let a <- call InputNum(); if a != a then call OutputNum(1) fi; while a < a do let a <- a + 1 od; call OutputNum(a) }.", "5 "),
+        ];
+        for (input, expected) in inputs {
+            let mut program = get_program(input);
+            simplify_algebra(&mut program);
+            assert!(get_operations(&program).iter().all(|operation| !operation.is_conditional_branch()), "{}", input);
+            assert_eq!(interpret(&program, "5"), expected, "{}", input);
+            assert_eq!(emulate(&program, "5"), expected, "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_function_entry_kept() {
+        // the function starts with 2 * 3, calls still have to find it once that is folded
+        let input = "main function six(); { return 2 * 3 }; { call OutputNum(call six()) }.";
+        let mut program = get_program(input);
+        simplify_algebra(&mut program);
+        assert_eq!(program.get_fn("six").bb_graph[NodeIndex::new(0)].instructions[0].operation, Operation::Empty);
+        assert_eq!(interpret(&program, ""), "6 ");
+    }
+
+    fn find_line(program: &Program, operation: Operation) -> LineNumber {
+        program.get_fn("main").bb_graph.node_weights()
+            .flat_map(|block| &block.instructions)
+            .find(|instruction| instruction.operation == operation)
+            .unwrap()
+            .get_line_number()
+    }
+}
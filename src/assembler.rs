@@ -1,12 +1,62 @@
 use crate::code_gen::AssemblyInstruction;
-use crate::code_gen::Fmt; 
+use crate::code_gen::{fits_in_immediate, Fmt, OpCode, OP_CODES};
+use std::fmt;
 
-type MachineCodeInstruction = u32; 
+pub type MachineCodeInstruction = u32; 
 
-type MachineCodeInstructions = Vec<u32>;
+pub type MachineCodeInstructions = Vec<u32>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Field {
+    A,
+    B,
+    C,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AssembleErrorKind {
+    /// a register field above R31
+    NotARegister,
+    /// an F1 constant outside the sign extended 16 bits
+    ImmediateOutOfRange,
+    /// a branch further away than 16 bits of words can reach
+    BranchOffsetOutOfRange,
+    /// a JSR target outside the 26 bit address
+    AddressOutOfRange,
+}
+
+/// An instruction with a field that does not fit its encoding, by its index in the program.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AssembleError {
+    pub index: usize,
+    pub instruction: AssemblyInstruction,
+    pub field: Field,
+    pub value: isize,
+    pub kind: AssembleErrorKind,
+}
+
+impl fmt::Display for AssembleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let field = match self.field {
+            Field::A => "a",
+            Field::B => "b",
+            Field::C => "c",
+        };
+        let problem = match self.kind {
+            AssembleErrorKind::NotARegister => "is not a register",
+            AssembleErrorKind::ImmediateOutOfRange => "does not fit in a 16 bit immediate",
+            AssembleErrorKind::BranchOffsetOutOfRange => "is a branch offset out of 16 bit range",
+            AssembleErrorKind::AddressOutOfRange => "does not fit in a 26 bit address",
+        };
+        write!(f, "instruction {} ({}): {} = {} {}", self.index, self.instruction, field, self.value, problem)
+    }
+}
 
 
-fn convert_assembly_to_machine_code(asm: AssemblyInstruction) -> MachineCodeInstruction {
+/// Encodes one instruction, the index of a returned error is always 0.
+pub fn convert_assembly_to_machine_code(asm: AssemblyInstruction) -> Result<MachineCodeInstruction, AssembleError> {
+    check_fields(&asm)?;
+
     let a = asm.get_a(); 
     let b = asm.get_b();
     let c = asm.get_c(); 
@@ -17,21 +67,20 @@ fn convert_assembly_to_machine_code(asm: AssemblyInstruction) -> MachineCodeInst
     let op_code = asm.get_op_code();
     let fmt = asm.get_fmt();
 
-    let mut ret: MachineCodeInstruction = 0; 
-    match fmt {
+    let word = match fmt {
         Fmt::F1 => {
             // 6op 5reg 5reg 16reg 
             
 
             let lower_16 = get_lower_n_bits(c, 16);
             let mut middle = get_lower_n_bits(b, 5);
-            middle = middle << 16; 
+            middle <<= 16; 
             let mut upper = get_lower_n_bits(a, 5); 
-            upper = upper << (16 + 5); 
+            upper <<= 16 + 5; 
             let mut op = get_lower_n_bits(op_code as u32, 6);
-            op = op << (16 + 5 + 5);
+            op <<= 16 + 5 + 5;
 
-            return lower_16 + middle + upper + op; 
+            lower_16 + middle + upper + op
             
 
 
@@ -41,13 +90,13 @@ fn convert_assembly_to_machine_code(asm: AssemblyInstruction) -> MachineCodeInst
             //6op 5reg 5reg 11none 5c
             let lower_5 = get_lower_n_bits(c, 5);
             let mut middle = get_lower_n_bits(b, 5);
-            middle = middle << 16; 
+            middle <<= 16; 
             let mut upper = get_lower_n_bits(a, 5); 
-            upper = upper << (16 + 5); 
+            upper <<= 16 + 5; 
             let mut op = get_lower_n_bits(op_code as u32, 6);
-            op = op << (16 + 5 + 5);
+            op <<= 16 + 5 + 5;
 
-            return lower_5 + middle + upper + op; 
+            lower_5 + middle + upper + op
             
 
             
@@ -60,15 +109,47 @@ fn convert_assembly_to_machine_code(asm: AssemblyInstruction) -> MachineCodeInst
             let lower_26= get_lower_n_bits(c, 26);
             
             let mut op = get_lower_n_bits(op_code as u32, 6);
-            op = op << (26);
+            op <<= 26;
 
-            return op + lower_26;
+            op + lower_26
 
             
+        }
+    };
+    Ok(word)
+}
+
+// fields are rejected rather than silently cut down to their width
+fn check_fields(asm: &AssemblyInstruction) -> Result<(), AssembleError> {
+    let error = |field, value, kind| Err(AssembleError { index: 0, instruction: *asm, field, value, kind });
+
+    for (field, register) in [(Field::A, asm.get_a()), (Field::B, asm.get_b())] {
+        if let Some(register) = register.filter(|register| *register > 31) {
+            return error(field, register as isize, AssembleErrorKind::NotARegister);
         }
     }
 
-    return 0
+    match asm.get_fmt() {
+        Fmt::F1 => {
+            if let Some(constant) = asm.get_const().filter(|constant| !fits_in_immediate(*constant)) {
+                let is_branch = matches!(asm.get_op_code(), 40..=46);
+                let kind = if is_branch { AssembleErrorKind::BranchOffsetOutOfRange } else { AssembleErrorKind::ImmediateOutOfRange };
+                return error(Field::C, constant, kind);
+            }
+        },
+        Fmt::F2 => {
+            if let Some(register) = asm.get_c().filter(|register| *register > 31) {
+                return error(Field::C, register as isize, AssembleErrorKind::NotARegister);
+            }
+        },
+        Fmt::F3 => {
+            let address = asm.get_const().unwrap();
+            if !(0..1 << 26).contains(&address) {
+                return error(Field::C, address, AssembleErrorKind::AddressOutOfRange);
+            }
+        },
+    }
+    Ok(())
 }
 
 fn get_lower_n_bits(num: u32, n: u32) -> u32 {
@@ -76,15 +157,186 @@ fn get_lower_n_bits(num: u32, n: u32) -> u32 {
 }
 
 
-fn get_machine_code_instructions(asm_instructions: Vec<AssemblyInstruction>) -> MachineCodeInstructions {
+pub fn get_machine_code_instructions(asm_instructions: Vec<AssemblyInstruction>) -> Result<MachineCodeInstructions, AssembleError> {
     let mut mci = MachineCodeInstructions::new(); 
 
-    for instruction in asm_instructions {
-        mci.push(convert_assembly_to_machine_code(instruction)); 
+    for (index, instruction) in asm_instructions.into_iter().enumerate() {
+        mci.push(convert_assembly_to_machine_code(instruction).map_err(|error| AssembleError { index, ..error })?); 
+    }
+
+    Ok(mci)
+}
+
+
+/// Returns the format an op code is encoded in, None if the op code does not exist.
+pub fn get_fmt_of_op_code(op_code: OpCode) -> Option<Fmt> {
+    OP_CODES.iter().find(|entry| entry.op_code == op_code).map(|entry| entry.fmt)
+}
+
+/// Turns a machine code word back into the instruction it encodes, None if the op code does not exist.
+/// F1 constants are sign extended from 16 bits, the JSR address is taken as unsigned.
+pub fn decode(word: MachineCodeInstruction) -> Option<AssemblyInstruction> {
+    let op_code = (word >> 26) as OpCode;
+    let a = ((word >> 21) & 0x1F) as u8;
+    let b = ((word >> 16) & 0x1F) as u8;
+
+    let c = match get_fmt_of_op_code(op_code)? {
+        Fmt::F1 => (word & 0xFFFF) as u16 as i16 as isize,
+        Fmt::F2 => (word & 0x1F) as isize,
+        Fmt::F3 => (word & 0x3FF_FFFF) as isize,
+    };
+
+    get_instruction_from_fields(op_code, a, b, c)
+}
+
+/// Builds the instruction an op code names from its a, b and c fields, None if the op code does not exist.
+/// Fields an instruction does not have are ignored.
+pub fn get_instruction_from_fields(op_code: OpCode, a: u8, b: u8, c: isize) -> Option<AssemblyInstruction> {
+    OP_CODES.iter().find(|entry| entry.op_code == op_code).map(|entry| (entry.build)(a, b, c))
+}
+
+/// The byte address an instruction at the given word jumps to, None if it does not jump to a fixed place.
+pub fn get_branch_target(address: usize, instruction: &AssemblyInstruction) -> Option<isize> {
+    match *instruction {
+        // branch offsets count words from the branch itself
+        AssemblyInstruction::BEQ(_, offset)
+        | AssemblyInstruction::BNE(_, offset)
+        | AssemblyInstruction::BLT(_, offset)
+        | AssemblyInstruction::BGE(_, offset)
+        | AssemblyInstruction::BLE(_, offset)
+        | AssemblyInstruction::BGT(_, offset)
+        | AssemblyInstruction::BSR(offset) => Some((address as isize + offset) * 4),
+        AssemblyInstruction::JSR(target) => Some(target),
+        _ => None,
+    }
+}
+
+/// Lists a machine code image one word per line with its byte address, the raw word and the
+/// instruction it decodes to, followed by the byte address it branches to if it has one.
+pub fn disassemble(program: &[MachineCodeInstruction]) -> String {
+    let mut listing = String::new();
+
+    for (address, word) in program.iter().enumerate() {
+        let line = match decode(*word) {
+            Some(instruction) => match get_branch_target(address, &instruction) {
+                Some(target) => format!("{:>6}: {:08x}  {} -> {}", address * 4, word, instruction, target),
+                None => format!("{:>6}: {:08x}  {}", address * 4, word, instruction),
+            },
+            None => format!("{:>6}: {:08x}  <illegal>", address * 4, word),
+        };
+        listing.push_str(&line);
+        listing.push('\n');
     }
 
-    mci
+    listing
 }
 
+#[cfg(test)]
+mod assembler_tests {
+    use super::*;
+
+    // every instruction with its operands filled from the given registers and constant
+    fn get_all_instructions(a: u8, b: u8, c: u8, constant: isize) -> Vec<AssemblyInstruction> {
+        vec![
+            AssemblyInstruction::ADD(a, b, c), AssemblyInstruction::SUB(a, b, c), AssemblyInstruction::MUL(a, b, c),
+            AssemblyInstruction::DIV(a, b, c), AssemblyInstruction::MOD(a, b, c), AssemblyInstruction::CMP(a, b, c),
+            AssemblyInstruction::OR(a, b, c), AssemblyInstruction::AND(a, b, c), AssemblyInstruction::BIC(a, b, c),
+            AssemblyInstruction::XOR(a, b, c), AssemblyInstruction::LSH(a, b, c), AssemblyInstruction::ASH(a, b, c),
+            AssemblyInstruction::CHK(a, c),
+            AssemblyInstruction::ADDI(a, b, constant), AssemblyInstruction::SUBI(a, b, constant), AssemblyInstruction::MULI(a, b, constant),
+            AssemblyInstruction::DIVI(a, b, constant), AssemblyInstruction::MODI(a, b, constant), AssemblyInstruction::CMPI(a, b, constant),
+            AssemblyInstruction::ORI(a, b, constant), AssemblyInstruction::ANDI(a, b, constant), AssemblyInstruction::BICI(a, b, constant),
+            AssemblyInstruction::XORI(a, b, constant), AssemblyInstruction::LSHI(a, b, constant), AssemblyInstruction::ASHI(a, b, constant),
+            AssemblyInstruction::CHKI(a, constant),
+            AssemblyInstruction::LDW(a, b, constant), AssemblyInstruction::LDX(a, b, c), AssemblyInstruction::POP(a, b, constant),
+            AssemblyInstruction::STW(a, b, constant), AssemblyInstruction::STX(a, b, c), AssemblyInstruction::PSH(a, b, constant),
+            AssemblyInstruction::BEQ(a, constant), AssemblyInstruction::BNE(a, constant), AssemblyInstruction::BLT(a, constant),
+            AssemblyInstruction::BGE(a, constant), AssemblyInstruction::BLE(a, constant), AssemblyInstruction::BGT(a, constant),
+            AssemblyInstruction::BSR(constant), AssemblyInstruction::JSR(constant.rem_euclid(1 << 26)), AssemblyInstruction::RET(c as isize),
+            AssemblyInstruction::RDD(a), AssemblyInstruction::WRD(b), AssemblyInstruction::WRH(b), AssemblyInstruction::WRL,
+        ]
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let registers = [0, 1, 15, 26, 31];
+        let constants = [i16::MIN as isize, -4, -1, 0, 1, 4, i16::MAX as isize];
+
+        for a in registers {
+            for (b, c) in registers.iter().zip(registers.iter().rev()) {
+                for constant in constants {
+                    for instruction in get_all_instructions(a, *b, *c, constant) {
+                        let word = convert_assembly_to_machine_code(instruction).unwrap();
+                        assert_eq!(decode(word), Some(instruction), "{:08x}", word);
+                        assert_eq!(convert_assembly_to_machine_code(decode(word).unwrap()), Ok(word));
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_op_code_table() {
+        let instructions = get_all_instructions(1, 2, 3, 4);
+        assert_eq!(instructions.len(), OP_CODES.len());
+
+        // each variant finds the row named after it, and each row has its own op code
+        for (instruction, entry) in instructions.iter().zip(OP_CODES.iter()) {
+            assert!(format!("{:?}", instruction).starts_with(entry.mnemonic), "{:?}", instruction);
+            assert_eq!(instruction.get_op_code(), entry.op_code);
+            assert_eq!(OP_CODES.iter().filter(|other| other.op_code == entry.op_code).count(), 1);
+        }
+    }
+
+    #[test]
+    fn test_sign_extension() {
+        assert_eq!(decode(0x4022_FFFC), Some(AssemblyInstruction::ADDI(1, 2, -4)));
+        assert_eq!(decode(0xA000_8000), Some(AssemblyInstruction::BEQ(0, i16::MIN as isize)));
+        assert_eq!(decode(0xC3FF_FFFC), Some(AssemblyInstruction::JSR(0x3FF_FFFC)));
+        assert_eq!(decode(0x1800_0000), None);
+        assert_eq!(decode(0xFC00_0000), None);
+    }
+
+    #[test]
+    fn test_errors() {
+        let error = |instructions: Vec<AssemblyInstruction>| get_machine_code_instructions(instructions).unwrap_err();
 
+        let immediate = error(vec![AssemblyInstruction::WRL, AssemblyInstruction::ADDI(1, 0, 40000)]);
+        assert_eq!(immediate, AssembleError {
+            index: 1,
+            instruction: AssemblyInstruction::ADDI(1, 0, 40000),
+            field: Field::C,
+            value: 40000,
+            kind: AssembleErrorKind::ImmediateOutOfRange,
+        });
+        assert_eq!(immediate.to_string(), "instruction 1 (ADDI R1, R0, 40000): c = 40000 does not fit in a 16 bit immediate");
+
+        assert_eq!(error(vec![AssemblyInstruction::ADD(32, 1, 2)]).to_string(), "instruction 0 (ADD R32, R1, R2): a = 32 is not a register");
+        assert_eq!(error(vec![AssemblyInstruction::STW(1, 40, 0)]).field, Field::B);
+        assert_eq!(error(vec![AssemblyInstruction::SUB(1, 2, 99)]).to_string(), "instruction 0 (SUB R1, R2, R99): c = 99 is not a register");
+        assert_eq!(error(vec![AssemblyInstruction::BNE(1, -40000)]).kind, AssembleErrorKind::BranchOffsetOutOfRange);
+        assert_eq!(error(vec![AssemblyInstruction::JSR(-4)]).to_string(), "instruction 0 (JSR -4): c = -4 does not fit in a 26 bit address");
+    }
 
+    #[test]
+    fn test_disassemble() {
+        let mut program = get_machine_code_instructions(vec![
+            AssemblyInstruction::ADDI(1, 0, 5),
+            AssemblyInstruction::BEQ(1, 2),
+            AssemblyInstruction::JSR(0),
+            AssemblyInstruction::WRD(1),
+            AssemblyInstruction::BSR(-3),
+        ]).unwrap();
+        program.push(0xFFFF_FFFF);
+
+        assert_eq!(disassemble(&program), [
+            "     0: 40200005  ADDI R1, R0, 5",
+            "     4: a0200002  BEQ R1, 2 -> 12",
+            "     8: c0000000  JSR 0 -> 0",
+            "    12: cc010000  WRD R1",
+            "    16: b800fffd  BSR -3 -> 4",
+            "    20: ffffffff  <illegal>",
+            "",
+        ].join("\n"));
+    }
+}
@@ -0,0 +1,292 @@
+use crate::code_gen::{AssemblyInstruction, AssemblyInstructions, AssemblyItem, Fmt, Label, Operands, OP_CODES};
+use std::collections::HashMap;
+use std::fmt;
+
+// writes the instruction with its target given as text, either a number or a label
+fn write_instruction(f: &mut fmt::Formatter<'_>, instruction: &AssemblyInstruction, target: &dyn fmt::Display) -> fmt::Result {
+    let mnemonic = instruction.get_mnemonic();
+    let a = instruction.get_a().unwrap_or(0);
+    let b = instruction.get_b().unwrap_or(0);
+    let c = instruction.get_c().unwrap_or(0);
+    let constant = instruction.get_const().unwrap_or(0);
+
+    match instruction.get_operands() {
+        Operands::ThreeRegisters => write!(f, "{} R{}, R{}, R{}", mnemonic, a, b, c),
+        Operands::TwoRegisters => write!(f, "{} R{}, R{}", mnemonic, a, c),
+        Operands::TwoRegistersConstant => write!(f, "{} R{}, R{}, {}", mnemonic, a, b, constant),
+        Operands::RegisterConstant => write!(f, "{} R{}, {}", mnemonic, a, constant),
+        Operands::RegisterTarget => write!(f, "{} R{}, {}", mnemonic, a, target),
+        Operands::Target => write!(f, "{} {}", mnemonic, target),
+        Operands::RegisterC => write!(f, "{} R{}", mnemonic, c),
+        Operands::RegisterA => write!(f, "{} R{}", mnemonic, a),
+        Operands::RegisterB => write!(f, "{} R{}", mnemonic, b),
+        Operands::Nothing => write!(f, "{}", mnemonic),
+    }
+}
+
+impl fmt::Display for AssemblyInstruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_instruction(f, self, &self.get_const().unwrap_or(0))
+    }
+}
+
+// a jump printed with the name of its label in place of the offset
+struct LabeledJump<'a>(&'a AssemblyInstruction, &'a str);
+
+impl fmt::Display for LabeledJump<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_instruction(f, self.0, &self.1)
+    }
+}
+
+// block labels are only unique within a function, so they are named after the function they are in
+fn get_label_name(label: &Label, function_name: &str) -> String {
+    match label {
+        Label::Function(name) => name.clone(),
+        Label::Block(block) if function_name.is_empty() => format!("BB{}", block),
+        Label::Block(block) => format!("{}_BB{}", function_name, block),
+        Label::Edge(from, to) if function_name.is_empty() => format!("BB{}_BB{}", from, to),
+        Label::Edge(from, to) => format!("{}_BB{}_BB{}", function_name, from, to),
+    }
+}
+
+/// Prints the code of each function as DLX assembly, with a label line for every label and a
+/// comment naming the SSA instruction each run of instructions was generated from.
+pub fn format_assembly(functions: &[&[AssemblyItem]]) -> String {
+    let mut text = String::new();
+
+    for items in functions {
+        let function_name = items.iter().find_map(|item| match item {
+            AssemblyItem::Label(label @ Label::Function(_)) => Some(get_label_name(label, "")),
+            _ => None,
+        }).unwrap_or_default();
+
+        for item in items.iter() {
+            let line = match item {
+                AssemblyItem::Label(label) => format!("{}:", get_label_name(label, &function_name)),
+                AssemblyItem::Source(line, operation) => format!("    ; {}: {:?}", line, operation),
+                AssemblyItem::Instruction(instruction) => format!("    {}", instruction),
+                AssemblyItem::Jump(instruction, label) => format!("    {}", LabeledJump(instruction, &get_label_name(label, &function_name))),
+            };
+            text.push_str(&line);
+            text.push('\n');
+        }
+    }
+
+    text
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct AssemblyParseError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AssemblyParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+// splits the labels off the front of a line, comments already removed
+fn split_labels(text: &str) -> (Vec<&str>, &str) {
+    let mut labels = Vec::new();
+    let mut rest = text.trim();
+    while let Some((label, after)) = rest.split_once(':') {
+        labels.push(label.trim());
+        rest = after.trim();
+    }
+    (labels, rest)
+}
+
+fn is_label_name(name: &str) -> bool {
+    let mut characters = name.chars();
+    characters.next().is_some_and(|first| first.is_ascii_alphabetic() || first == '_')
+        && characters.all(|character| character.is_ascii_alphanumeric() || character == '_' || character == '.')
+}
+
+/// Reads DLX assembly as written by `format_assembly` or by hand into instructions ready for the
+/// assembler. Labels are `name:` and may share a line with an instruction, `;` starts a comment,
+/// and branch and JSR targets are either numbers or label names.
+pub fn parse_assembly(text: &str) -> Result<AssemblyInstructions, AssemblyParseError> {
+    // first pass, every label gets the address of the instruction after it
+    let mut labels = HashMap::<&str, usize>::new();
+    let mut address = 0;
+    for (index, line) in text.lines().enumerate() {
+        let code = line.split(';').next().unwrap();
+        let (line_labels, instruction) = split_labels(code);
+        for label in line_labels {
+            if !is_label_name(label) {
+                return Err(AssemblyParseError { line: index + 1, message: format!("{:?} is not a label name", label) });
+            }
+            if labels.insert(label, address).is_some() {
+                return Err(AssemblyParseError { line: index + 1, message: format!("label {} is defined twice", label) });
+            }
+        }
+        if !instruction.is_empty() {
+            address += 1;
+        }
+    }
+
+    // second pass, instructions with their targets resolved
+    let mut instructions = AssemblyInstructions::new();
+    for (index, line) in text.lines().enumerate() {
+        let code = line.split(';').next().unwrap();
+        let (_, instruction) = split_labels(code);
+        if instruction.is_empty() {
+            continue;
+        }
+        let instruction = parse_instruction(instruction, instructions.len(), &labels)
+            .map_err(|message| AssemblyParseError { line: index + 1, message })?;
+        instructions.push(instruction);
+    }
+
+    Ok(instructions)
+}
+
+fn parse_instruction(text: &str, address: usize, labels: &HashMap<&str, usize>) -> Result<AssemblyInstruction, String> {
+    let (mnemonic, operand_text) = text.split_once(char::is_whitespace).unwrap_or((text, ""));
+    let Some(entry) = OP_CODES.iter().find(|entry| entry.mnemonic.eq_ignore_ascii_case(mnemonic)) else {
+        return Err(format!("unknown instruction {}", mnemonic));
+    };
+
+    let operand_texts: Vec<&str> = if operand_text.trim().is_empty() {
+        Vec::new()
+    } else {
+        operand_text.split(',').map(|operand| operand.trim()).collect()
+    };
+    let expected = match entry.operands {
+        Operands::ThreeRegisters | Operands::TwoRegistersConstant => 3,
+        Operands::TwoRegisters | Operands::RegisterConstant | Operands::RegisterTarget => 2,
+        Operands::Target | Operands::RegisterC | Operands::RegisterA | Operands::RegisterB => 1,
+        Operands::Nothing => 0,
+    };
+    if operand_texts.len() != expected {
+        return Err(format!("{} takes {} operands, got {}", mnemonic.to_uppercase(), expected, operand_texts.len()));
+    }
+
+    let register = |position: usize| parse_register(operand_texts[position]);
+    let constant = |position: usize| parse_constant(operand_texts[position]);
+    // branches count words from themselves, JSR takes a byte address
+    let target = |position: usize| match labels.get(operand_texts[position]) {
+        Some(label_address) if entry.fmt == Fmt::F3 => Ok(*label_address as isize * 4),
+        Some(label_address) => Ok(*label_address as isize - address as isize),
+        None if is_label_name(operand_texts[position]) => Err(format!("label {} is never defined", operand_texts[position])),
+        None => parse_constant(operand_texts[position]),
+    };
+
+    let (a, b, c) = match entry.operands {
+        Operands::ThreeRegisters => (register(0)?, register(1)?, register(2)? as isize),
+        Operands::TwoRegisters => (register(0)?, 0, register(1)? as isize),
+        Operands::TwoRegistersConstant => (register(0)?, register(1)?, constant(2)?),
+        Operands::RegisterConstant => (register(0)?, 0, constant(1)?),
+        Operands::RegisterTarget => (register(0)?, 0, target(1)?),
+        Operands::Target => (0, 0, target(0)?),
+        Operands::RegisterC => (0, 0, register(0)? as isize),
+        Operands::RegisterA => (register(0)?, 0, 0),
+        Operands::RegisterB => (0, register(0)?, 0),
+        Operands::Nothing => (0, 0, 0),
+    };
+
+    Ok((entry.build)(a, b, c))
+}
+
+fn parse_register(text: &str) -> Result<u8, String> {
+    let number = text.strip_prefix(['R', 'r']).and_then(|number| number.parse::<u8>().ok());
+    match number {
+        Some(register) if register < 32 => Ok(register),
+        _ => Err(format!("{} is not a register", text)),
+    }
+}
+
+fn parse_constant(text: &str) -> Result<isize, String> {
+    text.parse::<isize>().map_err(|_| format!("{} is not a number", text))
+}
+
+#[cfg(test)]
+mod assembly_text_tests {
+    use super::*;
+    use crate::assembler::get_machine_code_instructions;
+    use crate::code_gen::{resolve_labels, CodeGeneration};
+    use crate::emulator::Emulator;
+    use crate::instruction::Operation;
+    use crate::parser::Parser;
+
+    #[test]
+    fn test_display() {
+        assert_eq!(AssemblyInstruction::ADDI(1, 0, 5).to_string(), "ADDI R1, R0, 5");
+        assert_eq!(AssemblyInstruction::SUB(3, 1, 2).to_string(), "SUB R3, R1, R2");
+        assert_eq!(AssemblyInstruction::CHK(4, 5).to_string(), "CHK R4, R5");
+        assert_eq!(AssemblyInstruction::LDW(2, 30, -8).to_string(), "LDW R2, R30, -8");
+        assert_eq!(AssemblyInstruction::BLT(7, -3).to_string(), "BLT R7, -3");
+        assert_eq!(AssemblyInstruction::JSR(40).to_string(), "JSR 40");
+        assert_eq!(AssemblyInstruction::RET(31).to_string(), "RET R31");
+        assert_eq!(AssemblyInstruction::WRD(6).to_string(), "WRD R6");
+        assert_eq!(AssemblyInstruction::WRL.to_string(), "WRL");
+    }
+
+    #[test]
+    fn test_format_assembly() {
+        let items = [
+            AssemblyItem::Label(Label::Function("main".to_string())),
+            AssemblyItem::Source(3, Operation::Read),
+            AssemblyItem::Instruction(AssemblyInstruction::RDD(1)),
+            AssemblyItem::Label(Label::Block(2)),
+            AssemblyItem::Jump(AssemblyInstruction::BNE(1, 0), Label::Block(2)),
+            AssemblyItem::Jump(AssemblyInstruction::JSR(0), Label::Function("main".to_string())),
+        ];
+        assert_eq!(format_assembly(&[&items]), [
+            "main:",
+            "    ; 3: read",
+            "    RDD R1",
+            "main_BB2:",
+            "    BNE R1, main_BB2",
+            "    JSR main",
+            "",
+        ].join("\n"));
+    }
+
+    #[test]
+    fn test_hand_written_assembly() {
+        let text = "
+; counts down from the input
+        RDD R1
+loop:   WRD R1
+        SUBI R1, R1, 1
+        BGT R1, loop
+        WRL
+        bsr end          ; lower case works too
+        WRD R1
+end:    RET R0
+";
+        let program = get_machine_code_instructions(parse_assembly(text).unwrap()).unwrap();
+        let mut emulator = Emulator::new(&program, "3".as_bytes(), Vec::new()).unwrap();
+        emulator.run().unwrap();
+        assert_eq!(emulator.get_writer().as_slice(), b"3 2 1 \n");
+    }
+
+    #[test]
+    fn test_compiler_output_round_trip() {
+        let input = "main var a; { let a <- call InputNum(); if a < 10 then call OutputNum(a * 2) else call OutputNum(a) fi }.";
+        let mut parser = Parser::new(input.to_string());
+        parser.parse_computation();
+
+        let mut code_generation = CodeGeneration::new(&mut parser.internal_program.get_fn_mut("main").bb_graph);
+        code_generation.generate_code();
+        let items = code_generation.get_assembly_items();
+
+        let text = format_assembly(&[items]);
+        assert_eq!(parse_assembly(&text), Ok(resolve_labels(&[items])));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let error = |text: &str| parse_assembly(text).unwrap_err().to_string();
+        assert_eq!(error("ADDI R1, R0, 5\nFOO R1"), "line 2: unknown instruction FOO");
+        assert_eq!(error("ADD R1, R2"), "line 1: ADD takes 3 operands, got 2");
+        assert_eq!(error("ADDI R32, R0, 1"), "line 1: R32 is not a register");
+        assert_eq!(error("ADDI R1, R0, five"), "line 1: five is not a number");
+        assert_eq!(error("BEQ R0, nowhere"), "line 1: label nowhere is never defined");
+        assert_eq!(error("a: WRL\na: WRL"), "line 2: label a is defined twice");
+    }
+}
@@ -38,6 +38,8 @@ impl fmt::Debug for BasicBlockType {
 pub enum VariableType {
     Value(isize),
     NotInit,
+    // a global whose current value has to be loaded from the global area
+    InMemory,
 }
 
 // pub type Predecessors = Vec<BasicBlock>;
@@ -46,18 +48,20 @@ impl fmt::Debug for VariableType {
         match *self {
             VariableType::Value(value) => write!(f, "{}", value),
             VariableType::NotInit => write!(f, "NotInit"),
+            VariableType::InMemory => write!(f, "InMemory"),
         }
     }
 }
 
 impl VariableType {
 
+    // an uninitialized variable reads as the zero constant, which lives at line 0
     pub fn get_value(&self) -> isize {
-        if let VariableType::Value(value) = self {
-            return *value;
+        match self {
+            VariableType::Value(value) => *value,
+            VariableType::NotInit => 0,
+            VariableType::InMemory => panic!("A global in memory has to be loaded before its value is used"),
         }
-        eprintln!("Use of an uninitialized value");
-        0
     }
 }
 
@@ -87,15 +91,6 @@ impl BasicBlock {
         }
     }
 
-    pub fn new_with_id(block_type: BasicBlockType, block_id: usize) -> Self {
-        Self {
-            id: NodeIndex::new(block_id), 
-            instructions: Vec::new(),
-            variable_table: HashMap::new(),
-            block_type,
-            dominator_table: DominatorTable::new(),
-        }
-    }
 
     pub fn add_instruction(&mut self, instruction: Instruction) {
         self.instructions.push(instruction);
@@ -109,9 +104,6 @@ impl BasicBlock {
         self.variable_table.insert(variable.to_string(), VariableType::NotInit);
     }
 
-    pub fn get_block_type(&self) -> BasicBlockType {
-        self.block_type
-    }
 
     pub fn get_variable(&self, variable: &String) -> VariableType {
         // need to generate phi resolutions in parser
@@ -129,31 +121,14 @@ impl BasicBlock {
             panic!("Basic block is empty")
         }
     }
-    
-    pub fn get_last_instruction_line_number(&self) -> isize {
-        if let Some(instruction) = self.instructions.last() {
-            instruction.get_line_number()
-        } else {
-            panic!("Basic block is empty")
-        }
-    }
-
 
-    pub fn get_max_parents(&self) -> usize {
-        match self.block_type {
-            BasicBlockType::Entry  => return 0,
-            BasicBlockType::Branch | BasicBlockType::FallThrough | BasicBlockType::Follow => return 1,
-            BasicBlockType::Conditional | BasicBlockType::Join => return 2,
-            BasicBlockType::Exit => return 1,
-        }
-    }
 
     pub fn get_max_children(&self) -> usize {
         match self.block_type {
-            BasicBlockType::Entry => return 1,
-            BasicBlockType::Branch | BasicBlockType::FallThrough | BasicBlockType::Join | BasicBlockType::Follow=> return 1,
-            BasicBlockType::Conditional => return 2,
-            BasicBlockType::Exit => return 0,
+            BasicBlockType::Entry => 1,
+            BasicBlockType::Branch | BasicBlockType::FallThrough | BasicBlockType::Join | BasicBlockType::Follow=> 1,
+            BasicBlockType::Conditional => 2,
+            BasicBlockType::Exit => 0,
         }
     }
 
@@ -171,6 +146,11 @@ impl BasicBlock {
     pub fn is_empty(&self) -> bool {
         self.instructions.is_empty()
     }
+
+    // checks if control leaves the function somewhere in this block
+    pub fn ends_with_return(&self) -> bool {
+        self.instructions.iter().any(|instruction| matches!(instruction.operation, Operation::Ret(_) | Operation::End))
+    }
 }
 
 // #[cfg(test)]
@@ -0,0 +1,346 @@
+use std::collections::{HashMap, HashSet};
+
+use petgraph::graph::NodeIndex;
+use petgraph::Direction::Outgoing;
+
+use crate::function::{get_new_line, get_next_line};
+use crate::instruction::{Instruction, Operation};
+use crate::live_analysis::{BasicBlockGraph, Instructions};
+use crate::loop_analysis::find_loops;
+use crate::object_file::LineEntry;
+
+type LineNumber = isize;
+
+/// how often each block is expected to run, only the relative sizes matter
+pub type BlockWeights = HashMap<NodeIndex, u64>;
+
+// every loop a block is nested in makes it this many times hotter
+const LOOP_WEIGHT: u64 = 10;
+
+// where control goes once a block's own instructions are done
+enum Exit {
+    None,
+    Bra(NodeIndex),
+    Branch { taken: NodeIndex, fall_through: NodeIndex },
+    FallThrough(NodeIndex),
+}
+
+fn set_branch_target(operation: &Operation, target: isize) -> Operation {
+    match operation {
+        Operation::Bra(_) => Operation::Bra(target),
+        Operation::Bne(value, _) => Operation::Bne(*value, target),
+        Operation::Beq(value, _) => Operation::Beq(*value, target),
+        Operation::Ble(value, _) => Operation::Ble(*value, target),
+        Operation::Blt(value, _) => Operation::Blt(*value, target),
+        Operation::Bge(value, _) => Operation::Bge(*value, target),
+        Operation::Bgt(value, _) => Operation::Bgt(*value, target),
+        _ => panic!("{:?} is not a branch", operation),
+    }
+}
+
+// the branch that is taken exactly when the given one is not
+fn invert_branch(operation: &Operation, target: isize) -> Operation {
+    match operation {
+        Operation::Bne(value, _) => Operation::Beq(*value, target),
+        Operation::Beq(value, _) => Operation::Bne(*value, target),
+        Operation::Ble(value, _) => Operation::Bgt(*value, target),
+        Operation::Blt(value, _) => Operation::Bge(*value, target),
+        Operation::Bge(value, _) => Operation::Blt(*value, target),
+        Operation::Bgt(value, _) => Operation::Ble(*value, target),
+        _ => panic!("{:?} is not a conditional branch", operation),
+    }
+}
+
+// blocks without any instructions have no label, control passes through them to their successor
+fn skip_empty(g: &BasicBlockGraph, mut block: NodeIndex) -> Option<NodeIndex> {
+    let mut seen = HashSet::new();
+    while g[block].instructions.is_empty() {
+        if !seen.insert(block) {
+            return None;
+        }
+        block = g.neighbors_directed(block, Outgoing).next()?;
+    }
+    Some(block)
+}
+
+fn get_exit(g: &BasicBlockGraph, block: NodeIndex) -> Exit {
+    let Some(last) = g[block].instructions.last() else {
+        return Exit::None;
+    };
+    if matches!(last.operation, Operation::End | Operation::Ret(_)) {
+        return Exit::None;
+    }
+
+    let successors: Vec<NodeIndex> = g.neighbors_directed(block, Outgoing).filter_map(|successor| skip_empty(g, successor)).collect();
+    match last.operation.get_branch_target() {
+        Some(target) => {
+            let Some(taken) = skip_empty(g, NodeIndex::new(target as usize)) else {
+                return Exit::None;
+            };
+            if matches!(last.operation, Operation::Bra(_)) {
+                return Exit::Bra(taken);
+            }
+            match successors.iter().find(|successor| **successor != taken) {
+                Some(fall_through) => Exit::Branch { taken, fall_through: *fall_through },
+                None => Exit::Bra(taken),
+            }
+        },
+        None => successors.first().map_or(Exit::None, |successor| Exit::FallThrough(*successor)),
+    }
+}
+
+fn get_successors(g: &BasicBlockGraph, block: NodeIndex) -> Vec<NodeIndex> {
+    match get_exit(g, block) {
+        Exit::None => vec![],
+        Exit::Bra(target) | Exit::FallThrough(target) => vec![target],
+        Exit::Branch { taken, fall_through } => vec![fall_through, taken],
+    }
+}
+
+/// Estimates block weights from the structure alone: a block in n nested loops is expected
+/// to run 10^n times.
+pub fn get_static_weights(g: &BasicBlockGraph) -> BlockWeights {
+    let loops = find_loops(g);
+    g.node_indices()
+        .map(|block| (block, LOOP_WEIGHT.saturating_pow(loops.iter().filter(|found| found.contains(block)).count() as u32)))
+        .collect()
+}
+
+/// Turns per-instruction execution counts from the emulator into block weights. The line
+/// table says which SSA line every address was generated from, and a block weighs as much
+/// as its most executed instruction. Blocks that never ran weigh nothing.
+pub fn get_profile_weights(g: &BasicBlockGraph, line_table: &[LineEntry], counts: &[u64]) -> BlockWeights {
+    let line_blocks: HashMap<LineNumber, NodeIndex> = g.node_indices()
+        .flat_map(|block| g[block].instructions.iter().map(move |instruction| (instruction.get_line_number(), block)))
+        .collect();
+
+    let mut weights: BlockWeights = g.node_indices().map(|block| (block, 0)).collect();
+    for (index, entry) in line_table.iter().enumerate() {
+        let Some(block) = line_blocks.get(&(entry.line as LineNumber)) else {
+            continue;
+        };
+        // an entry covers the addresses up to the next one that emitted code of its own
+        let start = entry.address as usize / 4;
+        let end = line_table[index + 1..].iter()
+            .find(|next| next.address > entry.address)
+            .map_or(counts.len(), |next| next.address as usize / 4)
+            .min(counts.len());
+        let hottest = counts.get(start..end).and_then(|counts| counts.iter().max()).copied().unwrap_or(0);
+
+        let weight = weights.get_mut(block).unwrap();
+        *weight = (*weight).max(hottest);
+    }
+    weights
+}
+
+/// Places the blocks so that the heaviest edges become fall-throughs. Every block starts as a
+/// chain of its own and, going through the edges from heaviest to lightest, a chain ending
+/// in an edge's source is joined with the chain starting at its target. The entry chain goes
+/// first and the others follow from hottest to coldest. Empty blocks are left out.
+pub fn layout_blocks(g: &BasicBlockGraph, weights: &BlockWeights) -> Vec<NodeIndex> {
+    let weight = |block: NodeIndex| weights.get(&block).copied().unwrap_or(0);
+    let Some(entry) = skip_empty(g, NodeIndex::new(0)) else {
+        return vec![];
+    };
+    let blocks: Vec<NodeIndex> = g.node_indices().filter(|block| !g[*block].instructions.is_empty()).collect();
+
+    // an edge runs at most as often as the colder of its ends
+    let mut edges: Vec<(u64, NodeIndex, NodeIndex)> = blocks.iter()
+        .flat_map(|source| get_successors(g, *source).into_iter().map(move |target| (*source, target)))
+        .map(|(source, target)| (weight(source).min(weight(target)), source, target))
+        .collect();
+    // ties keep the order the program was written in
+    edges.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)).then(a.2.cmp(&b.2)));
+
+    let mut chains: HashMap<NodeIndex, Vec<NodeIndex>> = blocks.iter().map(|block| (*block, vec![*block])).collect();
+    let mut chain_of: HashMap<NodeIndex, NodeIndex> = blocks.iter().map(|block| (*block, *block)).collect();
+    for (_, source, target) in edges {
+        let (source_chain, target_chain) = (chain_of[&source], chain_of[&target]);
+        if source_chain == target_chain || target == entry
+            || chains[&source_chain].last() != Some(&source) || chains[&target_chain].first() != Some(&target) {
+            continue;
+        }
+        let moved = chains.remove(&target_chain).unwrap();
+        for block in &moved {
+            chain_of.insert(*block, source_chain);
+        }
+        chains.get_mut(&source_chain).unwrap().extend(moved);
+    }
+
+    let mut order = chains.remove(&chain_of[&entry]).unwrap();
+    let mut rest: Vec<Vec<NodeIndex>> = chains.into_values().collect();
+    rest.sort_by(|a, b| weight(b[0]).cmp(&weight(a[0])).then(a[0].cmp(&b[0])));
+    order.extend(rest.into_iter().flatten());
+    order
+}
+
+/// Lays the instructions of the blocks out in the given order. A branch to the block right
+/// after is dropped, a conditional branch over it is inverted, and a BRA is added wherever
+/// control would otherwise fall into the wrong block. The added branches get fresh lines.
+pub fn linearize(g: &BasicBlockGraph, order: &[NodeIndex]) -> Instructions {
+    let mut next_line = get_next_line(g);
+    let mut instructions = Instructions::new();
+
+    for (position, block) in order.iter().enumerate() {
+        let next = order.get(position + 1).copied();
+        let mut block_instructions = g[*block].instructions.clone();
+        let last = block_instructions.last_mut().unwrap();
+
+        match get_exit(g, *block) {
+            Exit::None => {},
+            Exit::Bra(target) if Some(target) == next => {
+                // the first line of a block carries its label, so it stays behind as an empty instruction
+                if block_instructions.len() == 1 {
+                    block_instructions[0].operation = Operation::Empty;
+                } else {
+                    block_instructions.pop();
+                }
+            },
+            Exit::Bra(target) => last.operation = Operation::Bra(target.index() as isize),
+            Exit::Branch { taken, fall_through } => {
+                if Some(fall_through) == next {
+                    last.operation = set_branch_target(&last.operation, taken.index() as isize);
+                } else if Some(taken) == next {
+                    last.operation = invert_branch(&last.operation, fall_through.index() as isize);
+                } else {
+                    last.operation = set_branch_target(&last.operation, taken.index() as isize);
+                    block_instructions.push(Instruction::new(get_new_line(&mut next_line), Operation::Bra(fall_through.index() as isize)));
+                }
+            },
+            Exit::FallThrough(target) => {
+                if Some(target) != next {
+                    block_instructions.push(Instruction::new(get_new_line(&mut next_line), Operation::Bra(target.index() as isize)));
+                }
+            },
+        }
+        instructions.extend(block_instructions);
+    }
+    instructions
+}
+
+#[cfg(test)]
+mod block_layout_tests {
+    use super::*;
+    use crate::assembler::get_machine_code_instructions;
+    use crate::code_gen::CodeGeneration;
+    use crate::emulator::Emulator;
+    use crate::linker::link;
+    use crate::parser::Parser;
+
+    fn get_main_graph(input: &str) -> (Parser, BasicBlockGraph) {
+        let mut parser = Parser::new(input.to_string());
+        parser.parse_computation();
+        let graph = parser.internal_program.get_fn("main").bb_graph.clone();
+        (parser, graph)
+    }
+
+    // the BRA instructions a layout still needs
+    fn count_unconditional_branches(instructions: &Instructions) -> usize {
+        instructions.iter().filter(|instruction| matches!(instruction.operation, Operation::Bra(_))).count()
+    }
+
+    fn get_lines(instructions: &[Instruction]) -> Vec<LineNumber> {
+        let mut lines: Vec<LineNumber> = instructions.iter()
+            .filter(|instruction| !matches!(instruction.operation, Operation::Bra(_)))
+            .map(|instruction| instruction.get_line_number())
+            .collect();
+        lines.sort();
+        lines
+    }
+
+    #[test]
+    fn test_static_weights() {
+        let (_, graph) = get_main_graph("main var i, j; { let i <- 0; while i < 3 do let j <- 0; while j < 3 do let j <- j + 1 od; let i <- i + 1 od; call OutputNum(i) }.");
+        let weights = get_static_weights(&graph);
+
+        // the outer header, its body and the block after the inner loop run ten times, the inner loop a hundred
+        let mut sorted: Vec<u64> = graph.node_indices().map(|block| weights[&block]).collect();
+        sorted.sort();
+        assert_eq!(sorted, vec![1, 1, 1, 10, 10, 10, 100, 100]);
+    }
+
+    #[test]
+    fn test_layout_keeps_instructions() {
+        let input = "
+main var a, b;
+{
+    let a <- call InputNum();
+    let b <- 0;
+    while a > 0 do
+        if a > 2 then let b <- b + a else let b <- b - 1 fi;
+        let a <- a - 1
+    od;
+    call OutputNum(b)
+}.";
+        let (_, graph) = get_main_graph(input);
+        let order = layout_blocks(&graph, &get_static_weights(&graph));
+        let instructions = linearize(&graph, &order);
+        let original: Instructions = graph.node_weights().flat_map(|block| block.instructions.clone()).collect();
+
+        assert_eq!(order[0], NodeIndex::new(0));
+        assert!(count_unconditional_branches(&instructions) <= count_unconditional_branches(&original));
+        assert_eq!(get_lines(&instructions), get_lines(&original));
+    }
+
+    #[test]
+    fn test_jump_to_next_block_removed() {
+        let (_, graph) = get_main_graph("main var a; { let a <- call InputNum(); while a > 0 do let a <- a - 1 od; call OutputNum(a) }.");
+
+        let in_order: Vec<NodeIndex> = graph.node_indices().filter(|block| !graph[*block].instructions.is_empty()).collect();
+        let instructions = linearize(&graph, &in_order);
+        assert_eq!(count_unconditional_branches(&instructions), 1);
+
+        // the loop body follows the header, so only the back edge needs a BRA
+        let order = layout_blocks(&graph, &get_static_weights(&graph));
+        let instructions = linearize(&graph, &order);
+        assert_eq!(count_unconditional_branches(&instructions), 1);
+        assert_eq!(graph[order[2]].block_type, crate::basic_block::BasicBlockType::FallThrough);
+    }
+
+    #[test]
+    fn test_profile_weights() {
+        let input = "main var a; { let a <- call InputNum(); if a > 2 then call OutputNum(1) else call OutputNum(2) fi; call OutputNewLine() }.";
+        let (parser, graph) = get_main_graph(input);
+        let object = link(&parser.internal_program).unwrap();
+
+        let mut emulator = Emulator::from_object_file(&object, "1".as_bytes(), Vec::new()).unwrap();
+        emulator.enable_profiling();
+        emulator.run().unwrap();
+        let weights = get_profile_weights(&graph, object.line_table.as_ref().unwrap(), emulator.get_profile().unwrap());
+
+        let then_block = graph.node_indices().find(|block| graph[*block].block_type == crate::basic_block::BasicBlockType::FallThrough).unwrap();
+        let else_block = graph.node_indices().find(|block| graph[*block].block_type == crate::basic_block::BasicBlockType::Branch).unwrap();
+        assert_eq!(weights[&NodeIndex::new(0)], 1);
+        assert_eq!(weights[&then_block], 0);
+        assert_eq!(weights[&else_block], 1);
+
+        // the branch that never ran goes last and the program still does the same thing
+        let order = layout_blocks(&graph, &weights);
+        assert_eq!(order.last(), Some(&then_block));
+        for (input, output) in [("1", "2 \n"), ("5", "1 \n")] {
+            let mut code_generation = CodeGeneration::with_block_order(&mut graph.clone(), &order);
+            code_generation.generate_code();
+            let code = get_machine_code_instructions(code_generation.get_assembly_instructions()).unwrap();
+            let mut emulator = Emulator::new(&code, input.as_bytes(), Vec::new()).unwrap();
+            emulator.run().unwrap();
+            assert_eq!(String::from_utf8(emulator.get_writer().clone()).unwrap(), output);
+        }
+    }
+
+    #[test]
+    fn test_profile_weights_of_empty_blocks() {
+        // the block for the missing else only holds the phi copies
+        let input = "main var a, b; { let a <- call InputNum(); let b <- a; if a < 10 then let b <- a * 2 fi; call OutputNum(b) }.";
+        let (parser, graph) = get_main_graph(input);
+        let object = link(&parser.internal_program).unwrap();
+
+        let mut emulator = Emulator::from_object_file(&object, "12".as_bytes(), Vec::new()).unwrap();
+        emulator.enable_profiling();
+        emulator.run().unwrap();
+        let weights = get_profile_weights(&graph, object.line_table.as_ref().unwrap(), emulator.get_profile().unwrap());
+
+        let else_block = graph.node_indices().find(|block| graph[*block].block_type == crate::basic_block::BasicBlockType::Branch).unwrap();
+        assert_eq!(weights[&else_block], 1);
+        assert_eq!(layout_blocks(&graph, &weights)[2], else_block);
+    }
+}
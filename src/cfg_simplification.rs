@@ -0,0 +1,268 @@
+use std::collections::HashSet;
+
+use petgraph::graph::{EdgeReference, NodeIndex};
+use petgraph::visit::{Dfs, EdgeRef};
+use petgraph::Direction::{Incoming, Outgoing};
+
+use crate::basic_block::BasicBlockType;
+use crate::instruction::{Instruction, Operation};
+use crate::live_analysis::BasicBlockGraph;
+use crate::program::Program;
+
+type Edge = (NodeIndex, NodeIndex, BasicBlockType);
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CfgSimplificationStatistics {
+    pub merged_blocks: usize,
+    pub removed_empty_blocks: usize,
+    pub removed_unreachable_blocks: usize,
+}
+
+// The types of blocks that only say how the block is entered. A block of one of these types
+// that has a single predecessor can become part of it without the predecessor changing what it
+// is to the interpreter, a conditional or exit block cannot.
+fn is_continuation(block_type: BasicBlockType) -> bool {
+    matches!(block_type, BasicBlockType::FallThrough | BasicBlockType::Branch | BasicBlockType::Follow | BasicBlockType::Join)
+}
+
+fn has_phis(g: &BasicBlockGraph, block: NodeIndex) -> bool {
+    g[block].instructions.iter().any(|instruction| matches!(instruction.operation, Operation::Phi(_, _)))
+}
+
+fn get_only_successor(g: &BasicBlockGraph, block: NodeIndex) -> Option<NodeIndex> {
+    let mut successors = g.neighbors_directed(block, Outgoing);
+    let successor = successors.next()?;
+    (successors.next().is_none() && successor != block).then_some(successor)
+}
+
+// Copies the graph without the removed block. Branches to it go to the replacement instead and
+// every edge becomes the edges `get_edges` makes of it, in the same order, which is what the
+// phis at the blocks they lead to are ordered by.
+fn rebuild(g: &BasicBlockGraph, removed: NodeIndex, replacement: NodeIndex, get_edges: impl Fn(EdgeReference<BasicBlockType>) -> Vec<Edge>) -> BasicBlockGraph {
+    let remap = |block: NodeIndex| {
+        let block = if block == removed { replacement } else { block };
+        NodeIndex::new(if block > removed { block.index() - 1 } else { block.index() })
+    };
+
+    let mut rebuilt = BasicBlockGraph::new();
+    for block in g.node_indices().filter(|block| *block != removed) {
+        let mut copy = g[block].clone();
+        copy.id = remap(block);
+        for instruction in &mut copy.instructions {
+            instruction.operation.map_block(|target| remap(NodeIndex::new(target as usize)).index() as isize);
+        }
+        rebuilt.add_node(copy);
+    }
+    for (source, target, weight) in g.edge_references().flat_map(get_edges) {
+        rebuilt.add_edge(remap(source), remap(target), weight);
+    }
+    rebuilt
+}
+
+// A join block that does nothing but go on to its successor. Its predecessors can go there
+// directly, unless the successor has phis that count on the number of edges coming in.
+fn get_skippable_successor(g: &BasicBlockGraph, block: NodeIndex) -> Option<NodeIndex> {
+    if g[block].block_type != BasicBlockType::Join {
+        return None;
+    }
+    let successor = get_only_successor(g, block)?;
+
+    let instructions = &g[block].instructions;
+    let does_nothing = instructions.iter().enumerate().all(|(position, instruction)| match instruction.operation {
+        Operation::Empty => true,
+        Operation::Bra(target) => position == instructions.len() - 1 && target == successor.index() as isize,
+        _ => false,
+    });
+    let predecessors: Vec<NodeIndex> = g.neighbors_directed(block, Incoming).collect();
+    let edges_kept = predecessors.iter().all(|predecessor| *predecessor != successor && !g.contains_edge(*predecessor, successor))
+        && (predecessors.len() == 1 || !has_phis(g, successor));
+
+    (does_nothing && !predecessors.is_empty() && edges_kept).then_some(successor)
+}
+
+// the predecessors go to the successor, the new edges taking the place of the one that left the block
+fn skip_block(g: &BasicBlockGraph, removed: NodeIndex, successor: NodeIndex) -> BasicBlockGraph {
+    let mut incoming: Vec<_> = g.edges_directed(removed, Incoming).collect();
+    incoming.sort_by_key(|edge| edge.id());
+    let incoming: Vec<Edge> = incoming.into_iter().map(|edge| (edge.source(), successor, *edge.weight())).collect();
+    rebuild(g, removed, successor, |edge| {
+        if edge.source() == removed {
+            incoming.clone()
+        } else if edge.target() == removed {
+            vec![]
+        } else {
+            vec![(edge.source(), edge.target(), *edge.weight())]
+        }
+    })
+}
+
+// A block whose only successor has it as its only predecessor. Blocks that return or end the
+// program are left alone, what comes after them never runs.
+fn get_mergeable_successor(g: &BasicBlockGraph, block: NodeIndex) -> Option<NodeIndex> {
+    let successor = get_only_successor(g, block)?;
+    let mut predecessors = g.neighbors_directed(successor, Incoming);
+    let single_predecessor = predecessors.next() == Some(block) && predecessors.next().is_none();
+
+    let instructions = &g[block].instructions;
+    let falls_through = instructions.iter().all(|instruction| !matches!(instruction.operation, Operation::Ret(_) | Operation::End))
+        && instructions.last().is_none_or(|instruction| match instruction.operation {
+            Operation::Bra(target) => target == successor.index() as isize,
+            operation => !operation.is_conditional_branch(),
+        });
+
+    (single_predecessor && successor.index() != 0 && is_continuation(g[successor].block_type) && !has_phis(g, successor) && falls_through)
+        .then_some(successor)
+}
+
+// the block takes the instructions and the outgoing edges of its successor
+fn merge_blocks(g: &BasicBlockGraph, block: NodeIndex, successor: NodeIndex) -> BasicBlockGraph {
+    let mut instructions: Vec<Instruction> = g[block].instructions.iter()
+        .filter(|instruction| !matches!(instruction.operation, Operation::Bra(_)))
+        .chain(&g[successor].instructions)
+        .cloned()
+        .collect();
+    // placeholders are only needed while the block is empty, the first line of the entry block names the function
+    let first = instructions.first().cloned();
+    instructions.retain(|instruction| instruction.operation != Operation::Empty);
+    if let Some(first) = first.filter(|first| first.operation == Operation::Empty && (instructions.is_empty() || block.index() == 0)) {
+        instructions.insert(0, first);
+    }
+
+    let mut merged = rebuild(g, successor, block, |edge| {
+        if edge.source() == block {
+            vec![]
+        } else {
+            vec![(edge.source(), edge.target(), *edge.weight())]
+        }
+    });
+    let merged_block = if block > successor { NodeIndex::new(block.index() - 1) } else { block };
+    merged[merged_block].instructions = instructions;
+    merged
+}
+
+// a block that cannot be reached and has no predecessors left, as long as the block it leads
+// to does not count it in its phis
+fn get_unreachable_block(g: &BasicBlockGraph) -> Option<NodeIndex> {
+    let mut reachable = HashSet::new();
+    let mut dfs = Dfs::new(g, NodeIndex::new(0));
+    while let Some(block) = dfs.next(g) {
+        reachable.insert(block);
+    }
+    g.node_indices().find(|block| {
+        !reachable.contains(block)
+            && g.neighbors_directed(*block, Incoming).next().is_none()
+            && g.neighbors_directed(*block, Outgoing).all(|successor| !has_phis(g, successor))
+    })
+}
+
+/// Takes join blocks that are left with nothing to do out of the graph, their predecessors going
+/// to their successor. Returns how many were removed.
+pub fn remove_empty_joins(g: &mut BasicBlockGraph) -> usize {
+    let mut removed = 0;
+    while let Some((block, successor)) = g.node_indices().find_map(|block| get_skippable_successor(g, block).map(|successor| (block, successor))) {
+        *g = skip_block(g, block, successor);
+        removed += 1;
+    }
+    removed
+}
+
+/// Cleans up the graph of every function: blocks nothing reaches are removed, a block that is the
+/// only successor of its only predecessor is merged into it and empty join blocks are skipped.
+/// Branches are retargeted as blocks go away. Conditional, entry and exit blocks keep their type
+/// and stay blocks of their own, so the graph keeps the shape the parser builds.
+pub fn simplify_cfg(program: &mut Program) -> CfgSimplificationStatistics {
+    let mut statistics = CfgSimplificationStatistics::default();
+    for function in program.functions.values_mut() {
+        let g = &mut function.bb_graph;
+        if g.node_count() == 0 {
+            continue;
+        }
+
+        while let Some(block) = get_unreachable_block(g) {
+            *g = rebuild(g, block, block, |edge| {
+                if edge.source() == block { vec![] } else { vec![(edge.source(), edge.target(), *edge.weight())] }
+            });
+            statistics.removed_unreachable_blocks += 1;
+        }
+
+        loop {
+            if let Some((block, successor)) = g.node_indices().find_map(|block| get_mergeable_successor(g, block).map(|successor| (block, successor))) {
+                *g = merge_blocks(g, block, successor);
+                statistics.merged_blocks += 1;
+                continue;
+            }
+            let removed = remove_empty_joins(g);
+            if removed == 0 {
+                break;
+            }
+            statistics.removed_empty_blocks += removed;
+        }
+    }
+    statistics
+}
+
+#[cfg(test)]
+mod cfg_simplification_tests {
+    use super::*;
+    use crate::inliner::{inline_functions, InlineConfig};
+    use crate::test_helpers::{get_program, interpret};
+
+    #[test]
+    fn test_merge_inlined_blocks() {
+        let input = "main void function show(x); { call OutputNum(x) }; { call show(call InputNum()); call OutputNum(1) }.";
+        let mut program = get_program(input);
+        inline_functions(&mut program, &InlineConfig::default());
+        let expected = interpret(&program, "5");
+
+        let statistics = simplify_cfg(&mut program);
+        assert_eq!(statistics.merged_blocks, 2);
+        let g = &program.get_fn("main").bb_graph;
+        assert_eq!(g.node_count(), 2);
+        assert_eq!(g[NodeIndex::new(0)].block_type, BasicBlockType::Entry);
+        assert_eq!(interpret(&program, "5"), expected);
+        assert_eq!(expected, "5 1 ");
+    }
+
+    #[test]
+    fn test_unreachable_and_branches() {
+        // inlining leaves the join of max behind, which nothing reaches as both sides return
+        let input = "main var a; function max(a, b); { if a > b then return a else return b fi }; { let a <- call max(call InputNum(), 3); call OutputNum(a) }.";
+        let mut program = get_program(input);
+        inline_functions(&mut program, &InlineConfig::default());
+        let expected: Vec<String> = ["1", "8"].iter().map(|input| interpret(&program, input)).collect();
+
+        let statistics = simplify_cfg(&mut program);
+        assert_eq!(statistics.removed_unreachable_blocks, 1);
+        assert!(statistics.merged_blocks >= 1);
+        let g = &program.get_fn("main").bb_graph;
+        for block in g.node_indices() {
+            assert_eq!(g[block].id, block);
+            if let Some(Operation::Bra(target)) = g[block].instructions.last().map(|instruction| instruction.operation) {
+                assert!(g.contains_edge(block, NodeIndex::new(target as usize)));
+            }
+        }
+        assert_eq!(["1", "8"].iter().map(|input| interpret(&program, input)).collect::<Vec<_>>(), expected);
+        assert_eq!(expected, vec!["3 ", "8 "]);
+    }
+
+    #[test]
+    fn test_empty_join_keeps_shape() {
+        let input = "main var a; { let a <- call InputNum(); if a > 2 then if a > 5 then call OutputNum(1) fi fi; call OutputNum(a) }.";
+        let mut program = get_program(input);
+        let expected: Vec<String> = ["1", "4", "7"].iter().map(|input| interpret(&program, input)).collect();
+
+        let statistics = simplify_cfg(&mut program);
+        assert_eq!(statistics.removed_empty_blocks, 1);
+        assert_eq!(["1", "4", "7"].iter().map(|input| interpret(&program, input)).collect::<Vec<_>>(), expected);
+
+        // both conditionals still fall through to one successor and branch to the other
+        let g = &program.get_fn("main").bb_graph;
+        let conditionals: Vec<NodeIndex> = g.node_indices().filter(|block| g[*block].block_type == BasicBlockType::Conditional).collect();
+        assert_eq!(conditionals.len(), 2);
+        for block in conditionals {
+            let edge_types: Vec<BasicBlockType> = g.edges(block).map(|edge| *edge.weight()).collect();
+            assert_eq!(edge_types.len(), 2);
+            assert!(edge_types.contains(&BasicBlockType::FallThrough) && edge_types.contains(&BasicBlockType::Branch));
+        }
+    }
+}
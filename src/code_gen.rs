@@ -1,11 +1,12 @@
-use crate::instruction::{self, Instruction, Operation};
-use crate::register_allocation::color_graph;
+use crate::instruction::{Instruction, Operation};
 use crate::live_analysis::*;
-use crate::cfg_traversal::*;
-use crate::parser::*;
+use crate::block_layout::{get_static_weights, layout_blocks, linearize};
 use crate::register_allocation::*;
-use std::collections::HashMap;
 use petgraph::graph::NodeIndex;
+use petgraph::visit::EdgeRef;
+use petgraph::Direction::{Incoming, Outgoing};
+use std::collections::{HashMap, HashSet};
+use std::mem::discriminant;
 
 
 type RegSource = u8;
@@ -18,7 +19,21 @@ pub type AssemblyInstructions = Vec<AssemblyInstruction>;
 type RegisterNumber = usize;
 type LineNumber = isize;
 
-#[derive(Debug, PartialEq, Eq)]
+/// register a callee leaves its return value in for the caller's call to pick up
+pub const RETURN_VALUE_REGISTER: Register = 27;
+/// register JSR leaves the return address in
+pub const RETURN_ADDRESS_REGISTER: Register = 31;
+/// register constants are materialised in when an instruction has no immediate form
+const SCRATCH_REGISTER: Register = 26;
+/// register the current function's frame is addressed from
+pub const FRAME_POINTER_REGISTER: Register = 28;
+/// register pointing at the top of the stack, which grows down from below the globals
+pub const STACK_POINTER_REGISTER: Register = 29;
+/// register globals are addressed from, they sit at negative offsets below it
+pub const GLOBAL_POINTER_REGISTER: Register = 30;
+
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum AssemblyInstruction {
 
     ADD(RegDestination, RegSource, RegSource),
@@ -77,6 +92,9 @@ pub enum AssemblyInstruction {
     WRL,
 }
 
+/// F1 - op a b with a 16 bit constant c, F2 - op a b with a register c,
+/// F3 - op with a 26 bit absolute address c
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Fmt {
     F1,
     F2,
@@ -84,173 +102,109 @@ pub enum Fmt {
 }
 
 pub type OpCode = u8;
+
+// how an instruction writes its operands, which also says which fields it has
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operands {
+    // Ra, Rb, Rc
+    ThreeRegisters,
+    // Ra, Rc
+    TwoRegisters,
+    // Ra, Rb, c
+    TwoRegistersConstant,
+    // Ra, c
+    RegisterConstant,
+    // Ra, offset or label
+    RegisterTarget,
+    // offset, address or label
+    Target,
+    // Rc
+    RegisterC,
+    // Ra
+    RegisterA,
+    // Rb
+    RegisterB,
+    Nothing,
+}
+
+/// One row per instruction. Op codes, formats, mnemonics and decoding are all read from here.
+pub struct OpCodeEntry {
+    pub mnemonic: &'static str,
+    pub op_code: OpCode,
+    pub fmt: Fmt,
+    pub operands: Operands,
+    /// builds the instruction from its a, b and c fields, ignoring the ones it does not have
+    pub build: fn(Register, Register, Constant) -> AssemblyInstruction,
+}
+
+pub const OP_CODES: [OpCodeEntry; 45] = [
+    OpCodeEntry { mnemonic: "ADD", op_code: 0, fmt: Fmt::F2, operands: Operands::ThreeRegisters, build: |a, b, c| AssemblyInstruction::ADD(a, b, c as Register) },
+    OpCodeEntry { mnemonic: "SUB", op_code: 1, fmt: Fmt::F2, operands: Operands::ThreeRegisters, build: |a, b, c| AssemblyInstruction::SUB(a, b, c as Register) },
+    OpCodeEntry { mnemonic: "MUL", op_code: 2, fmt: Fmt::F2, operands: Operands::ThreeRegisters, build: |a, b, c| AssemblyInstruction::MUL(a, b, c as Register) },
+    OpCodeEntry { mnemonic: "DIV", op_code: 3, fmt: Fmt::F2, operands: Operands::ThreeRegisters, build: |a, b, c| AssemblyInstruction::DIV(a, b, c as Register) },
+    OpCodeEntry { mnemonic: "MOD", op_code: 4, fmt: Fmt::F2, operands: Operands::ThreeRegisters, build: |a, b, c| AssemblyInstruction::MOD(a, b, c as Register) },
+    OpCodeEntry { mnemonic: "CMP", op_code: 5, fmt: Fmt::F2, operands: Operands::ThreeRegisters, build: |a, b, c| AssemblyInstruction::CMP(a, b, c as Register) },
+    OpCodeEntry { mnemonic: "OR", op_code: 8, fmt: Fmt::F2, operands: Operands::ThreeRegisters, build: |a, b, c| AssemblyInstruction::OR(a, b, c as Register) },
+    OpCodeEntry { mnemonic: "AND", op_code: 9, fmt: Fmt::F2, operands: Operands::ThreeRegisters, build: |a, b, c| AssemblyInstruction::AND(a, b, c as Register) },
+    OpCodeEntry { mnemonic: "BIC", op_code: 10, fmt: Fmt::F2, operands: Operands::ThreeRegisters, build: |a, b, c| AssemblyInstruction::BIC(a, b, c as Register) },
+    OpCodeEntry { mnemonic: "XOR", op_code: 11, fmt: Fmt::F2, operands: Operands::ThreeRegisters, build: |a, b, c| AssemblyInstruction::XOR(a, b, c as Register) },
+    OpCodeEntry { mnemonic: "LSH", op_code: 12, fmt: Fmt::F2, operands: Operands::ThreeRegisters, build: |a, b, c| AssemblyInstruction::LSH(a, b, c as Register) },
+    OpCodeEntry { mnemonic: "ASH", op_code: 13, fmt: Fmt::F2, operands: Operands::ThreeRegisters, build: |a, b, c| AssemblyInstruction::ASH(a, b, c as Register) },
+    OpCodeEntry { mnemonic: "CHK", op_code: 14, fmt: Fmt::F2, operands: Operands::TwoRegisters, build: |a, _, c| AssemblyInstruction::CHK(a, c as Register) },
+    OpCodeEntry { mnemonic: "ADDI", op_code: 16, fmt: Fmt::F1, operands: Operands::TwoRegistersConstant, build: |a, b, c| AssemblyInstruction::ADDI(a, b, c) },
+    OpCodeEntry { mnemonic: "SUBI", op_code: 17, fmt: Fmt::F1, operands: Operands::TwoRegistersConstant, build: |a, b, c| AssemblyInstruction::SUBI(a, b, c) },
+    OpCodeEntry { mnemonic: "MULI", op_code: 18, fmt: Fmt::F1, operands: Operands::TwoRegistersConstant, build: |a, b, c| AssemblyInstruction::MULI(a, b, c) },
+    OpCodeEntry { mnemonic: "DIVI", op_code: 19, fmt: Fmt::F1, operands: Operands::TwoRegistersConstant, build: |a, b, c| AssemblyInstruction::DIVI(a, b, c) },
+    OpCodeEntry { mnemonic: "MODI", op_code: 20, fmt: Fmt::F1, operands: Operands::TwoRegistersConstant, build: |a, b, c| AssemblyInstruction::MODI(a, b, c) },
+    OpCodeEntry { mnemonic: "CMPI", op_code: 21, fmt: Fmt::F1, operands: Operands::TwoRegistersConstant, build: |a, b, c| AssemblyInstruction::CMPI(a, b, c) },
+    OpCodeEntry { mnemonic: "ORI", op_code: 24, fmt: Fmt::F1, operands: Operands::TwoRegistersConstant, build: |a, b, c| AssemblyInstruction::ORI(a, b, c) },
+    OpCodeEntry { mnemonic: "ANDI", op_code: 25, fmt: Fmt::F1, operands: Operands::TwoRegistersConstant, build: |a, b, c| AssemblyInstruction::ANDI(a, b, c) },
+    OpCodeEntry { mnemonic: "BICI", op_code: 26, fmt: Fmt::F1, operands: Operands::TwoRegistersConstant, build: |a, b, c| AssemblyInstruction::BICI(a, b, c) },
+    OpCodeEntry { mnemonic: "XORI", op_code: 27, fmt: Fmt::F1, operands: Operands::TwoRegistersConstant, build: |a, b, c| AssemblyInstruction::XORI(a, b, c) },
+    OpCodeEntry { mnemonic: "LSHI", op_code: 28, fmt: Fmt::F1, operands: Operands::TwoRegistersConstant, build: |a, b, c| AssemblyInstruction::LSHI(a, b, c) },
+    OpCodeEntry { mnemonic: "ASHI", op_code: 29, fmt: Fmt::F1, operands: Operands::TwoRegistersConstant, build: |a, b, c| AssemblyInstruction::ASHI(a, b, c) },
+    OpCodeEntry { mnemonic: "CHKI", op_code: 30, fmt: Fmt::F1, operands: Operands::RegisterConstant, build: |a, _, c| AssemblyInstruction::CHKI(a, c) },
+    OpCodeEntry { mnemonic: "LDW", op_code: 32, fmt: Fmt::F1, operands: Operands::TwoRegistersConstant, build: |a, b, c| AssemblyInstruction::LDW(a, b, c) },
+    OpCodeEntry { mnemonic: "LDX", op_code: 33, fmt: Fmt::F2, operands: Operands::ThreeRegisters, build: |a, b, c| AssemblyInstruction::LDX(a, b, c as Register) },
+    OpCodeEntry { mnemonic: "POP", op_code: 34, fmt: Fmt::F1, operands: Operands::TwoRegistersConstant, build: |a, b, c| AssemblyInstruction::POP(a, b, c) },
+    OpCodeEntry { mnemonic: "STW", op_code: 36, fmt: Fmt::F1, operands: Operands::TwoRegistersConstant, build: |a, b, c| AssemblyInstruction::STW(a, b, c) },
+    OpCodeEntry { mnemonic: "STX", op_code: 37, fmt: Fmt::F2, operands: Operands::ThreeRegisters, build: |a, b, c| AssemblyInstruction::STX(a, b, c as Register) },
+    OpCodeEntry { mnemonic: "PSH", op_code: 38, fmt: Fmt::F1, operands: Operands::TwoRegistersConstant, build: |a, b, c| AssemblyInstruction::PSH(a, b, c) },
+    OpCodeEntry { mnemonic: "BEQ", op_code: 40, fmt: Fmt::F1, operands: Operands::RegisterTarget, build: |a, _, c| AssemblyInstruction::BEQ(a, c) },
+    OpCodeEntry { mnemonic: "BNE", op_code: 41, fmt: Fmt::F1, operands: Operands::RegisterTarget, build: |a, _, c| AssemblyInstruction::BNE(a, c) },
+    OpCodeEntry { mnemonic: "BLT", op_code: 42, fmt: Fmt::F1, operands: Operands::RegisterTarget, build: |a, _, c| AssemblyInstruction::BLT(a, c) },
+    OpCodeEntry { mnemonic: "BGE", op_code: 43, fmt: Fmt::F1, operands: Operands::RegisterTarget, build: |a, _, c| AssemblyInstruction::BGE(a, c) },
+    OpCodeEntry { mnemonic: "BLE", op_code: 44, fmt: Fmt::F1, operands: Operands::RegisterTarget, build: |a, _, c| AssemblyInstruction::BLE(a, c) },
+    OpCodeEntry { mnemonic: "BGT", op_code: 45, fmt: Fmt::F1, operands: Operands::RegisterTarget, build: |a, _, c| AssemblyInstruction::BGT(a, c) },
+    OpCodeEntry { mnemonic: "BSR", op_code: 46, fmt: Fmt::F1, operands: Operands::Target, build: |_, _, c| AssemblyInstruction::BSR(c) },
+    OpCodeEntry { mnemonic: "JSR", op_code: 48, fmt: Fmt::F3, operands: Operands::Target, build: |_, _, c| AssemblyInstruction::JSR(c) },
+    OpCodeEntry { mnemonic: "RET", op_code: 49, fmt: Fmt::F2, operands: Operands::RegisterC, build: |_, _, c| AssemblyInstruction::RET(c) },
+    OpCodeEntry { mnemonic: "RDD", op_code: 50, fmt: Fmt::F2, operands: Operands::RegisterA, build: |a, _, _| AssemblyInstruction::RDD(a) },
+    OpCodeEntry { mnemonic: "WRD", op_code: 51, fmt: Fmt::F2, operands: Operands::RegisterB, build: |_, b, _| AssemblyInstruction::WRD(b) },
+    OpCodeEntry { mnemonic: "WRH", op_code: 52, fmt: Fmt::F2, operands: Operands::RegisterB, build: |_, b, _| AssemblyInstruction::WRH(b) },
+    OpCodeEntry { mnemonic: "WRL", op_code: 53, fmt: Fmt::F1, operands: Operands::Nothing, build: |_, _, _| AssemblyInstruction::WRL },
+];
+
 impl AssemblyInstruction {
+    // the row of the op code table this instruction is built by
+    fn get_op_code_entry(&self) -> &'static OpCodeEntry {
+        OP_CODES.iter().find(|entry| discriminant(&(entry.build)(0, 0, 0)) == discriminant(self)).unwrap()
+    }
+
     pub fn get_op_code(&self) -> OpCode {
-        match *self {
-            AssemblyInstruction::ADD(_, _, _) => 0,
-            AssemblyInstruction::SUB(_, _, _) => 1,
-            AssemblyInstruction::MUL(_, _, _) => 2,
-            AssemblyInstruction::DIV(_, _, _) => 3,
-            AssemblyInstruction::MOD(_, _, _) => 4,
-            AssemblyInstruction::CMP(_, _, _) => 5,
-
-            AssemblyInstruction::OR(_, _, _) => 8,
-            AssemblyInstruction::AND(_, _, _) => 9,
-            AssemblyInstruction::BIC(_, _, _) => 10,
-            AssemblyInstruction::XOR(_, _, _) => 11,
-
-            AssemblyInstruction::LSH(_, _, _) => 12,
-            AssemblyInstruction::ASH(_, _, _) => 13,
-            AssemblyInstruction::CHK(_, _) => 14,
-
-            AssemblyInstruction::ADDI(_, _, _) => 16,
-            AssemblyInstruction::SUBI(_, _, _) => 17,
-            AssemblyInstruction::MULI(_, _, _) => 18,
-            AssemblyInstruction::DIVI(_, _, _) => 19,
-            AssemblyInstruction::MODI(_, _, _) => 20,
-            AssemblyInstruction::CMPI(_, _, _) => 21,
-            AssemblyInstruction::ORI(_, _, _) => 24,
-            AssemblyInstruction::ANDI(_, _, _) => 25,
-            AssemblyInstruction::BICI(_, _, _) => 26,
-            AssemblyInstruction::XORI(_, _, _) => 27,
-            AssemblyInstruction::LSHI(_, _, _) => 28,
-            AssemblyInstruction::ASHI(_, _, _) => 29,
-            AssemblyInstruction::CHKI(_, _) => 30,
-
-            AssemblyInstruction::LDW(_, _, _) => 32,
-            AssemblyInstruction::LDX(_, _, _) => 33,
-            AssemblyInstruction::POP(_, _, _) => 34,
-            AssemblyInstruction::STW(_, _, _) => 36,
-            AssemblyInstruction::STX(_, _, _) => 37,
-            AssemblyInstruction::PSH(_, _, _) => 38,
-
-            AssemblyInstruction::BEQ(_, _) => 40,
-            AssemblyInstruction::BNE(_, _) => 41,
-            AssemblyInstruction::BLT(_, _) => 42,
-            AssemblyInstruction::BGE(_, _) => 43,
-            AssemblyInstruction::BLE(_, _) => 44,
-            AssemblyInstruction::BGT(_, _) => 45,
-
-            AssemblyInstruction::BSR(_) => 46,
-            AssemblyInstruction::JSR(_) => 48,
-            AssemblyInstruction::RET(_) => 49,
-
-            AssemblyInstruction::RDD(_) => 50,
-            AssemblyInstruction::WRD(_) => 51,
-            AssemblyInstruction::WRH(_) => 52,
-            AssemblyInstruction::WRL => 53,
-        }
+        self.get_op_code_entry().op_code
     }
+
     pub fn get_fmt(&self) -> Fmt {
-        match *self {
-            // Fmt::F1 - Instructions with three registers
-            AssemblyInstruction::ADD(_, _, _)
-            | AssemblyInstruction::SUB(_, _, _)
-            | AssemblyInstruction::MUL(_, _, _)
-            | AssemblyInstruction::DIV(_, _, _)
-            | AssemblyInstruction::MOD(_, _, _)
-            | AssemblyInstruction::CMP(_, _, _)
-            | AssemblyInstruction::OR(_, _, _)
-            | AssemblyInstruction::AND(_, _, _)
-            | AssemblyInstruction::BIC(_, _, _)
-            | AssemblyInstruction::XOR(_, _, _)
-            | AssemblyInstruction::LSH(_, _, _)
-            | AssemblyInstruction::ASH(_, _, _)
-            | AssemblyInstruction::LDX(_, _, _)
-            | AssemblyInstruction::STX(_, _, _) => Fmt::F1,
-
-            // Fmt::F2 - Instructions with two registers and a constant
-            AssemblyInstruction::ADDI(_, _, _)
-            | AssemblyInstruction::SUBI(_, _, _)
-            | AssemblyInstruction::MULI(_, _, _)
-            | AssemblyInstruction::DIVI(_, _, _)
-            | AssemblyInstruction::MODI(_, _, _)
-            | AssemblyInstruction::CMPI(_, _, _)
-            | AssemblyInstruction::ORI(_, _, _)
-            | AssemblyInstruction::ANDI(_, _, _)
-            | AssemblyInstruction::BICI(_, _, _)
-            | AssemblyInstruction::XORI(_, _, _)
-            | AssemblyInstruction::LSHI(_, _, _)
-            | AssemblyInstruction::ASHI(_, _, _)
-            | AssemblyInstruction::CHKI(_, _)
-            | AssemblyInstruction::LDW(_, _, _)
-            | AssemblyInstruction::POP(_, _, _)
-            | AssemblyInstruction::STW(_, _, _)
-            | AssemblyInstruction::PSH(_, _, _) => Fmt::F2,
-
-            // Fmt::F3 - Instructions with one or two registers and a constant or none
-            AssemblyInstruction::CHK(_, _)
-            | AssemblyInstruction::BEQ(_, _)
-            | AssemblyInstruction::BNE(_, _)
-            | AssemblyInstruction::BLT(_, _)
-            | AssemblyInstruction::BGE(_, _)
-            | AssemblyInstruction::BLE(_, _)
-            | AssemblyInstruction::BGT(_, _)
-            | AssemblyInstruction::BSR(_)
-            | AssemblyInstruction::JSR(_)
-            | AssemblyInstruction::RET(_)
-            | AssemblyInstruction::RDD(_)
-            | AssemblyInstruction::WRD(_)
-            | AssemblyInstruction::WRH(_)
-            | AssemblyInstruction::WRL => Fmt::F3,
-        }
+        self.get_op_code_entry().fmt
     }
 
-    pub fn get_registers(&self) -> Vec<Register> {
-        match *self {
-            AssemblyInstruction::ADD(rd, rs1, rs2)
-            | AssemblyInstruction::SUB(rd, rs1, rs2)
-            | AssemblyInstruction::MUL(rd, rs1, rs2)
-            | AssemblyInstruction::DIV(rd, rs1, rs2)
-            | AssemblyInstruction::MOD(rd, rs1, rs2)
-            | AssemblyInstruction::CMP(rd, rs1, rs2)
-            | AssemblyInstruction::OR(rd, rs1, rs2)
-            | AssemblyInstruction::AND(rd, rs1, rs2)
-            | AssemblyInstruction::BIC(rd, rs1, rs2)
-            | AssemblyInstruction::XOR(rd, rs1, rs2)
-            | AssemblyInstruction::LSH(rd, rs1, rs2)
-            | AssemblyInstruction::ASH(rd, rs1, rs2)
-            | AssemblyInstruction::LDX(rd, rs1, rs2)
-            | AssemblyInstruction::STX(rs1, rd, rs2) => vec![rd, rs1, rs2],
-
-            AssemblyInstruction::ADDI(rd, rs, _)
-            | AssemblyInstruction::SUBI(rd, rs, _)
-            | AssemblyInstruction::MULI(rd, rs, _)
-            | AssemblyInstruction::DIVI(rd, rs, _)
-            | AssemblyInstruction::MODI(rd, rs, _)
-            | AssemblyInstruction::CMPI(rd, rs, _)
-            | AssemblyInstruction::ORI(rd, rs, _)
-            | AssemblyInstruction::ANDI(rd, rs, _)
-            | AssemblyInstruction::BICI(rd, rs, _)
-            | AssemblyInstruction::XORI(rd, rs, _)
-            | AssemblyInstruction::LSHI(rd, rs, _)
-            | AssemblyInstruction::ASHI(rd, rs, _) => vec![rd, rs],
-
-            AssemblyInstruction::CHK(rs1, rs2) => vec![rs1, rs2],
-
-            AssemblyInstruction::LDW(rd, rs, _)
-            | AssemblyInstruction::POP(rd, rs, _)
-            | AssemblyInstruction::STW(rs, rd, _)
-            | AssemblyInstruction::PSH(rs, rd, _) => vec![rd, rs],
-
-            AssemblyInstruction::CHKI(rs1, _) => vec![rs1],
-
-            AssemblyInstruction::BEQ(rs, _)
-            | AssemblyInstruction::BNE(rs, _)
-            | AssemblyInstruction::BLT(rs, _)
-            | AssemblyInstruction::BGE(rs, _)
-            | AssemblyInstruction::BLE(rs, _)
-            | AssemblyInstruction::BGT(rs, _) => vec![rs],
-
-            AssemblyInstruction::RDD(rd) => vec![rd],
-
-            AssemblyInstruction::WRD(rs) | AssemblyInstruction::WRH(rs) => vec![rs],
-
-            AssemblyInstruction::BSR(_)
-            | AssemblyInstruction::JSR(_)
-            | AssemblyInstruction::RET(_)
-            | AssemblyInstruction::WRL => vec![],
-        }
+    pub fn get_mnemonic(&self) -> &'static str {
+        self.get_op_code_entry().mnemonic
+    }
+
+    pub fn get_operands(&self) -> Operands {
+        self.get_op_code_entry().operands
     }
 
     pub fn get_c(&self) -> Option<Generic> {
@@ -267,6 +221,8 @@ impl AssemblyInstruction {
             | AssemblyInstruction::XOR(_, _, c)
             | AssemblyInstruction::LSH(_, _, c)
             | AssemblyInstruction::ASH(_, _, c)
+            | AssemblyInstruction::LDX(_, _, c)
+            | AssemblyInstruction::STX(_, _, c)
             | AssemblyInstruction::CHK(_, c) => Some(c as Generic),
 
             AssemblyInstruction::ADDI(_, _, c)
@@ -422,52 +378,157 @@ impl AssemblyInstruction {
             | AssemblyInstruction::PSH(_, b, _)
             => Some(b as Generic),
 
+            AssemblyInstruction::WRD(b)
+            | AssemblyInstruction::WRH(b)
+            => Some(b as Generic),
+
             _ => None,
         }
     }
 }
+/// whether a constant fits the sign extended 16 bit c field of F1
+pub fn fits_in_immediate(constant: Constant) -> bool {
+    (i16::MIN as Constant..=i16::MAX as Constant).contains(&constant)
+}
+
 impl AssemblyInstruction {
-    fn update(&mut self, new_value2: isize) {
-        match self {
-            AssemblyInstruction::BEQ(_, ref mut value2) => {
-                *value2 = new_value2;
-            },
-            AssemblyInstruction::BNE(_, ref mut value2) => {
-                *value2 = new_value2;
-            },
-            AssemblyInstruction::BLT(_, ref mut value2) => {
-                *value2 = new_value2;
-            },
-            AssemblyInstruction::BGE(_, ref mut value2) => {
-                *value2 = new_value2;
-            },
-            AssemblyInstruction::BLE(_, ref mut value2) => {
-                *value2 = new_value2;
-            },
-            AssemblyInstruction::BGT(_, ref mut value2) => {
-                *value2 = new_value2;
-            },
-            AssemblyInstruction::JSR(ref mut value2) => {
-                *value2 = new_value2;
+    /// The same operation with its constant taken from register c instead, None if there is no such form.
+    pub fn get_register_form(&self, c: Register) -> Option<AssemblyInstruction> {
+        let register_form = match *self {
+            AssemblyInstruction::ADDI(a, b, _) => AssemblyInstruction::ADD(a, b, c),
+            AssemblyInstruction::SUBI(a, b, _) => AssemblyInstruction::SUB(a, b, c),
+            AssemblyInstruction::MULI(a, b, _) => AssemblyInstruction::MUL(a, b, c),
+            AssemblyInstruction::DIVI(a, b, _) => AssemblyInstruction::DIV(a, b, c),
+            AssemblyInstruction::MODI(a, b, _) => AssemblyInstruction::MOD(a, b, c),
+            AssemblyInstruction::CMPI(a, b, _) => AssemblyInstruction::CMP(a, b, c),
+            AssemblyInstruction::ORI(a, b, _) => AssemblyInstruction::OR(a, b, c),
+            AssemblyInstruction::ANDI(a, b, _) => AssemblyInstruction::AND(a, b, c),
+            AssemblyInstruction::BICI(a, b, _) => AssemblyInstruction::BIC(a, b, c),
+            AssemblyInstruction::XORI(a, b, _) => AssemblyInstruction::XOR(a, b, c),
+            AssemblyInstruction::LSHI(a, b, _) => AssemblyInstruction::LSH(a, b, c),
+            AssemblyInstruction::ASHI(a, b, _) => AssemblyInstruction::ASH(a, b, c),
+            AssemblyInstruction::CHKI(a, _) => AssemblyInstruction::CHK(a, c),
+            AssemblyInstruction::LDW(a, b, _) => AssemblyInstruction::LDX(a, b, c),
+            AssemblyInstruction::STW(a, b, _) => AssemblyInstruction::STX(a, b, c),
+            _ => return None,
+        };
+        Some(register_form)
+    }
+}
+
+/// Where a branch or jump goes before the code has addresses.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Label {
+    /// a basic block of the function being generated, by its node index
+    Block(usize),
+    /// the copies for the phis of the second block on the edge from the first, for a branch that
+    /// cannot do them before it jumps
+    Edge(usize, usize),
+    /// a function, by its name
+    Function(String),
+}
+
+/// One line of code before its labels are resolved.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AssemblyItem {
+    Label(Label),
+    /// the SSA instruction the code after it was generated from
+    Source(LineNumber, Operation),
+    Instruction(AssemblyInstruction),
+    /// a branch or JSR whose c is filled in once the label has an address
+    Jump(AssemblyInstruction, Label),
+}
+
+impl AssemblyInstruction {
+    /// The branch or JSR at word address `address` pointed at word address `target`. Branches
+    /// take the offset in words from themselves, JSR the byte address of the target.
+    pub fn with_target(&self, address: AssemblyIndex, target: AssemblyIndex) -> AssemblyInstruction {
+        let offset = target as Constant - address as Constant;
+        match *self {
+            AssemblyInstruction::BEQ(a, _) => AssemblyInstruction::BEQ(a, offset),
+            AssemblyInstruction::BNE(a, _) => AssemblyInstruction::BNE(a, offset),
+            AssemblyInstruction::BLT(a, _) => AssemblyInstruction::BLT(a, offset),
+            AssemblyInstruction::BGE(a, _) => AssemblyInstruction::BGE(a, offset),
+            AssemblyInstruction::BLE(a, _) => AssemblyInstruction::BLE(a, offset),
+            AssemblyInstruction::BGT(a, _) => AssemblyInstruction::BGT(a, offset),
+            AssemblyInstruction::BSR(_) => AssemblyInstruction::BSR(offset),
+            AssemblyInstruction::JSR(_) => AssemblyInstruction::JSR(target as Constant * 4),
+            _ => panic!("{:?} does not jump to a label", self),
+        }
+    }
+}
+
+/// Lays the functions out one after the other from address 0 and fills in every jump.
+/// Block and edge labels are local to the function they appear in, function labels are shared.
+pub fn resolve_labels(functions: &[&[AssemblyItem]]) -> AssemblyInstructions {
+    let mut function_addresses = HashMap::<&str, AssemblyIndex>::new();
+    let mut block_addresses = Vec::<HashMap<&Label, AssemblyIndex>>::new();
+
+    // first pass, every label gets the address of the instruction after it
+    let mut address = 0;
+    for items in functions {
+        let mut blocks = HashMap::new();
+        for item in items.iter() {
+            match item {
+                AssemblyItem::Label(Label::Function(name)) => { function_addresses.insert(name, address); },
+                AssemblyItem::Label(label) => { blocks.insert(label, address); },
+                AssemblyItem::Source(_, _) => (),
+                AssemblyItem::Instruction(_) | AssemblyItem::Jump(_, _) => address += 1,
             }
-            _ => {
-                panic!("yaaa add more shit later {:?}", self);
-                
+        }
+        block_addresses.push(blocks);
+    }
+
+    // second pass, jumps get their offsets
+    let mut assembly_instructions = AssemblyInstructions::new();
+    for (items, blocks) in functions.iter().zip(&block_addresses) {
+        for item in items.iter() {
+            match item {
+                AssemblyItem::Label(_) | AssemblyItem::Source(_, _) => (),
+                AssemblyItem::Instruction(instruction) => assembly_instructions.push(*instruction),
+                AssemblyItem::Jump(instruction, label) => {
+                    let target = match label {
+                        Label::Function(name) => function_addresses.get(name.as_str()),
+                        _ => blocks.get(label),
+                    };
+                    let target = *target.unwrap_or_else(|| panic!("{:?} is never defined", label));
+                    assembly_instructions.push(instruction.with_target(assembly_instructions.len(), target));
+                },
             }
         }
     }
+
+    assembly_instructions
 }
 
 type AssemblyIndex = usize;
 
+// the block control enters `to` through when it leaves `from`, blocks without instructions
+// have no code and are passed through
+fn get_entering_block(g: &BasicBlockGraph, from: NodeIndex, to: NodeIndex) -> Option<NodeIndex> {
+    g.neighbors_directed(from, Outgoing).find_map(|successor| {
+        let mut previous = from;
+        let mut block = successor;
+        let mut seen = HashSet::new();
+        while block != to && g[block].instructions.is_empty() && seen.insert(block) {
+            previous = block;
+            block = g.neighbors_directed(block, Outgoing).next()?;
+        }
+        (block == to).then_some(previous)
+    })
+}
+
 pub struct CodeGeneration {
     instructions: Vec<Instruction>,
     original_graph: BasicBlockGraph,
     register_mapping: HashMap<LineNumber, RegisterNumber>,
-    assembly_instructions: Vec<AssemblyInstruction>,
-    line_number_to_assembly_map: HashMap<LineNumber, AssemblyIndex>,
-    // maps future instruction to past instruction
-    branch_map: HashMap<LineNumber, AssemblyIndex>
+    assembly_items: Vec<AssemblyItem>,
+    // the first line of every block, where its label goes
+    block_labels: HashMap<LineNumber, usize>,
+    // functions that return keep a frame for their caller, main is not returned from
+    returns_to_caller: bool,
+    // the name of every function by the first line of its entry block, which is how jsr and call name them
+    function_names: HashMap<LineNumber, String>,
 }
 
 impl CodeGeneration {
@@ -475,331 +536,468 @@ impl CodeGeneration {
         let graph1 = graph.clone();
         let new_graph = get_interference_graph(&graph1);
         let cluster_possibilities = get_clusters(&graph1);
-        let (new_upgraded_graph, line_node_map) = get_graph_and_map(&new_graph, &cluster_possibilities);
+        let new_upgraded_graph = get_upgraded_interference_graph(&new_graph, &cluster_possibilities);
         
         let register_mapping = generate_register_mapping(&new_upgraded_graph);
 
-        let mut graph2 = graph.clone();
-        let instructions = traverse_in_order(&mut graph2);
-        
-        let mut graph3 = graph.clone();
+        // blocks go where static weights put them, the order the blocks were parsed in cannot
+        // place a loop that sits in a branch
+        let instructions = linearize(graph, &layout_blocks(graph, &get_static_weights(graph)));
+
+        let graph3 = graph.clone();
+        let block_labels = graph3.node_indices()
+            .filter_map(|node| graph3[node].instructions.first().map(|instruction| (instruction.get_line_number(), node.index())))
+            .collect();
 
         Self {
             original_graph: graph3,
             instructions,
             register_mapping,
-            assembly_instructions: Vec::new(),
-            line_number_to_assembly_map: HashMap::new(),
-            branch_map: HashMap::new()
+            assembly_items: Vec::new(),
+            block_labels,
+            returns_to_caller: graph.node_weights().flat_map(|block| &block.instructions).any(|instruction| matches!(instruction.operation, Operation::Ret(_))),
+            function_names: HashMap::new(),
         }
     }
 
+    /// Generates code with the blocks placed in the given order instead of the one static weights
+    /// give, see `block_layout::layout_blocks`.
+    pub fn with_block_order(graph: &mut BasicBlockGraph, order: &[NodeIndex]) -> Self {
+        let mut code_generation = Self::new(graph);
+        code_generation.instructions = linearize(graph, order);
+        code_generation
+    }
+
+    /// Names the functions calls go to, and the function being generated, by the first line of
+    /// their entry block. Code for a function that is not linked has no name.
+    pub fn set_function_names(&mut self, function_names: HashMap<LineNumber, String>) {
+        self.function_names = function_names;
+    }
+
     pub fn generate_code(&mut self) {
-        for instruction in &self.instructions {
+        let entry_line = self.original_graph.node_weights().next().and_then(|block| block.instructions.first());
+        if let Some(name) = entry_line.and_then(|instruction| self.function_names.get(&instruction.get_line_number())) {
+            self.assembly_items.push(AssemblyItem::Label(Label::Function(name.clone())));
+        }
+        if self.returns_to_caller {
+            self.emit_prologue();
+        }
+
+        let mut block = 0;
+        let mut falls_through = false;
+        // copies for branches that leave a block for a join with phis, placed after the function
+        let mut edges = Vec::new();
+        // the arguments of the next call, pushed once the call is reached
+        let mut arguments = Vec::new();
+        // whatever follows a return in its block is never run, a branch there may name a block with no code
+        let mut returned = false;
+        for instruction in self.instructions.clone() {
 
-            // update any waiting instructions
             let line_number = instruction.get_line_number();
             let operation = *instruction.get_operation_ref();
 
+            // blocks that start with an empty instruction still need their label
+            if let Some(next_block) = self.block_labels.get(&line_number).copied() {
+                // the phis of the next block get their values before control falls into it
+                if falls_through {
+                    self.emit_phi_copies(block, next_block);
+                }
+                block = next_block;
+                self.assembly_items.push(AssemblyItem::Label(Label::Block(block)));
+                returned = false;
+            }
+            if returned {
+                continue;
+            }
+            falls_through = !matches!(operation, Operation::Bra(_) | Operation::Ret(_) | Operation::End);
+            returned = matches!(operation, Operation::Ret(_));
 
+            // an empty instruction emits nothing but keeps its block in the line table
+            self.assembly_items.push(AssemblyItem::Source(line_number, operation));
             if operation == Operation::Empty {
                 continue;
             }
 
+            // a block layout may place blocks after the exit block
             if operation == Operation::End {
-                self.assembly_instructions.push(AssemblyInstruction::RET(0));
-                break;
+                self.emit(AssemblyInstruction::RET(0));
+                continue;
             }
 
-            println!("{}", line_number);
-
             match operation {
                 Operation::Add(value1, value2) => {
                     let line_num_register: u8 = *self.register_mapping.get(&line_number).unwrap() as u8;
                     if value1 <= 0 && value2 <= 0 {
-                        self.assembly_instructions.push(AssemblyInstruction::ADDI(line_num_register, 0, -value1));
-                        self.assembly_instructions.push(AssemblyInstruction::ADDI(line_num_register, line_num_register as u8, -value2));
+                        self.emit(AssemblyInstruction::ADDI(line_num_register, 0, -value1));
+                        self.emit(AssemblyInstruction::ADDI(line_num_register, line_num_register, -value2));
                     } else if value1 > 0 && value2 > 0 {
                         let value1_register:u8 = *self.register_mapping.get(&value1).unwrap() as u8; 
                         let value2_register:u8 = *self.register_mapping.get(&value2).unwrap() as u8;
-                        self.assembly_instructions.push(AssemblyInstruction::ADD(line_num_register, value1_register, value2_register));
+                        self.emit(AssemblyInstruction::ADD(line_num_register, value1_register, value2_register));
 
                     } else if value1 <= 0 {
                         let constant = -value1;
                         let value2_register = *self.register_mapping.get(&value2).unwrap();
-                        self.assembly_instructions.push(AssemblyInstruction::ADDI(line_num_register, value2_register as u8, constant));
+                        self.emit(AssemblyInstruction::ADDI(line_num_register, value2_register as u8, constant));
                     } else if value2 <= 0 {
                         let constant = -value2;
                         let value1_register = *self.register_mapping.get(&value1).unwrap();
-                        self.assembly_instructions.push(AssemblyInstruction::ADDI(line_num_register, value1_register as u8, constant));
+                        self.emit(AssemblyInstruction::ADDI(line_num_register, value1_register as u8, constant));
                     }
                 },
                 Operation::Sub(value1, value2) => {
                     let line_num_register = *self.register_mapping.get(&line_number).unwrap();
                     if value1 <= 0 && value2 <= 0 {
-                        self.assembly_instructions.push(AssemblyInstruction::ADDI(line_num_register as u8, 0, -value1));
-                        self.assembly_instructions.push(AssemblyInstruction::SUBI(line_num_register as u8, line_num_register as u8, -value2));
+                        self.emit(AssemblyInstruction::ADDI(line_num_register as u8, 0, -value1));
+                        self.emit(AssemblyInstruction::SUBI(line_num_register as u8, line_num_register as u8, -value2));
 
                     }
 
                     else if value1 > 0 && value2 > 0 {
                         let value1_register = *self.register_mapping.get(&value1).unwrap(); 
                         let value2_register = *self.register_mapping.get(&value2).unwrap();
-                        self.assembly_instructions.push(AssemblyInstruction::SUB(line_num_register as u8, value1_register as u8, value2_register as u8));
+                        self.emit(AssemblyInstruction::SUB(line_num_register as u8, value1_register as u8, value2_register as u8));
 
                     }
                     
+                    // there is no immediate form with the constant on the left
                     else if value1 <= 0 {
-                        let constant = -value1;
                         let value2_register = *self.register_mapping.get(&value2).unwrap();
-                        self.assembly_instructions.push(AssemblyInstruction::SUBI(line_num_register as u8, value2_register as u8, constant));
+                        self.emit(AssemblyInstruction::ADDI(SCRATCH_REGISTER, 0, -value1));
+                        self.emit(AssemblyInstruction::SUB(line_num_register as u8, SCRATCH_REGISTER, value2_register as u8));
                     }
                     
                     else if value2 <= 0 {
                         let constant = -value2;
                         let value1_register = *self.register_mapping.get(&value1).unwrap();
-                        self.assembly_instructions.push(AssemblyInstruction::SUBI(line_num_register as u8, value1_register as u8, constant));
+                        self.emit(AssemblyInstruction::SUBI(line_num_register as u8, value1_register as u8, constant));
                     }
                 },
                 Operation::Mul(value1, value2) => {
                     let line_num_register = *self.register_mapping.get(&line_number).unwrap();
                     if value1 <= 0 && value2 <= 0 {
-                        self.assembly_instructions.push(AssemblyInstruction::ADDI(line_num_register as u8, 0, -value1));
-                        self.assembly_instructions.push(AssemblyInstruction::MULI(line_num_register as u8, line_num_register as u8, -value2));
+                        self.emit(AssemblyInstruction::ADDI(line_num_register as u8, 0, -value1));
+                        self.emit(AssemblyInstruction::MULI(line_num_register as u8, line_num_register as u8, -value2));
 
                     }
                     
                     else if value1 > 0 && value2 > 0 {
                         let value1_register = *self.register_mapping.get(&value1).unwrap(); 
                         let value2_register = *self.register_mapping.get(&value2).unwrap();
-                        self.assembly_instructions.push(AssemblyInstruction::MUL(line_num_register as u8, value1_register as u8, value2_register as u8));
+                        self.emit(AssemblyInstruction::MUL(line_num_register as u8, value1_register as u8, value2_register as u8));
 
                     }
                     
                     else if value1 <= 0 {
                         let constant = -value1;
                         let value2_register = *self.register_mapping.get(&value2).unwrap();
-                        self.assembly_instructions.push(AssemblyInstruction::MULI(line_num_register as u8, value2_register as u8, constant));
+                        self.emit(AssemblyInstruction::MULI(line_num_register as u8, value2_register as u8, constant));
                     }
 
                     else if value2 <= 0 {
                         let constant = -value2;
                         let value1_register = *self.register_mapping.get(&value1).unwrap();
-                        self.assembly_instructions.push(AssemblyInstruction::MULI(line_num_register as u8, value1_register as u8, constant));
+                        self.emit(AssemblyInstruction::MULI(line_num_register as u8, value1_register as u8, constant));
 
                     }
                 },
                 Operation::Div(value1, value2) => {
                     let line_num_register = *self.register_mapping.get(&line_number).unwrap();
                     if value1 <= 0 && value2 <= 0 {
-                        self.assembly_instructions.push(AssemblyInstruction::ADDI(line_num_register as u8, 0, -value1));
-                        self.assembly_instructions.push(AssemblyInstruction::DIVI(line_num_register as u8, line_num_register as u8, -value2));
+                        self.emit(AssemblyInstruction::ADDI(line_num_register as u8, 0, -value1));
+                        self.emit(AssemblyInstruction::DIVI(line_num_register as u8, line_num_register as u8, -value2));
 
                     }
                     else if value1 > 0 && value2 > 0 {
                         let value1_register = *self.register_mapping.get(&value1).unwrap(); 
                         let value2_register = *self.register_mapping.get(&value2).unwrap();
-                        self.assembly_instructions.push(AssemblyInstruction::DIV(line_num_register as u8, value1_register as u8, value2_register as u8));
+                        self.emit(AssemblyInstruction::DIV(line_num_register as u8, value1_register as u8, value2_register as u8));
 
                     }
+                    // there is no immediate form with the constant on the left
                     else if value1 <= 0 {
-                        let constant = -value1;
                         let value2_register = *self.register_mapping.get(&value2).unwrap();
-                        self.assembly_instructions.push(AssemblyInstruction::DIVI(line_num_register as u8, value2_register as u8, constant));
+                        self.emit(AssemblyInstruction::ADDI(SCRATCH_REGISTER, 0, -value1));
+                        self.emit(AssemblyInstruction::DIV(line_num_register as u8, SCRATCH_REGISTER, value2_register as u8));
                     }
                     else if value2 <= 0 {
                         let constant = -value2;
                         let value1_register = *self.register_mapping.get(&value1).unwrap();
-                        self.assembly_instructions.push(AssemblyInstruction::DIVI(line_num_register as u8, value1_register as u8, constant));
+                        self.emit(AssemblyInstruction::DIVI(line_num_register as u8, value1_register as u8, constant));
 
                     }
                 },
-                Operation::Phi(value1, value2) => {
-                    let line_num_register = *self.register_mapping.get(&line_number).unwrap();
-                    let value1_register = *self.register_mapping.get(&value1).unwrap(); 
-                    let value2_register = *self.register_mapping.get(&value2).unwrap();
-                    
-                    if line_num_register != value1_register {
-                        let index_to_insert = self.find_instruction_index_in_vector_given_line(value1_register as isize);
-                        self.assembly_instructions.insert(index_to_insert, AssemblyInstruction::ADD(line_num_register as u8, value1_register as u8, 0));
-                    
-                    }
-                    
-                    if line_num_register != value2_register {
-                        let index_to_insert = self.find_instruction_index_in_vector_given_line(value2_register as isize);
-                        self.assembly_instructions.insert(index_to_insert, AssemblyInstruction::ADD(line_num_register as u8, value2_register as u8, 0));
-                    }
-
-
-                },
+                // the predecessors copy the operands in on their way into the block
+                Operation::Phi(_, _) => {},
 
                 Operation::Cmp(value1, value2) => {
                     let line_num_register = *self.register_mapping.get(&line_number).unwrap();
                     if value1 <= 0 && value2 <= 0 {
                         let value1_register = *self.register_mapping.get(&value1).unwrap(); 
-                        self.assembly_instructions.push(AssemblyInstruction::ADDI(value1_register as u8, 0, -value1));
-                        self.assembly_instructions.push(AssemblyInstruction::CMPI(line_num_register as u8, value1_register as u8, -value2));
+                        self.emit(AssemblyInstruction::ADDI(value1_register as u8, 0, -value1));
+                        self.emit(AssemblyInstruction::CMPI(line_num_register as u8, value1_register as u8, -value2));
 
                     }
                     else if value1 > 0 && value2 > 0 {
                         let value1_register = *self.register_mapping.get(&value1).unwrap(); 
                         let value2_register = *self.register_mapping.get(&value2).unwrap();
-                        self.assembly_instructions.push(AssemblyInstruction::CMP(line_num_register as u8, value1_register as u8, value2_register as u8));
+                        self.emit(AssemblyInstruction::CMP(line_num_register as u8, value1_register as u8, value2_register as u8));
 
                     }
                     else if value1 <= 0 {
                         let value2_register = *self.register_mapping.get(&value2).unwrap(); 
-                        self.assembly_instructions.push(AssemblyInstruction::CMPI(line_num_register as u8, value2_register as u8, -value1));
+                        self.emit(AssemblyInstruction::CMPI(line_num_register as u8, value2_register as u8, -value1));
                     }
                     else if value2 <= 0 {
                         let value1_register = *self.register_mapping.get(&value1).unwrap();
-                        self.assembly_instructions.push(AssemblyInstruction::CMPI(line_num_register as u8, value1_register as u8, -value2));
+                        self.emit(AssemblyInstruction::CMPI(line_num_register as u8, value1_register as u8, -value2));
 
                     }
                 },
-                Operation::Bne(comparison_line_number, block_index) => {
-                    let comparison_line_number_register = *self.register_mapping.get(&comparison_line_number).unwrap();
-                    self.assembly_instructions.push(AssemblyInstruction::BNE(comparison_line_number_register as u8, 0)); // 0 is a BS value
-                    let len = self.assembly_instructions.len() - 1;
-                    let mut new_instruction_line_num = self.original_graph[NodeIndex::new(block_index as usize)].get_first_instruction_line_number();
-                    if self.original_graph.node_weight(NodeIndex::from(block_index as u32)).unwrap().instructions[0].get_operation_ref() == &Operation::Empty {
-                        new_instruction_line_num = self.original_graph[NodeIndex::new((block_index + 1) as usize)].get_first_instruction_line_number(); 
+                Operation::Bne(comparison_line_number, block_index) |
+                Operation::Beq(comparison_line_number, block_index) |
+                Operation::Ble(comparison_line_number, block_index) |
+                Operation::Blt(comparison_line_number, block_index) |
+                Operation::Bge(comparison_line_number, block_index) |
+                Operation::Bgt(comparison_line_number, block_index) => {
+                    let comparison_register = *self.register_mapping.get(&comparison_line_number).unwrap() as u8;
+                    let branch = match operation {
+                        Operation::Bne(_, _) => AssemblyInstruction::BNE(comparison_register, 0),
+                        Operation::Beq(_, _) => AssemblyInstruction::BEQ(comparison_register, 0),
+                        Operation::Ble(_, _) => AssemblyInstruction::BLE(comparison_register, 0),
+                        Operation::Blt(_, _) => AssemblyInstruction::BLT(comparison_register, 0),
+                        Operation::Bge(_, _) => AssemblyInstruction::BGE(comparison_register, 0),
+                        _ => AssemblyInstruction::BGT(comparison_register, 0),
+                    };
+                    // the copies for the target's phis must not happen when the branch is not taken
+                    let target = block_index as usize;
+                    if self.get_phi_copies(block, target).is_empty() {
+                        self.assembly_items.push(AssemblyItem::Jump(branch, Label::Block(target)));
+                    } else {
+                        self.assembly_items.push(AssemblyItem::Jump(branch, Label::Edge(block, target)));
+                        edges.push((block, target));
                     }
-
-                    // self.assembly_instructions[len].update(self.original_graph[block_index].); // yay this works
-                    self.branch_map.insert(new_instruction_line_num, len);
-
                 },
-                Operation::Ble(comparison_line_number, block_index) => {
-                    let comparison_line_number_register = *self.register_mapping.get(&comparison_line_number).unwrap();
-                    self.assembly_instructions.push(AssemblyInstruction::BLE(comparison_line_number_register as u8, 0)); // 0 is a BS value
-                    let len = self.assembly_instructions.len() - 1;
-                    let mut new_instruction_line_num = self.original_graph[NodeIndex::new(block_index as usize)].get_first_instruction_line_number();
-                    if self.original_graph.node_weight(NodeIndex::from(block_index as u32)).unwrap().instructions[0].get_operation_ref() == &Operation::Empty {
-                        new_instruction_line_num = self.original_graph[NodeIndex::new((block_index + 1) as usize)].get_first_instruction_line_number(); 
+                Operation::Bra(block_index) => {
+                    self.emit_phi_copies(block, block_index as usize);
+                    // R0 is always zero so the branch is always taken, unlike BSR it leaves R31 alone
+                    self.assembly_items.push(AssemblyItem::Jump(AssemblyInstruction::BEQ(0, 0), Label::Block(block_index as usize)));
+                },
+                Operation::SetPar1(value) | Operation::SetPar2(value) | Operation::SetPar3(value) => {
+                    arguments.push(value);
+                },
+                Operation::GetPar1 | Operation::GetPar2 | Operation::GetPar3 => {
+                    // the arguments sit above the saved return address and frame pointer, the first on top
+                    let position = match operation {
+                        Operation::GetPar1 => 0,
+                        Operation::GetPar2 => 1,
+                        _ => 2,
+                    };
+                    if let Some(line_num_register) = self.register_mapping.get(&line_number) {
+                        self.emit(AssemblyInstruction::LDW(*line_num_register as u8, FRAME_POINTER_REGISTER, 8 + 4 * position));
                     }
-
-                    // self.assembly_instructions[len].update(self.original_graph[block_index].); // yay this works
-                    self.branch_map.insert(new_instruction_line_num, len);
-
                 },
-                Operation::Bge(comparison_line_number, block_index) => {
-                    let comparison_line_number_register = *self.register_mapping.get(&comparison_line_number).unwrap();
-                    self.assembly_instructions.push(AssemblyInstruction::BGE(comparison_line_number_register as u8, 0)); // 0 is a BS value
-                    let len = self.assembly_instructions.len() - 1;
-                    let mut new_instruction_line_num = self.original_graph[NodeIndex::new(block_index as usize)].get_first_instruction_line_number();
-                    if self.original_graph.node_weight(NodeIndex::from(block_index as u32)).unwrap().instructions[0].get_operation_ref() == &Operation::Empty {
-                        new_instruction_line_num = self.original_graph[NodeIndex::new((block_index + 1) as usize)].get_first_instruction_line_number(); 
+                Operation::Jsr(function) | Operation::Call(function) => {
+                    // the last argument is pushed first so the first one ends up on top
+                    for value in arguments.iter().rev().copied().collect::<Vec<_>>() {
+                        let value_register = if value <= 0 {
+                            self.emit(AssemblyInstruction::ADDI(SCRATCH_REGISTER, 0, -value));
+                            SCRATCH_REGISTER
+                        } else {
+                            *self.register_mapping.get(&value).unwrap() as Register
+                        };
+                        self.emit(AssemblyInstruction::PSH(value_register, STACK_POINTER_REGISTER, -4));
                     }
 
-                    // self.assembly_instructions[len].update(self.original_graph[block_index].); // yay this works
-                    self.branch_map.insert(new_instruction_line_num, len);
+                    // the target is resolved by name once functions are laid out
+                    let callee = self.function_names.get(&function).unwrap_or_else(|| panic!("call to ({}), which does not start a function", function));
+                    self.assembly_items.push(AssemblyItem::Jump(AssemblyInstruction::JSR(0), Label::Function(callee.clone())));
 
-                },
-                Operation::Bgt(comparison_line_number, block_index) => {
-                    let comparison_line_number_register = *self.register_mapping.get(&comparison_line_number).unwrap();
-                    self.assembly_instructions.push(AssemblyInstruction::BGT(comparison_line_number_register as u8, 0)); // 0 is a BS value
-                    let len = self.assembly_instructions.len() - 1;
-                    let mut new_instruction_line_num = self.original_graph[NodeIndex::new(block_index as usize)].get_first_instruction_line_number();
-                    if self.original_graph.node_weight(NodeIndex::from(block_index as u32)).unwrap().instructions[0].get_operation_ref() == &Operation::Empty {
-                        new_instruction_line_num = self.original_graph[NodeIndex::new((block_index + 1) as usize)].get_first_instruction_line_number(); 
+                    if !arguments.is_empty() {
+                        self.emit(AssemblyInstruction::ADDI(STACK_POINTER_REGISTER, STACK_POINTER_REGISTER, 4 * arguments.len() as Constant));
+                        arguments.clear();
                     }
 
-                    // self.assembly_instructions[len].update(self.original_graph[block_index].); // yay this works
-                    self.branch_map.insert(new_instruction_line_num, len);
-
+                    // the call's value arrives in the return value register
+                    if let (Operation::Call(_), Some(line_num_register)) = (operation, self.register_mapping.get(&line_number)) {
+                        self.emit(AssemblyInstruction::ADD(*line_num_register as u8, RETURN_VALUE_REGISTER, 0));
+                    }
                 },
-                Operation::Blt(comparison_line_number, block_index) => {
-                    let comparison_line_number_register = *self.register_mapping.get(&comparison_line_number).unwrap();
-                    self.assembly_instructions.push(AssemblyInstruction::BLT(comparison_line_number_register as u8, 0)); // 0 is a BS value
-                    let len = self.assembly_instructions.len() - 1;
-                    let mut new_instruction_line_num = self.original_graph[NodeIndex::new(block_index as usize)].get_first_instruction_line_number();
-                    if self.original_graph.node_weight(NodeIndex::from(block_index as u32)).unwrap().instructions[0].get_operation_ref() == &Operation::Empty {
-                        new_instruction_line_num = self.original_graph[NodeIndex::new((block_index + 1) as usize)].get_first_instruction_line_number(); 
+                Operation::Ret(value) => {
+                    if value <= 0 {
+                        self.emit(AssemblyInstruction::ADDI(RETURN_VALUE_REGISTER, 0, -value));
+                    } else {
+                        let value_register = *self.register_mapping.get(&value).unwrap();
+                        self.emit(AssemblyInstruction::ADD(RETURN_VALUE_REGISTER, value_register as u8, 0));
                     }
-
-                    // self.assembly_instructions[len].update(self.original_graph[block_index].); // yay this works
-                    self.branch_map.insert(new_instruction_line_num, len);
-
+                    self.emit_epilogue();
+                    self.emit(AssemblyInstruction::RET(RETURN_ADDRESS_REGISTER as Constant));
+                },
+                Operation::Read => {
+                    // an unused read still has to consume its input, so it is read into R0
+                    let line_num_register = self.register_mapping.get(&line_number).copied().unwrap_or(0);
+                    self.emit(AssemblyInstruction::RDD(line_num_register as u8));
                 },
-                Operation::Beq(comparison_line_number, block_index) => {
-                    let comparison_line_number_register = *self.register_mapping.get(&comparison_line_number).unwrap();
-                    self.assembly_instructions.push(AssemblyInstruction::BEQ(comparison_line_number_register as u8, 0)); // 0 is a BS value
-                    let len = self.assembly_instructions.len() - 1;
-                    let mut new_instruction_line_num = self.original_graph[NodeIndex::new(block_index as usize)].get_first_instruction_line_number();
-                    if self.original_graph.node_weight(NodeIndex::from(block_index as u32)).unwrap().instructions[0].get_operation_ref() == &Operation::Empty {
-                        new_instruction_line_num = self.original_graph[NodeIndex::new((block_index + 1) as usize)].get_first_instruction_line_number(); 
+                Operation::Write(value) => {
+                    if value <= 0 {
+                        self.emit(AssemblyInstruction::ADDI(SCRATCH_REGISTER, 0, -value));
+                        self.emit(AssemblyInstruction::WRD(SCRATCH_REGISTER));
+                    } else {
+                        let value_register = *self.register_mapping.get(&value).unwrap();
+                        self.emit(AssemblyInstruction::WRD(value_register as u8));
                     }
-
-                    // self.assembly_instructions[len].update(self.original_graph[block_index].); // yay this works
-                    self.branch_map.insert(new_instruction_line_num, len);
-
                 },
-                Operation::Bra(value) => {
-                    self.assembly_instructions.push(AssemblyInstruction::JSR(0)); // 0 is a BS valuegi
-                    let len = self.assembly_instructions.len() - 1;
-                    let mut new_instruction_line_num = self.original_graph[NodeIndex::new(value as usize)].get_first_instruction_line_number();
-                    if self.original_graph.node_weight(NodeIndex::from(value as u32)).unwrap().instructions[0].get_operation_ref() == &Operation::Empty {
-                        new_instruction_line_num = self.original_graph[NodeIndex::new((value + 1) as usize)].get_first_instruction_line_number(); 
+                Operation::WriteNL => {
+                    self.emit(AssemblyInstruction::WRL);
+                },
+                Operation::Load(offset) => {
+                    // a load whose value is never used has no register and nothing to do
+                    if let Some(line_num_register) = self.register_mapping.get(&line_number) {
+                        self.emit(AssemblyInstruction::LDW(*line_num_register as u8, GLOBAL_POINTER_REGISTER, offset));
+                    }
+                },
+                Operation::Store(value, offset) => {
+                    if value <= 0 {
+                        self.emit(AssemblyInstruction::ADDI(SCRATCH_REGISTER, 0, -value));
+                        self.emit(AssemblyInstruction::STW(SCRATCH_REGISTER, GLOBAL_POINTER_REGISTER, offset));
+                    } else {
+                        let value_register = *self.register_mapping.get(&value).unwrap();
+                        self.emit(AssemblyInstruction::STW(value_register as u8, GLOBAL_POINTER_REGISTER, offset));
                     }
-
-                    // self.assembly_instructions[len].update(self.original_graph[block_index].); // yay this works
-                    self.branch_map.insert(new_instruction_line_num, len);
                 },
                 _ => panic!("placeholder: {:?}", operation),
             }
-            
-            self.line_number_to_assembly_map.insert(line_number, self.assembly_instructions.len() - 1);
-            
-            if self.branch_map.contains_key(&line_number) {
-                let assembly_index = *self.line_number_to_assembly_map.get(&line_number).unwrap();
-                
-                let skill_diff = assembly_index - self.branch_map.get(&line_number).unwrap();
-                println!("fuck {:?}", self.assembly_instructions[assembly_index]);
-                match self.assembly_instructions[assembly_index - skill_diff] {
-                    AssemblyInstruction::JSR(_) => {
-                        let x = self.line_number_to_assembly_map.get(&line_number); 
-                        let x = x.unwrap();
-                        self.assembly_instructions[assembly_index - skill_diff].update(*x as isize);
-                        // self.assembly_instructions[assembly_index] = AssemblyInstruction::JSR(line_number);
-                    },
-                    _ => {
-                        self.assembly_instructions[assembly_index - skill_diff].update(skill_diff as isize);
-                    }
-                }
+        }
 
+        for (from, to) in edges {
+            self.assembly_items.push(AssemblyItem::Label(Label::Edge(from, to)));
+            self.emit_phi_copies(from, to);
+            self.assembly_items.push(AssemblyItem::Jump(AssemblyInstruction::BEQ(0, 0), Label::Block(to)));
+        }
+    }
 
+    // every register the function writes, the caller may have values of its own in them
+    fn get_saved_registers(&self) -> Vec<Register> {
+        let mut registers: Vec<Register> = self.register_mapping.values().map(|register| *register as Register).collect();
+        registers.sort();
+        registers.dedup();
+        registers
+    }
 
+    // the return address, the caller's frame pointer and the saved registers go on the stack, and
+    // the frame pointer is left pointing at the caller's frame pointer
+    fn emit_prologue(&mut self) {
+        self.emit(AssemblyInstruction::PSH(RETURN_ADDRESS_REGISTER, STACK_POINTER_REGISTER, -4));
+        self.emit(AssemblyInstruction::PSH(FRAME_POINTER_REGISTER, STACK_POINTER_REGISTER, -4));
+        self.emit(AssemblyInstruction::ADD(FRAME_POINTER_REGISTER, STACK_POINTER_REGISTER, 0));
+        for register in self.get_saved_registers() {
+            self.emit(AssemblyInstruction::PSH(register, STACK_POINTER_REGISTER, -4));
+        }
+    }
 
+    fn emit_epilogue(&mut self) {
+        for register in self.get_saved_registers().into_iter().rev() {
+            self.emit(AssemblyInstruction::POP(register, STACK_POINTER_REGISTER, 4));
+        }
+        self.emit(AssemblyInstruction::POP(FRAME_POINTER_REGISTER, STACK_POINTER_REGISTER, 4));
+        self.emit(AssemblyInstruction::POP(RETURN_ADDRESS_REGISTER, STACK_POINTER_REGISTER, 4));
+    }
 
+    // the register of every phi in `to` with the operand it takes when control comes from `from`
+    fn get_phi_copies(&self, from: usize, to: usize) -> Vec<(Register, LineNumber)> {
+        let g = &self.original_graph;
+        let to = NodeIndex::new(to);
+        let Some(predecessor) = get_entering_block(g, NodeIndex::new(from), to) else {
+            return Vec::new();
+        };
+        // phi operands are in the order of the incoming edges
+        let mut incoming: Vec<_> = g.edges_directed(to, Incoming).collect();
+        incoming.sort_by_key(|edge| edge.id());
+        let Some(position) = incoming.iter().position(|edge| edge.source() == predecessor) else {
+            return Vec::new();
+        };
+
+        g[to].instructions.iter().filter_map(|instruction| match instruction.operation {
+            Operation::Phi(value1, value2) => {
+                let register = *self.register_mapping.get(&instruction.get_line_number())?;
+                Some((register as Register, if position == 0 { value1 } else { value2 }))
+            },
+            _ => None,
+        }).collect()
+    }
 
+    // the copies happen all at once: a register is only written once no other copy still reads
+    // it, and where the copies form a cycle one value is moved out to the scratch register
+    fn emit_phi_copies(&mut self, from: usize, to: usize) {
+        let copies = self.get_phi_copies(from, to);
+        let mut moves: Vec<(Register, Register)> = copies.iter()
+            .filter(|(_, value)| *value > 0)
+            .map(|(register, value)| (*register, *self.register_mapping.get(value).unwrap() as Register))
+            .filter(|(destination, source)| destination != source)
+            .collect();
+
+        while !moves.is_empty() {
+            match moves.iter().position(|(destination, _)| moves.iter().all(|(_, source)| source != destination)) {
+                Some(index) => {
+                    let (destination, source) = moves.remove(index);
+                    self.emit(AssemblyInstruction::ADD(destination, source, 0));
+                },
+                None => {
+                    let (_, source) = moves[0];
+                    self.emit(AssemblyInstruction::ADD(SCRATCH_REGISTER, source, 0));
+                    for (_, other_source) in moves.iter_mut().filter(|(_, other_source)| *other_source == source) {
+                        *other_source = SCRATCH_REGISTER;
+                    }
+                },
+            }
+        }
 
-;            }
-            
+        // constants go last, their registers may still have been read above
+        for (register, value) in copies {
+            if value <= 0 {
+                self.emit(AssemblyInstruction::ADDI(register, 0, -value));
+            }
         }
+    }
 
 
-        println!("MAPPING OF REGISTERS");
-        for (line_number, register_num) in &self.register_mapping {
-            println!("Line({}): R{}", line_number, register_num);
+    pub fn get_assembly_items(&self) -> &[AssemblyItem] {
+        &self.assembly_items
+    }
 
-        }
-        
-        println!("ASSEMBLY INSTRUCTIONS");
-        for assembly_instruction in &self.assembly_instructions {
-            println!("{:?}", assembly_instruction);
-        }
+    /// the code of this function on its own, placed at address 0
+    #[cfg(test)]
+    pub fn get_assembly_instructions(&self) -> AssemblyInstructions {
+        resolve_labels(&[&self.assembly_items])
+    }
 
-        println!("SUPA PRINTS");
-        for instruction in &self.instructions {
-            println!("{:?}", instruction);
+    // constants that do not fit the 16 bit immediate field are built in a register first
+    fn emit(&mut self, instruction: AssemblyInstruction) {
+        if let (Some(constant), Some(register_form)) = (instruction.get_const(), instruction.get_register_form(SCRATCH_REGISTER)) {
+            if !fits_in_immediate(constant) {
+                if let AssemblyInstruction::ADDI(destination, 0, _) = instruction {
+                    self.emit_large_constant(destination, constant);
+                } else {
+                    self.emit_large_constant(SCRATCH_REGISTER, constant);
+                    self.assembly_items.push(AssemblyItem::Instruction(register_form));
+                }
+                return;
+            }
         }
+        self.assembly_items.push(AssemblyItem::Instruction(instruction));
     }
 
+    // immediates are sign extended, so the high half absorbs the borrow of a negative low half
+    fn emit_large_constant(&mut self, register: Register, constant: Constant) {
+        let value = constant as i32;
+        let low = value as i16;
+        let high = (value.wrapping_sub(low as i32) >> 16) as i16;
 
-    fn find_instruction_index_in_vector_given_line(&self, line_number: isize) -> AssemblyIndex {
-        println!("YA: {}", line_number);
-        *self.line_number_to_assembly_map.get(&line_number).unwrap()
+        self.assembly_items.push(AssemblyItem::Instruction(AssemblyInstruction::ADDI(register, 0, high as Constant)));
+        self.assembly_items.push(AssemblyItem::Instruction(AssemblyInstruction::LSHI(register, register, 16)));
+        if low != 0 {
+            self.assembly_items.push(AssemblyItem::Instruction(AssemblyInstruction::ADDI(register, register, low as Constant)));
+        }
     }
 
 }
@@ -809,7 +1007,10 @@ impl CodeGeneration {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::assembler::get_machine_code_instructions;
     use crate::dot_viz::generate_dot_viz;
+    use crate::emulator::Emulator;
+    use crate::parser::Parser;
     #[test]
     pub fn first() {
         let input = "
@@ -830,7 +1031,7 @@ mod tests {
 
         println!("{}", generate_dot_viz("main", &parser.internal_program));
 
-        let mut bbg = &parser.internal_program.get_curr_fn().bb_graph;
+        let bbg = &parser.internal_program.get_curr_fn().bb_graph;
         let mut bbg = bbg.clone(); 
 
         let mut bruh = CodeGeneration::new(&mut bbg);
@@ -840,5 +1041,89 @@ mod tests {
        
 
 
+    }
+
+    #[test]
+    fn test_resolve_labels() {
+        let main = [
+            AssemblyItem::Label(Label::Function("main".to_string())),
+            AssemblyItem::Jump(AssemblyInstruction::JSR(0), Label::Function("f".to_string())),
+            AssemblyItem::Label(Label::Block(1)),
+            AssemblyItem::Jump(AssemblyInstruction::BNE(1, 0), Label::Block(2)),
+            AssemblyItem::Jump(AssemblyInstruction::BEQ(0, 0), Label::Block(1)),
+            AssemblyItem::Label(Label::Block(2)),
+            AssemblyItem::Instruction(AssemblyInstruction::RET(0)),
+        ];
+        // the same block label in another function is a different place
+        let function = [
+            AssemblyItem::Label(Label::Function("f".to_string())),
+            AssemblyItem::Label(Label::Block(2)),
+            AssemblyItem::Jump(AssemblyInstruction::BSR(0), Label::Block(2)),
+            AssemblyItem::Instruction(AssemblyInstruction::RET(31)),
+        ];
+
+        assert_eq!(resolve_labels(&[&main, &function]), vec![
+            AssemblyInstruction::JSR(16),
+            AssemblyInstruction::BNE(1, 2),
+            AssemblyInstruction::BEQ(0, -1),
+            AssemblyInstruction::RET(0),
+            AssemblyInstruction::BSR(0),
+            AssemblyInstruction::RET(31),
+        ]);
+    }
+
+    #[test]
+    fn test_large_constants() {
+        let input = "main var a, b; { let a <- 100000; let b <- call InputNum(); call OutputNum(a); call OutputNum(b + 2147483647); call OutputNum(b * 40000); call OutputNum(0 - 32769) }.";
+        let mut parser = Parser::new(input.to_string());
+        parser.parse_computation();
+
+        let mut code_generation = CodeGeneration::new(&mut parser.internal_program.get_fn_mut("main").bb_graph);
+        code_generation.generate_code();
+        let assembly_instructions = code_generation.get_assembly_instructions();
+        assert!(assembly_instructions.iter().all(|instruction| instruction.get_const().is_none_or(fits_in_immediate)));
+
+        let machine_code = get_machine_code_instructions(assembly_instructions).unwrap();
+        let mut emulator = Emulator::new(&machine_code, "3".as_bytes(), Vec::new()).unwrap();
+        emulator.run().unwrap();
+        assert_eq!(emulator.get_writer().as_slice(), b"100000 -2147483646 120000 -32769 ");
+    }
+
+    #[test]
+    #[should_panic(expected = "is never defined")]
+    fn test_undefined_label() {
+        resolve_labels(&[&[AssemblyItem::Jump(AssemblyInstruction::BEQ(0, 0), Label::Block(3))]]);
+    }
+
+    fn run(input: &str, program_input: &str) -> String {
+        let mut parser = Parser::new(input.to_string());
+        parser.parse_computation();
+
+        let mut code_generation = CodeGeneration::new(&mut parser.internal_program.get_fn_mut("main").bb_graph);
+        code_generation.generate_code();
+        let machine_code = get_machine_code_instructions(code_generation.get_assembly_instructions()).unwrap();
+        let mut emulator = Emulator::new(&machine_code, program_input.as_bytes(), Vec::new()).unwrap();
+        emulator.run().unwrap();
+        String::from_utf8(emulator.get_writer().clone()).unwrap()
+    }
+
+    #[test]
+    fn test_phi_copies() {
+        // the branch around the then block goes through copies of its own
+        let input = "main var a, b; { let a <- call InputNum(); let b <- a; if a < 10 then let b <- a + 1 fi; call OutputNum(b) }.";
+        assert_eq!(run(input, "3"), "4 ");
+        assert_eq!(run(input, "12"), "12 ");
+
+        let input = "main var a, b; { let a <- call InputNum(); if a < 10 then let b <- 10 else let b <- a fi; call OutputNum(b) }.";
+        assert_eq!(run(input, "3"), "10 ");
+        assert_eq!(run(input, "12"), "12 ");
+
+        // the loop phis of a and b swap their values on the back edge
+        let input = "main var a, b, c, i; {
+            let a <- call InputNum(); let b <- call InputNum(); let i <- 0;
+            while i < 3 do let c <- a; let a <- b; let b <- c; let i <- i + 1 od;
+            call OutputNum(a); call OutputNum(b)
+        }.";
+        assert_eq!(run(input, "1\n2"), "2 1 ");
     }
 }
@@ -21,7 +21,7 @@ impl ConstantBlock {
     // Adds a constant into the table
     pub fn add_constant(&mut self, constant: isize) {
         let operation = Operation::Const(constant);
-        let line_number = constant * -1;
+        let line_number = -constant;
         let instruction = Instruction::new(line_number, operation);
 
         self.constant_table.insert(constant, instruction);
@@ -38,11 +38,10 @@ impl ConstantBlock {
         }
     }
     
-    // used for testing purposes to display values
-    pub fn display_table(&self) {
-        for instruction in self.constant_table.values() {
-            println!("{:?}", instruction);
-        }
-    }
 
 }
+
+// the value of a constant line, constants live at the negated line numbers
+pub fn get_constant_value(line: isize) -> Option<isize> {
+    (line <= 0).then_some(-line)
+}
@@ -0,0 +1,229 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::hash::Hash;
+
+use petgraph::graph::NodeIndex;
+use petgraph::visit::DfsPostOrder;
+use petgraph::Direction::{Incoming, Outgoing};
+
+use crate::live_analysis::BasicBlockGraph;
+
+/// The facts an analysis tracks. Meeting a fact with the one every block starts from leaves it
+/// as it is, and meeting only ever moves a fact down, so the solver reaches a fixed point.
+pub trait Lattice: Clone + PartialEq {
+    /// what a block starts from before anything has flowed into it
+    fn top() -> Self;
+    /// combines the facts of two paths into this one
+    fn meet(&mut self, other: &Self);
+}
+
+/// A set that holds what is true on some path, combined by union.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnionSet<T: Eq + Hash>(pub HashSet<T>);
+
+impl<T: Clone + Eq + Hash> Lattice for UnionSet<T> {
+    fn top() -> Self {
+        UnionSet(HashSet::new())
+    }
+
+    fn meet(&mut self, other: &Self) {
+        self.0.extend(other.0.iter().cloned());
+    }
+}
+
+/// A set that holds what is true on every path, combined by intersection. Nothing has been
+/// ruled out at the top, which is every value there is.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IntersectionSet<T: Eq + Hash> {
+    All,
+    Of(HashSet<T>),
+}
+
+impl<T: Eq + Hash> IntersectionSet<T> {
+    pub fn contains(&self, value: &T) -> bool {
+        match self {
+            IntersectionSet::All => true,
+            IntersectionSet::Of(values) => values.contains(value),
+        }
+    }
+}
+
+impl<T: Clone + Eq + Hash> Lattice for IntersectionSet<T> {
+    fn top() -> Self {
+        IntersectionSet::All
+    }
+
+    fn meet(&mut self, other: &Self) {
+        match (&mut *self, other) {
+            (_, IntersectionSet::All) => {},
+            (IntersectionSet::All, IntersectionSet::Of(values)) => *self = IntersectionSet::Of(values.clone()),
+            (IntersectionSet::Of(values), IntersectionSet::Of(other_values)) => values.retain(|value| other_values.contains(value)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// facts flow from the entry along the edges
+    Forward,
+    /// facts flow from the exits against the edges
+    Backward,
+}
+
+/// An analysis over the blocks of a function.
+pub trait Analysis {
+    type Fact: Lattice;
+    const DIRECTION: Direction;
+
+    /// the fact at the start of the entry block going forward, or at the end of every block
+    /// without successors going backward
+    fn get_boundary(&self, g: &BasicBlockGraph) -> Self::Fact;
+
+    /// what the block makes of the fact flowing into it, at its start going forward or at its
+    /// end going backward
+    fn transfer(&self, g: &BasicBlockGraph, block: NodeIndex, fact: &Self::Fact) -> Self::Fact;
+}
+
+/// The facts at the start and the end of every block.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DataflowResult<F> {
+    pub in_facts: HashMap<NodeIndex, F>,
+    pub out_facts: HashMap<NodeIndex, F>,
+}
+
+// the reachable blocks in reverse post-order, then the unreachable ones
+fn get_order(g: &BasicBlockGraph) -> Vec<NodeIndex> {
+    let mut order = Vec::new();
+    if g.node_count() > 0 {
+        let mut dfs = DfsPostOrder::new(g, NodeIndex::new(0));
+        while let Some(block) = dfs.next(g) {
+            order.push(block);
+        }
+    }
+    order.reverse();
+    let reachable: HashSet<NodeIndex> = order.iter().copied().collect();
+    order.extend(g.node_indices().filter(|block| !reachable.contains(block)));
+    order
+}
+
+/// Finds the fixed point of the analysis with a worklist. Blocks are taken in reverse post-order
+/// going forward and in post-order going backward, so most blocks see the facts of the blocks
+/// before them in the direction of the analysis the first time they are visited.
+pub fn solve<A: Analysis>(g: &BasicBlockGraph, analysis: &A) -> DataflowResult<A::Fact> {
+    let mut order = get_order(g);
+    let (before, after) = match A::DIRECTION {
+        Direction::Forward => (Incoming, Outgoing),
+        Direction::Backward => {
+            order.reverse();
+            (Outgoing, Incoming)
+        },
+    };
+    let positions: HashMap<NodeIndex, usize> = order.iter().enumerate().map(|(position, block)| (*block, position)).collect();
+
+    // the fact flowing into a block and the one it passes on, in the direction of the analysis
+    let mut inputs: HashMap<NodeIndex, A::Fact> = HashMap::new();
+    let mut outputs: HashMap<NodeIndex, A::Fact> = g.node_indices().map(|block| (block, A::Fact::top())).collect();
+
+    let boundary = analysis.get_boundary(g);
+    let mut work: BTreeSet<usize> = (0..order.len()).collect();
+    while let Some(position) = work.pop_first() {
+        let block = order[position];
+
+        let is_boundary = match A::DIRECTION {
+            Direction::Forward => block.index() == 0,
+            Direction::Backward => g.neighbors_directed(block, Outgoing).next().is_none(),
+        };
+        let mut input = if is_boundary { boundary.clone() } else { A::Fact::top() };
+        for neighbor in g.neighbors_directed(block, before) {
+            input.meet(&outputs[&neighbor]);
+        }
+
+        let output = analysis.transfer(g, block, &input);
+        inputs.insert(block, input);
+        if output != outputs[&block] {
+            outputs.insert(block, output);
+            work.extend(g.neighbors_directed(block, after).map(|neighbor| positions[&neighbor]));
+        }
+    }
+
+    match A::DIRECTION {
+        Direction::Forward => DataflowResult { in_facts: inputs, out_facts: outputs },
+        Direction::Backward => DataflowResult { in_facts: outputs, out_facts: inputs },
+    }
+}
+
+#[cfg(test)]
+mod dataflow_tests {
+    use super::*;
+    use crate::instruction::Operation;
+    use crate::parser::Parser;
+
+    type LineNumber = isize;
+
+    // the lines that are defined on every path to a block
+    struct DefiniteDefinitions;
+
+    impl Analysis for DefiniteDefinitions {
+        type Fact = IntersectionSet<LineNumber>;
+        const DIRECTION: Direction = Direction::Forward;
+
+        fn get_boundary(&self, _g: &BasicBlockGraph) -> Self::Fact {
+            IntersectionSet::Of(HashSet::new())
+        }
+
+        fn transfer(&self, g: &BasicBlockGraph, block: NodeIndex, fact: &Self::Fact) -> Self::Fact {
+            match fact {
+                IntersectionSet::All => IntersectionSet::All,
+                IntersectionSet::Of(lines) => {
+                    let defined = g[block].instructions.iter().map(|instruction| instruction.get_line_number());
+                    IntersectionSet::Of(lines.iter().copied().chain(defined).collect())
+                },
+            }
+        }
+    }
+
+    fn get_graph(input: &str) -> BasicBlockGraph {
+        let mut parser = Parser::new(input.to_string());
+        parser.parse_computation();
+        parser.internal_program.get_fn("main").bb_graph.clone()
+    }
+
+    fn find_line(g: &BasicBlockGraph, matches: impl Fn(&Operation) -> bool) -> (NodeIndex, LineNumber) {
+        g.node_indices()
+            .find_map(|block| g[block].instructions.iter().find(|instruction| matches(&instruction.operation)).map(|instruction| (block, instruction.get_line_number())))
+            .unwrap()
+    }
+
+    #[test]
+    fn test_lattices() {
+        let mut union = UnionSet(HashSet::from([1, 2]));
+        union.meet(&UnionSet(HashSet::from([3])));
+        union.meet(&UnionSet::top());
+        assert_eq!(union, UnionSet(HashSet::from([1, 2, 3])));
+
+        let mut intersection = IntersectionSet::top();
+        intersection.meet(&IntersectionSet::Of(HashSet::from([1, 2])));
+        intersection.meet(&IntersectionSet::Of(HashSet::from([2, 3])));
+        assert_eq!(intersection, IntersectionSet::Of(HashSet::from([2])));
+        assert!(IntersectionSet::<isize>::All.contains(&7));
+    }
+
+    #[test]
+    fn test_forward_analysis() {
+        let g = get_graph("main var a, b; { let a <- call InputNum(); if a > 2 then let b <- a + 1 else let b <- a + 2 fi; while b > 0 do let b <- b - 1 od; call OutputNum(b) }.");
+        let result = solve(&g, &DefiniteDefinitions);
+
+        let (_, read) = find_line(&g, |operation| *operation == Operation::Read);
+        let (then_block, then_add) = find_line(&g, |operation| matches!(operation, Operation::Add(_, -1)));
+        let (join, _) = find_line(&g, |operation| matches!(operation, Operation::Phi(_, _)));
+        let (write_block, write) = find_line(&g, |operation| matches!(operation, Operation::Write(_)));
+        let (_, subtraction) = find_line(&g, |operation| matches!(operation, Operation::Sub(_, _)));
+
+        assert!(result.out_facts[&then_block].contains(&then_add));
+        // only one side of the if defines the addition, both come after the read
+        assert!(result.in_facts[&join].contains(&read));
+        assert!(!result.in_facts[&join].contains(&then_add));
+        // the loop body may not run before the write
+        assert!(!result.in_facts[&write_block].contains(&subtraction));
+        assert!(result.out_facts[&write_block].contains(&write));
+    }
+}
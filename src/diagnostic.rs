@@ -0,0 +1,40 @@
+use crate::tokenizer::Span;
+use std::fmt;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// A problem found in the source, reported against the token it was found at.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub span: Span,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        write!(f, "{}: {}: {}", self.span, severity, self.message)
+    }
+}
+
+impl Diagnostic {
+    pub fn error(span: Span, message: String) -> Self {
+        Self { severity: Severity::Error, span, message }
+    }
+
+    pub fn warning(span: Span, message: String) -> Self {
+        Self { severity: Severity::Warning, span, message }
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+}
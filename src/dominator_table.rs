@@ -27,7 +27,7 @@ impl DominatorTable {
     
     // inserts the instructions in the table
     pub fn insert_instruction(&mut self, operation: &Operation, line_number: isize) {
-        let inserted_operation = operation.clone();
+        let inserted_operation = *operation;
         match operation {
             Operation::Add(_, _) => { self.add_list.insert(inserted_operation, line_number); },
             Operation::Sub(_, _) => { self.sub_list.insert(inserted_operation, line_number); },
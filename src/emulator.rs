@@ -0,0 +1,422 @@
+use crate::assembler::{decode, MachineCodeInstruction};
+use crate::code_gen::{Fmt, OpCode, GLOBAL_POINTER_REGISTER, RETURN_ADDRESS_REGISTER};
+use crate::object_file::ObjectFile;
+use std::fmt;
+use std::io::{BufRead, Write};
+
+type Word = i32;
+type Address = usize;
+
+/// words of memory, the program is loaded at address 0 and the globals sit at the top
+pub const DEFAULT_MEMORY_SIZE: usize = 10_000;
+pub const DEFAULT_INSTRUCTION_LIMIT: usize = 10_000_000;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum EmulatorError {
+    IllegalInstruction { pc: Address, word: MachineCodeInstruction },
+    MemoryOutOfBounds { pc: Address, address: i64 },
+    DivisionByZero { pc: Address },
+    IndexOutOfBounds { pc: Address, index: Word, bound: Word },
+    InvalidInput(String),
+    Io(String),
+    InstructionLimitExceeded(usize),
+    /// the code and data of a program take more words than the memory has
+    ProgramTooLarge { words: usize, memory_size: usize },
+}
+
+impl fmt::Display for EmulatorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EmulatorError::IllegalInstruction { pc, word } => write!(f, "illegal instruction {:#010x} at {}", word, pc * 4),
+            EmulatorError::MemoryOutOfBounds { pc, address } => write!(f, "memory access at {} out of bounds at {}", address, pc * 4),
+            EmulatorError::DivisionByZero { pc } => write!(f, "division by zero at {}", pc * 4),
+            EmulatorError::IndexOutOfBounds { pc, index, bound } => write!(f, "index {} is not below {} at {}", index, bound, pc * 4),
+            EmulatorError::InvalidInput(input) => write!(f, "expected a number as input, got {:?}", input),
+            EmulatorError::Io(error) => write!(f, "{}", error),
+            EmulatorError::InstructionLimitExceeded(limit) => write!(f, "stopped after {} instructions", limit),
+            EmulatorError::ProgramTooLarge { words, memory_size } => write!(f, "a program of {} words does not fit in {} words of memory", words, memory_size),
+        }
+    }
+}
+
+// the fields of an instruction word, c already sign extended for F1
+struct Decoded {
+    op_code: OpCode,
+    fmt: Fmt,
+    a: usize,
+    b: usize,
+    c: Word,
+}
+
+// fields an instruction does not have read as 0, the way they are encoded
+fn decode_fields(word: MachineCodeInstruction) -> Option<Decoded> {
+    let instruction = decode(word)?;
+    let fmt = instruction.get_fmt();
+    let c = match fmt {
+        Fmt::F2 => instruction.get_c().unwrap_or(0) as Word,
+        Fmt::F1 | Fmt::F3 => instruction.get_const().unwrap_or(0) as Word,
+    };
+
+    Some(Decoded {
+        op_code: instruction.get_op_code(),
+        fmt,
+        a: instruction.get_a().unwrap_or(0) as usize,
+        b: instruction.get_b().unwrap_or(0) as usize,
+        c,
+    })
+}
+
+/// Runs DLX machine code the way the reference simulator does. Code and data share one
+/// word-addressed memory, addresses in registers are in bytes, and returning to address 0 halts.
+pub struct Emulator<R: BufRead, W: Write> {
+    registers: [Word; 32],
+    memory: Vec<Word>,
+    pc: Address,
+    reader: R,
+    writer: W,
+    instruction_limit: usize,
+    // how often each word of code was executed, when profiling
+    profile: Option<Vec<u64>>,
+}
+
+impl<R: BufRead, W: Write> Emulator<R, W> {
+    pub fn new(program: &[MachineCodeInstruction], reader: R, writer: W) -> Result<Self, EmulatorError> {
+        Self::with_memory_size(program, DEFAULT_MEMORY_SIZE, reader, writer)
+    }
+
+    pub fn with_memory_size(program: &[MachineCodeInstruction], memory_size: usize, reader: R, writer: W) -> Result<Self, EmulatorError> {
+        if program.len() > memory_size {
+            return Err(EmulatorError::ProgramTooLarge { words: program.len(), memory_size });
+        }
+
+        let mut memory = vec![0; memory_size];
+        for (address, word) in program.iter().enumerate() {
+            memory[address] = *word as Word;
+        }
+
+        let mut registers = [0; 32];
+        registers[GLOBAL_POINTER_REGISTER as usize] = (memory_size * 4) as Word;
+
+        Ok(Self {
+            registers,
+            memory,
+            pc: 0,
+            reader,
+            writer,
+            instruction_limit: DEFAULT_INSTRUCTION_LIMIT,
+            profile: None,
+        })
+    }
+
+    /// Loads an object file into default sized memory, with its data right below the global pointer.
+    pub fn from_object_file(object: &ObjectFile, reader: R, writer: W) -> Result<Self, EmulatorError> {
+        let words = object.code.len() + object.data.len();
+        if words > DEFAULT_MEMORY_SIZE {
+            return Err(EmulatorError::ProgramTooLarge { words, memory_size: DEFAULT_MEMORY_SIZE });
+        }
+        let mut emulator = Self::with_memory_size(&object.code, DEFAULT_MEMORY_SIZE, reader, writer)?;
+
+        let data_start = DEFAULT_MEMORY_SIZE - object.data.len();
+        emulator.memory[data_start..].copy_from_slice(&object.data);
+        emulator.pc = object.entry_point as Address / 4;
+        Ok(emulator)
+    }
+
+    pub fn set_instruction_limit(&mut self, instruction_limit: usize) {
+        self.instruction_limit = instruction_limit;
+    }
+
+    /// counts how often every instruction runs from now on, indexed by word address
+    pub fn enable_profiling(&mut self) {
+        self.profile = Some(vec![0; self.memory.len()]);
+    }
+
+    pub fn get_profile(&self) -> Option<&[u64]> {
+        self.profile.as_deref()
+    }
+
+    #[cfg(test)]
+    pub fn get_register(&self, register: usize) -> Word {
+        self.registers[register]
+    }
+
+    #[cfg(test)]
+    pub fn get_writer(&self) -> &W {
+        &self.writer
+    }
+
+    /// runs until the program returns to address 0, returning how many instructions were executed
+    pub fn run(&mut self) -> Result<usize, EmulatorError> {
+        let mut executed = 0;
+        while executed < self.instruction_limit {
+            executed += 1;
+            if !self.step()? {
+                self.writer.flush().map_err(|error| EmulatorError::Io(error.to_string()))?;
+                return Ok(executed);
+            }
+        }
+        Err(EmulatorError::InstructionLimitExceeded(self.instruction_limit))
+    }
+
+    /// executes one instruction, returns false once the program has halted
+    pub fn step(&mut self) -> Result<bool, EmulatorError> {
+        let pc = self.pc;
+        let word = *self.memory.get(pc).ok_or(EmulatorError::MemoryOutOfBounds { pc, address: (pc * 4) as i64 })? as MachineCodeInstruction;
+        let Decoded { op_code, fmt, a, b, c } = decode_fields(word).ok_or(EmulatorError::IllegalInstruction { pc, word })?;
+        if let Some(profile) = &mut self.profile {
+            profile[pc] += 1;
+        }
+
+        let rb = self.registers[b];
+        // F2 names a register in c where F1 holds the constant itself
+        let operand = if matches!(fmt, Fmt::F2) { self.registers[c as usize] } else { c };
+        let mut next_pc = pc + 1;
+
+        match op_code {
+            0 | 16 => self.set(a, rb.wrapping_add(operand)),
+            1 | 17 => self.set(a, rb.wrapping_sub(operand)),
+            2 | 18 => self.set(a, rb.wrapping_mul(operand)),
+            3 | 19 => {
+                if operand == 0 {
+                    return Err(EmulatorError::DivisionByZero { pc });
+                }
+                self.set(a, rb.wrapping_div(operand));
+            },
+            4 | 20 => {
+                if operand == 0 {
+                    return Err(EmulatorError::DivisionByZero { pc });
+                }
+                self.set(a, rb.wrapping_rem(operand));
+            },
+            5 | 21 => self.set(a, rb.cmp(&operand) as Word),
+            8 | 24 => self.set(a, rb | operand),
+            9 | 25 => self.set(a, rb & operand),
+            10 | 26 => self.set(a, rb & !operand),
+            11 | 27 => self.set(a, rb ^ operand),
+            // a negative shift amount shifts right, logically for LSH and arithmetically for ASH
+            12 | 28 => {
+                let shifted = if operand >= 0 { (rb as u32).wrapping_shl(operand as u32) } else { (rb as u32).wrapping_shr(operand.unsigned_abs()) };
+                self.set(a, shifted as Word);
+            },
+            13 | 29 => {
+                let shifted = if operand >= 0 { rb.wrapping_shl(operand as u32) } else { rb.wrapping_shr(operand.unsigned_abs()) };
+                self.set(a, shifted);
+            },
+            14 | 30 => {
+                let index = self.registers[a];
+                if index < 0 || index >= operand {
+                    return Err(EmulatorError::IndexOutOfBounds { pc, index, bound: operand });
+                }
+            },
+
+            32 | 33 => {
+                let address = self.get_address(pc, rb as i64 + operand as i64)?;
+                self.set(a, self.memory[address]);
+            },
+            34 => {
+                let address = self.get_address(pc, rb as i64)?;
+                self.set(a, self.memory[address]);
+                self.set(b, rb.wrapping_add(c));
+            },
+            36 | 37 => {
+                let address = self.get_address(pc, rb as i64 + operand as i64)?;
+                self.memory[address] = self.registers[a];
+            },
+            38 => {
+                let pushed_to = rb.wrapping_add(c);
+                let address = self.get_address(pc, pushed_to as i64)?;
+                self.set(b, pushed_to);
+                self.memory[address] = self.registers[a];
+            },
+
+            40..=45 => {
+                let value = self.registers[a];
+                let is_taken = match op_code {
+                    40 => value == 0,
+                    41 => value != 0,
+                    42 => value < 0,
+                    43 => value >= 0,
+                    44 => value <= 0,
+                    _ => value > 0,
+                };
+                if is_taken {
+                    next_pc = self.get_branch_target(pc, c)?;
+                }
+            },
+            46 => {
+                self.set(RETURN_ADDRESS_REGISTER as usize, ((pc + 1) * 4) as Word);
+                next_pc = self.get_branch_target(pc, c)?;
+            },
+            48 => {
+                self.set(RETURN_ADDRESS_REGISTER as usize, ((pc + 1) * 4) as Word);
+                next_pc = c as Address / 4;
+            },
+            49 => {
+                let return_address = self.registers[c as usize];
+                if return_address == 0 {
+                    return Ok(false);
+                }
+                next_pc = self.get_address(pc, return_address as i64)?;
+            },
+
+            50 => {
+                let value = self.read_number()?;
+                self.set(a, value);
+            },
+            51 => self.write(&format!("{} ", rb))?,
+            52 => self.write(&format!("{:x} ", rb))?,
+            53 => self.write("\n")?,
+            _ => return Err(EmulatorError::IllegalInstruction { pc, word }),
+        }
+
+        self.pc = next_pc;
+        Ok(true)
+    }
+
+    // R0 always reads as zero
+    fn set(&mut self, register: usize, value: Word) {
+        if register != 0 {
+            self.registers[register] = value;
+        }
+    }
+
+    // turns a byte address into the index of the word it falls in
+    fn get_address(&self, pc: Address, byte_address: i64) -> Result<Address, EmulatorError> {
+        if byte_address < 0 || byte_address / 4 >= self.memory.len() as i64 {
+            return Err(EmulatorError::MemoryOutOfBounds { pc, address: byte_address });
+        }
+        Ok(byte_address as Address / 4)
+    }
+
+    // branch offsets count words from the branch itself
+    fn get_branch_target(&self, pc: Address, offset: Word) -> Result<Address, EmulatorError> {
+        let target = pc as i64 + offset as i64;
+        self.get_address(pc, target * 4)
+    }
+
+    fn read_number(&mut self) -> Result<Word, EmulatorError> {
+        let mut line = String::new();
+        loop {
+            line.clear();
+            let read = self.reader.read_line(&mut line).map_err(|error| EmulatorError::Io(error.to_string()))?;
+            if read == 0 {
+                return Err(EmulatorError::InvalidInput(String::new()));
+            }
+            if !line.trim().is_empty() {
+                break;
+            }
+        }
+
+        line.trim().parse::<Word>().map_err(|_| EmulatorError::InvalidInput(line.trim().to_string()))
+    }
+
+    fn write(&mut self, output: &str) -> Result<(), EmulatorError> {
+        self.writer.write_all(output.as_bytes()).map_err(|error| EmulatorError::Io(error.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod emulator_tests {
+    use super::*;
+    use crate::assembler::get_machine_code_instructions;
+    use crate::code_gen::AssemblyInstruction;
+
+    fn run(assembly: Vec<AssemblyInstruction>, input: &str) -> (Result<usize, EmulatorError>, String) {
+        let program = get_machine_code_instructions(assembly).unwrap();
+        let mut emulator = Emulator::new(&program, input.as_bytes(), Vec::new()).unwrap();
+        let result = emulator.run();
+
+        (result, String::from_utf8(emulator.get_writer().clone()).unwrap())
+    }
+
+    #[test]
+    fn test_arithmetic_and_io() {
+        let (result, output) = run(vec![
+            AssemblyInstruction::RDD(1),
+            AssemblyInstruction::RDD(2),
+            AssemblyInstruction::MUL(3, 1, 2),
+            AssemblyInstruction::SUBI(3, 3, 50),
+            AssemblyInstruction::WRD(3),
+            AssemblyInstruction::ADDI(0, 0, 7),
+            AssemblyInstruction::WRD(0),
+            AssemblyInstruction::WRL,
+            AssemblyInstruction::RET(0),
+        ], "6\n-7\n");
+
+        // R0 ignores the write
+        assert_eq!(result, Ok(9));
+        assert_eq!(output, "-92 0 \n");
+    }
+
+    #[test]
+    fn test_branches() {
+        // counts down from 3, writing each value
+        let (result, output) = run(vec![
+            AssemblyInstruction::ADDI(1, 0, 3),
+            AssemblyInstruction::WRD(1),
+            AssemblyInstruction::SUBI(1, 1, 1),
+            AssemblyInstruction::CMPI(2, 1, 0),
+            AssemblyInstruction::BGT(2, -3),
+            AssemblyInstruction::RET(0),
+        ], "");
+
+        assert!(result.is_ok());
+        assert_eq!(output, "3 2 1 ");
+    }
+
+    #[test]
+    fn test_jsr_and_ret() {
+        let (result, output) = run(vec![
+            AssemblyInstruction::ADDI(1, 0, 20),
+            AssemblyInstruction::JSR(5 * 4),
+            AssemblyInstruction::WRD(27),
+            AssemblyInstruction::WRL,
+            AssemblyInstruction::RET(0),
+            // doubles R1 into R27 and returns
+            AssemblyInstruction::ADD(27, 1, 1),
+            AssemblyInstruction::RET(31),
+        ], "");
+
+        assert!(result.is_ok());
+        assert_eq!(output, "40 \n");
+    }
+
+    #[test]
+    fn test_globals_below_global_pointer() {
+        let (result, output) = run(vec![
+            AssemblyInstruction::ADDI(1, 0, 11),
+            AssemblyInstruction::STW(1, 30, -4),
+            AssemblyInstruction::LDW(2, 30, -4),
+            AssemblyInstruction::WRD(2),
+            AssemblyInstruction::RET(0),
+        ], "");
+
+        assert!(result.is_ok());
+        assert_eq!(output, "11 ");
+    }
+
+    #[test]
+    fn test_instruction_limit() {
+        let program = get_machine_code_instructions(vec![AssemblyInstruction::BEQ(0, 0)]).unwrap();
+        let mut emulator = Emulator::new(&program, "".as_bytes(), Vec::new()).unwrap();
+        emulator.set_instruction_limit(1000);
+
+        assert_eq!(emulator.run(), Err(EmulatorError::InstructionLimitExceeded(1000)));
+    }
+
+    #[test]
+    fn test_errors() {
+        let (result, _) = run(vec![AssemblyInstruction::DIV(1, 0, 0)], "");
+        assert_eq!(result, Err(EmulatorError::DivisionByZero { pc: 0 }));
+
+        let (result, _) = run(vec![AssemblyInstruction::LDW(1, 0, -4)], "");
+        assert_eq!(result, Err(EmulatorError::MemoryOutOfBounds { pc: 0, address: -4 }));
+
+        let (result, _) = run(vec![AssemblyInstruction::RDD(1)], "seven\n");
+        assert_eq!(result, Err(EmulatorError::InvalidInput("seven".to_string())));
+
+        let program = get_machine_code_instructions(vec![AssemblyInstruction::RET(0), AssemblyInstruction::RET(0), AssemblyInstruction::RET(0)]).unwrap();
+        let emulator = Emulator::with_memory_size(&program, 2, "".as_bytes(), Vec::new());
+        assert_eq!(emulator.err(), Some(EmulatorError::ProgramTooLarge { words: 3, memory_size: 2 }));
+    }
+}
@@ -1,21 +1,26 @@
-use crate::{basic_block::{BasicBlock, BasicBlockType, VariableType}, instruction::Operation};
-use std::collections::HashMap;
+use crate::{basic_block::{BasicBlock, BasicBlockType, VariableType}, instruction::{Instruction, Operation}, tokenizer::Span};
+use std::collections::{HashMap, HashSet};
 use petgraph::{
     graph::{DiGraph, NodeIndex},
-    Direction::Incoming,
+    Direction::{Incoming, Outgoing},
 };
 
-use crate::dot_viz::generate_dot_viz;
 
 #[derive(Debug, Clone)]
 pub struct Function {
     pub name: String,
     pub parameters: Vec<String>,
     pub is_void: bool,
+    // globals this function reaches through memory, mapped to their offset from the global pointer
+    pub globals: HashMap<String, isize>,
 
     pub bb_graph: DiGraph<BasicBlock, BasicBlockType>,
     pub curr_node: NodeIndex<u32>,
-    entry_node: NodeIndex<u32>
+    entry_node: NodeIndex<u32>,
+    // the header block of every while, with where the while is in the source
+    pub loop_headers: Vec<(Span, NodeIndex)>,
+    // the block and position every statement starts at, with where the statement is in the source
+    pub statement_starts: Vec<(Span, NodeIndex, usize)>,
 }
 
 impl Function {
@@ -30,10 +35,13 @@ impl Function {
             name,
             parameters: Vec::new(),
             is_void,
+            globals: HashMap::new(),
 
             bb_graph: bb_g,
             curr_node: entry_node,
-            entry_node
+            entry_node,
+            loop_headers: Vec::new(),
+            statement_starts: Vec::new(),
         }
     }
 
@@ -92,10 +100,6 @@ impl Function {
 
 
 
-    pub fn connect_to_conditional(&mut self, conditional_index: NodeIndex) {
-        self.add_edge(self.curr_node, conditional_index, BasicBlockType::Conditional);
-        self.curr_node = conditional_index;
-    }
 
     pub fn add_node_to_index(&mut self, node_index: NodeIndex, bb_type: BasicBlockType) -> NodeIndex<u32> {
         let bb = BasicBlock::new(bb_type);
@@ -103,7 +107,7 @@ impl Function {
         let parent_node_index = node_index;
         let parent_block = self.get_bb(&parent_node_index).unwrap().clone();
 
-        if !self.can_add_child(parent_node_index.clone()) {
+        if !self.can_add_child(parent_node_index) {
             panic!("Can no longer add any new children");
         }
 
@@ -131,11 +135,6 @@ impl Function {
         self.add_node_to_index(self.curr_node, bb_type)
     }
 
-    /// returns the parent of the current node
-    pub fn get_prev_index(&self) -> Option<NodeIndex> {
-        let mut parents_iter = self.bb_graph.neighbors_directed(self.curr_node, Incoming);
-        parents_iter.nth(0)
-    }
 
     pub fn get_prev_index_of_node(&self, node: NodeIndex) -> Option<NodeIndex> {
         let mut parents_iter = self.bb_graph.neighbors_directed(node, Incoming);
@@ -165,11 +164,23 @@ impl Function {
         (self.curr_node, phi_instructions)
     }
 
-    /// Join two blocks with one block as the target, propagating the variable table
-    pub fn join_with_target(&mut self, source_index: NodeIndex, target_index: NodeIndex) -> Vec<(Operation, String)> {
-        let (new_table, phi_instructions) = self.join_variable_tables(source_index, target_index);
-        self.get_bb_mut(&target_index).unwrap().variable_table = new_table;
-        phi_instructions
+    /// Removes a loop phi whose back edge carries the phi itself, so the variable keeps its value from before the loop.
+    /// Blocks created after the loop header are the only ones that can refer to the phi.
+    pub fn remove_trivial_phi(&mut self, header_index: NodeIndex, phi_line: isize, entry_value: VariableType) {
+        let header = self.get_bb_mut(&header_index).unwrap();
+        header.instructions.retain(|instruction| instruction.get_line_number() != phi_line);
+
+        for node_index in self.bb_graph.node_indices().filter(|node_index| *node_index >= header_index).collect::<Vec<_>>() {
+            let block = self.get_bb_mut(&node_index).unwrap();
+            for instruction in &mut block.instructions {
+                instruction.operation.replace_line(phi_line, entry_value.get_value());
+            }
+            for value in block.variable_table.values_mut() {
+                if *value == VariableType::Value(phi_line) {
+                    *value = entry_value;
+                }
+            }
+        }
     }
 
     // Helper function to join variable tables
@@ -183,7 +194,10 @@ impl Function {
         for (variable, left_value) in &left_parent_block.variable_table {
             let right_value = right_parent_block.variable_table.get(variable).unwrap();
             if left_value == right_value {
-                join_variable_table.insert(variable.clone(), left_value.clone());
+                join_variable_table.insert(variable.clone(), *left_value);
+            } else if *left_value == VariableType::InMemory || *right_value == VariableType::InMemory {
+                // memory is always up to date, so the join just reloads the global
+                join_variable_table.insert(variable.clone(), VariableType::InMemory);
             } else {
                 phi_instructions.push((Operation::Phi(left_value.get_value(), right_value.get_value()), variable.clone()));
             }
@@ -200,7 +214,7 @@ impl Function {
         // Check if there is exactly one outgoing edge
         if let Some(target_index) = neighbors.next() {
             if neighbors.next().is_none() {
-                return Some(target_index);
+                Some(target_index)
             } else {
                 panic!("Block has more than one outgoing edge");
             }
@@ -220,6 +234,102 @@ impl Function {
 
 
 
+    /// edges that leave a block with several successors for a block with several predecessors,
+    /// nothing can be placed on them without running on the other paths through either block
+    pub fn get_critical_edges(&self) -> Vec<(NodeIndex, NodeIndex)> {
+        self.bb_graph.edge_indices()
+            .filter_map(|edge| self.bb_graph.edge_endpoints(edge))
+            .filter(|(source, target)| {
+                self.bb_graph.neighbors_directed(*source, Outgoing).count() > 1
+                    && self.bb_graph.neighbors_directed(*target, Incoming).count() > 1
+            })
+            .collect()
+    }
+
+    /// Puts a new block on the edge, which branches on to the target with a line from `next_line`.
+    /// The source branches to the new block instead, and the new edges take the place of the old
+    /// one so the phis of the target keep their operands in the order of its incoming edges.
+    /// The block is a branch block when the source branched to the target, a fall-through block
+    /// when it fell through, or of the type of the edge when that says how the target is entered.
+    /// The source enters the new block by an edge of the block's type, the edge on to the target
+    /// keeps the type of the old one. Returns None when there is no such edge.
+    pub fn split_edge(&mut self, from_index: NodeIndex, to_index: NodeIndex, next_line: &mut isize) -> Option<NodeIndex> {
+        let edge = self.bb_graph.find_edge(from_index, to_index)?;
+        let edge_type = self.bb_graph[edge];
+
+        let source = self.get_bb(&from_index).unwrap();
+        // whether the branch ending the source names the target
+        let branches_to_target = source.instructions.last().is_some_and(|instruction| {
+            let mut operation = instruction.operation;
+            operation.map_block(|block| if block == to_index.index() as isize { -1 } else { block });
+            operation != instruction.operation
+        });
+        let block_type = match edge_type {
+            BasicBlockType::FallThrough | BasicBlockType::Branch | BasicBlockType::Follow => edge_type,
+            _ if branches_to_target => BasicBlockType::Branch,
+            _ => BasicBlockType::FallThrough,
+        };
+
+        let mut block = BasicBlock::new(block_type);
+        block.variable_table = source.variable_table.clone();
+        block.dominator_table = source.dominator_table.clone();
+        block.dominator_table.dominated_by = from_index;
+        block.add_instruction(Instruction::new(get_new_line(next_line), Operation::Bra(to_index.index() as isize)));
+        let split_index = self.bb_graph.add_node(block);
+        self.bb_graph[split_index].id = split_index;
+
+        if let Some(instruction) = self.get_bb_mut(&from_index).unwrap().instructions.last_mut() {
+            instruction.operation.map_block(|block| if block == to_index.index() as isize { split_index.index() as isize } else { block });
+        }
+
+        let edges: Vec<(NodeIndex, NodeIndex, BasicBlockType)> = self.bb_graph.edge_indices()
+            .map(|edge| {
+                let (source, target) = self.bb_graph.edge_endpoints(edge).unwrap();
+                (source, target, self.bb_graph[edge])
+            })
+            .collect();
+        self.bb_graph.clear_edges();
+        for (source, target, weight) in edges {
+            if (source, target) == (from_index, to_index) {
+                self.add_edge(from_index, split_index, block_type);
+                self.add_edge(split_index, to_index, weight);
+            } else {
+                self.add_edge(source, target, weight);
+            }
+        }
+
+        Some(split_index)
+    }
+
+    /// Splits every critical edge, see [`split_edge`](#method.split_edge). Returns the new blocks.
+    pub fn split_critical_edges(&mut self, next_line: &mut isize) -> Vec<NodeIndex> {
+        self.get_critical_edges()
+            .into_iter()
+            .filter_map(|(from_index, to_index)| self.split_edge(from_index, to_index, next_line))
+            .collect()
+    }
+
+    /// checks that every path from the entry block reaches a return
+    pub fn returns_on_all_paths(&self) -> bool {
+        let mut visited = HashSet::<NodeIndex>::new();
+        let mut stack = vec![self.entry_node];
+
+        while let Some(node) = stack.pop() {
+            if !visited.insert(node) || self.bb_graph[node].ends_with_return() {
+                continue;
+            }
+
+            let mut successors = self.bb_graph.neighbors_directed(node, Outgoing).peekable();
+            if successors.peek().is_none() {
+                // fell off the end of the function without returning
+                return false;
+            }
+            stack.extend(successors);
+        }
+
+        true
+    }
+
     /// validates whether or not a child can be added to an instnace of a node index
     fn can_add_child(&self, possible_parent: NodeIndex) -> bool {
         let parent_bb = self.bb_graph.node_weight(possible_parent);
@@ -236,16 +346,52 @@ impl Function {
         false
     }
 
-    ///wrapper function for can_add_child()
-    /// wrapper function for [`can_add_child`](#method.can_add_child)
-    fn validate_addition(&self, possible_parent_option: Option<NodeIndex<u32>>) -> bool {
-        if possible_parent_option == None {
-            return false;
+
+
+}
+
+/// The block and position of the instruction at a line, None for constants and removed lines.
+pub fn find_line(g: &DiGraph<BasicBlock, BasicBlockType>, line: isize) -> Option<(NodeIndex, usize)> {
+    g.node_indices().find_map(|block| {
+        g[block].instructions.iter().position(|instruction| instruction.get_line_number() == line).map(|position| (block, position))
+    })
+}
+
+pub fn get_operation(g: &DiGraph<BasicBlock, BasicBlockType>, line: isize) -> Option<Operation> {
+    find_line(g, line).map(|(block, position)| g[block].instructions[position].operation)
+}
+
+/// Makes every instruction that reads `old_line` read `new_line` instead.
+pub fn replace_uses(g: &mut DiGraph<BasicBlock, BasicBlockType>, old_line: isize, new_line: isize) {
+    for block in g.node_weights_mut() {
+        for instruction in &mut block.instructions {
+            instruction.operation.replace_line(old_line, new_line);
         }
-        return self.can_add_child(possible_parent_option.unwrap());
     }
+}
 
+/// Takes an instruction out of its block. A block keeps an empty instruction so it still has a
+/// first line to be labelled by, and the entry block keeps its first line as calls name the
+/// function by it.
+pub fn remove_instruction(g: &mut DiGraph<BasicBlock, BasicBlockType>, block: NodeIndex, position: usize) {
+    let instructions = &mut g[block].instructions;
+    if instructions.len() == 1 || (block.index() == 0 && position == 0) {
+        instructions[position].operation = Operation::Empty;
+    } else {
+        instructions.remove(position);
+    }
+}
 
+/// The first line after every line of the graph.
+pub fn get_next_line(g: &DiGraph<BasicBlock, BasicBlockType>) -> isize {
+    g.node_weights().flat_map(|block| &block.instructions).map(|instruction| instruction.get_line_number()).max().unwrap_or(0) + 1
+}
+
+/// Hands out the line `next_line` is at and moves it on.
+pub fn get_new_line(next_line: &mut isize) -> isize {
+    let line = *next_line;
+    *next_line += 1;
+    line
 }
 
 // used for testing purposes
@@ -257,16 +403,6 @@ fn iter_len(x: &petgraph::graph::Neighbors<BasicBlockType, u32>) -> usize {
     i
 }
 
-// used for testing purposes
-fn in_iter(neighbor_iter: &petgraph::graph::Neighbors<BasicBlockType, u32>, needle: &NodeIndex<u32>) -> bool {
-    let l = needle.clone();
-    for neighbor in neighbor_iter.clone() {
-        if neighbor == l {
-            return true;
-        }
-    }
-    false
-}
 
 
 // #[cfg(test)]
@@ -305,3 +441,99 @@ fn in_iter(neighbor_iter: &petgraph::graph::Neighbors<BasicBlockType, u32>, need
 //     }
 //
 // }
+
+#[cfg(test)]
+mod function_tests {
+    use super::*;
+    use crate::test_helpers::interpret;
+    use crate::parser::Parser;
+    use crate::program::Program;
+    use petgraph::visit::EdgeRef;
+
+    // if the input is above 2 it is incremented, the entry branches straight to the join otherwise
+    fn get_program() -> Program {
+        let mut program = Program::new();
+        program.add_function("main", true);
+        let function = program.get_fn_mut("main");
+        let entry = function.get_entry_node();
+        let mut add_block = |block_type| {
+            let block = function.bb_graph.add_node(BasicBlock::new(block_type));
+            function.bb_graph[block].id = block;
+            block
+        };
+        let then_block = add_block(BasicBlockType::FallThrough);
+        let join = add_block(BasicBlockType::Join);
+
+        for (block, line, operation) in [
+            (entry, 1, Operation::Read),
+            (entry, 2, Operation::Cmp(1, -2)),
+            (entry, 3, Operation::Ble(2, 2)),
+            (then_block, 4, Operation::Add(1, -1)),
+            (then_block, 5, Operation::Bra(2)),
+            (join, 6, Operation::Phi(1, 4)),
+            (join, 7, Operation::Write(6)),
+            (join, 8, Operation::End),
+        ] {
+            function.get_bb_mut(&block).unwrap().add_instruction(Instruction::new(line, operation));
+        }
+        function.add_edge(entry, then_block, BasicBlockType::FallThrough);
+        function.add_edge(entry, join, BasicBlockType::Branch);
+        function.add_edge(then_block, join, BasicBlockType::FallThrough);
+        program
+    }
+
+    #[test]
+    fn test_split_critical_edges() {
+        let mut program = get_program();
+        let expected: Vec<String> = ["1", "5"].iter().map(|input| interpret(&program, input)).collect();
+
+        let function = program.get_fn_mut("main");
+        assert_eq!(function.get_critical_edges(), vec![(NodeIndex::new(0), NodeIndex::new(2))]);
+        let mut next_line = 9;
+        let split = function.split_critical_edges(&mut next_line);
+        assert_eq!(split, vec![NodeIndex::new(3)]);
+        assert_eq!(next_line, 10);
+        assert!(function.get_critical_edges().is_empty());
+
+        let g = &function.bb_graph;
+        assert_eq!(g[split[0]].block_type, BasicBlockType::Branch);
+        assert_eq!(g[split[0]].instructions, vec![Instruction::new(9, Operation::Bra(2))]);
+        assert_eq!(g[NodeIndex::new(0)].instructions.last().unwrap().operation, Operation::Ble(2, 3));
+        // the new block comes into the join where the entry did, so the phi still reads 1 from it
+        let mut incoming: Vec<_> = g.edges_directed(NodeIndex::new(2), Incoming).collect();
+        incoming.sort_by_key(|edge| edge.id());
+        assert_eq!(incoming.iter().map(|edge| edge.source()).collect::<Vec<_>>(), vec![NodeIndex::new(3), NodeIndex::new(1)]);
+        assert_eq!(g[NodeIndex::new(2)].instructions[0].operation, Operation::Phi(1, 4));
+
+        assert_eq!(["1", "5"].iter().map(|input| interpret(&program, input)).collect::<Vec<_>>(), expected);
+        assert_eq!(expected, vec!["1 ", "6 "]);
+    }
+
+    #[test]
+    fn test_split_edge_types() {
+        let mut program = get_program();
+        let function = program.get_fn_mut("main");
+        let (entry, join) = (NodeIndex::new(0), NodeIndex::new(2));
+        let edge = function.bb_graph.find_edge(entry, join).unwrap();
+        function.bb_graph[edge] = BasicBlockType::Join;
+
+        let mut next_line = 9;
+        let split = function.split_edge(entry, join, &mut next_line).unwrap();
+        let g = &function.bb_graph;
+        // the entry branches to the new block, which goes on to the join the way the entry did
+        assert_eq!(g[split].block_type, BasicBlockType::Branch);
+        assert_eq!(g[g.find_edge(entry, split).unwrap()], BasicBlockType::Branch);
+        assert_eq!(g[g.find_edge(split, join).unwrap()], BasicBlockType::Join);
+
+        assert_eq!(function.split_edge(join, entry, &mut next_line), None);
+        assert_eq!(next_line, 10);
+    }
+
+    #[test]
+    fn test_parsed_graphs_have_no_critical_edges() {
+        let input = "main var i, a; { let i <- 0; let a <- call InputNum(); while i < a do if i > 2 then let a <- a - 1 fi; let i <- i + 1 od; call OutputNum(i) }.";
+        let mut parser = Parser::new(input.to_string());
+        parser.parse_computation();
+        assert!(parser.internal_program.get_fn("main").get_critical_edges().is_empty());
+    }
+}
@@ -0,0 +1,374 @@
+use std::collections::{HashMap, HashSet};
+
+use petgraph::graph::NodeIndex;
+use petgraph::visit::EdgeRef;
+
+use crate::basic_block::{BasicBlock, BasicBlockType};
+use crate::function::{get_new_line, Function};
+use crate::instruction::{Instruction, Operation};
+use crate::live_analysis::BasicBlockGraph;
+use crate::program::Program;
+
+type LineNumber = isize;
+
+/// Which calls the inliner replaces by the body of the callee.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InlineConfig {
+    /// callees with more instructions than this stay calls
+    pub size_threshold: usize,
+    /// how often a function that can call itself may be inlined into one caller, 0 never inlines it
+    pub recursion_depth: usize,
+}
+
+impl Default for InlineConfig {
+    fn default() -> Self {
+        Self { size_threshold: 20, recursion_depth: 0 }
+    }
+}
+
+// a jsr or call and the arguments its setPars pass
+struct CallSite {
+    block: NodeIndex,
+    // where the setPars for the call start, the call follows them
+    start: usize,
+    position: usize,
+    callee: String,
+    arguments: Vec<LineNumber>,
+}
+
+fn get_parameter_index(operation: &Operation) -> Option<usize> {
+    match operation {
+        Operation::GetPar1 => Some(0),
+        Operation::GetPar2 => Some(1),
+        Operation::GetPar3 => Some(2),
+        _ => None,
+    }
+}
+
+fn get_argument_index(operation: &Operation) -> Option<usize> {
+    match operation {
+        Operation::SetPar1(_) => Some(0),
+        Operation::SetPar2(_) => Some(1),
+        Operation::SetPar3(_) => Some(2),
+        _ => None,
+    }
+}
+
+fn get_call_target(operation: &Operation) -> Option<LineNumber> {
+    match operation {
+        Operation::Jsr(line) | Operation::Call(line) => Some(*line),
+        _ => None,
+    }
+}
+
+fn remap_branch(operation: Operation, blocks: &HashMap<NodeIndex, NodeIndex>) -> Operation {
+    let remap = |block: isize| blocks[&NodeIndex::new(block as usize)].index() as isize;
+    match operation {
+        Operation::Bra(block) => Operation::Bra(remap(block)),
+        Operation::Bne(value, block) => Operation::Bne(value, remap(block)),
+        Operation::Beq(value, block) => Operation::Beq(value, remap(block)),
+        Operation::Ble(value, block) => Operation::Ble(value, remap(block)),
+        Operation::Blt(value, block) => Operation::Blt(value, remap(block)),
+        Operation::Bge(value, block) => Operation::Bge(value, remap(block)),
+        Operation::Bgt(value, block) => Operation::Bgt(value, remap(block)),
+        _ => operation,
+    }
+}
+
+fn get_size(function: &Function) -> usize {
+    function.bb_graph.node_weights()
+        .flat_map(|block| &block.instructions)
+        .filter(|instruction| instruction.operation != Operation::Empty)
+        .count()
+}
+
+fn get_return_count(function: &Function) -> usize {
+    function.bb_graph.node_weights()
+        .flat_map(|block| &block.instructions)
+        .filter(|instruction| matches!(instruction.operation, Operation::Ret(_)))
+        .count()
+}
+
+fn find_call_sites(g: &BasicBlockGraph, entry_lines: &HashMap<LineNumber, String>) -> Vec<CallSite> {
+    let mut call_sites = Vec::new();
+    for block in g.node_indices() {
+        let instructions = &g[block].instructions;
+        for (position, instruction) in instructions.iter().enumerate() {
+            let Some(callee) = get_call_target(&instruction.operation).and_then(|line| entry_lines.get(&line)) else {
+                continue;
+            };
+            // the setPars of a call come right before it
+            let start = instructions[..position].iter().rposition(|instruction| get_argument_index(&instruction.operation).is_none()).map_or(0, |position| position + 1);
+            let mut arguments = Vec::new();
+            for instruction in &instructions[start..position] {
+                let index = get_argument_index(&instruction.operation).unwrap();
+                arguments.resize(arguments.len().max(index + 1), 0);
+                arguments[index] = instruction.operation.get_lines()[0];
+            }
+            call_sites.push(CallSite { block, start, position, callee: callee.clone(), arguments });
+        }
+    }
+    call_sites
+}
+
+// the functions every function calls directly
+fn get_call_graph(program: &Program, entry_lines: &HashMap<LineNumber, String>) -> HashMap<String, HashSet<String>> {
+    program.functions.iter().map(|(name, function)| {
+        let callees = function.bb_graph.node_weights()
+            .flat_map(|block| &block.instructions)
+            .filter_map(|instruction| get_call_target(&instruction.operation).and_then(|line| entry_lines.get(&line)).cloned())
+            .collect();
+        (name.clone(), callees)
+    }).collect()
+}
+
+fn is_recursive(call_graph: &HashMap<String, HashSet<String>>, function_name: &str) -> bool {
+    let mut visited = HashSet::new();
+    let mut work: Vec<&String> = call_graph[function_name].iter().collect();
+    while let Some(callee) = work.pop() {
+        if callee == function_name {
+            return true;
+        }
+        if visited.insert(callee) {
+            work.extend(&call_graph[callee]);
+        }
+    }
+    false
+}
+
+struct Inliner<'a> {
+    next_line: &'a mut LineNumber,
+}
+
+impl Inliner<'_> {
+    // The block with the call keeps what comes before the setPars and falls into a copy of the
+    // callee, whose returns branch to a new block holding the rest. The graph is rebuilt so the
+    // outgoing edges of the split block can move to the continuation without changing their
+    // order at the blocks they lead to, which is what the phis there are ordered by.
+    fn inline(&mut self, g: &BasicBlockGraph, call_site: &CallSite, callee: &Function) -> BasicBlockGraph {
+        let split = call_site.block;
+        let instructions = &g[split].instructions;
+        let call = &instructions[call_site.position];
+
+        let mut inlined = BasicBlockGraph::new();
+        for block in g.node_weights() {
+            inlined.add_node(block.clone());
+        }
+
+        let mut before = instructions[..call_site.start].to_vec();
+        // the first line labels the block, and names the function when this is its entry
+        if before.is_empty() {
+            before.push(Instruction::new(instructions[0].get_line_number(), Operation::Empty));
+        }
+        inlined[split].instructions = before;
+
+        let mut continuation = BasicBlock::new(g[split].block_type);
+        continuation.instructions = instructions[call_site.position + 1..].to_vec();
+        if continuation.instructions.is_empty() {
+            continuation.instructions.push(Instruction::new(get_new_line(self.next_line), Operation::Empty));
+        }
+        let continuation = inlined.add_node(continuation);
+        inlined[continuation].id = continuation;
+
+        for edge in g.edge_references() {
+            let source = if edge.source() == split { continuation } else { edge.source() };
+            inlined.add_edge(source, edge.target(), *edge.weight());
+        }
+
+        // parameters become the arguments, every other line gets a new number
+        let mut lines = HashMap::<LineNumber, LineNumber>::new();
+        for instruction in callee.bb_graph.node_weights().flat_map(|block| &block.instructions) {
+            let line = match get_parameter_index(&instruction.operation) {
+                Some(index) => call_site.arguments[index],
+                None => get_new_line(self.next_line),
+            };
+            lines.insert(instruction.get_line_number(), line);
+        }
+        let blocks: HashMap<NodeIndex, NodeIndex> = callee.bb_graph.node_indices().map(|block| {
+            // the entry of the callee is entered from the block with the call
+            let block_type = match callee.bb_graph[block].block_type {
+                BasicBlockType::Entry => BasicBlockType::FallThrough,
+                block_type => block_type,
+            };
+            let copy = inlined.add_node(BasicBlock::new(block_type));
+            inlined[copy].id = copy;
+            (block, copy)
+        }).collect();
+
+        let mut returns = Vec::new();
+        for block in callee.bb_graph.node_indices() {
+            let copy = blocks[&block];
+            for instruction in &callee.bb_graph[block].instructions {
+                if get_parameter_index(&instruction.operation).is_some() {
+                    continue;
+                }
+                let mut operation = remap_branch(instruction.operation, &blocks);
+                // constants keep their lines
+                operation.map_lines(|line| lines.get(&line).copied().unwrap_or(line));
+                let line = lines[&instruction.get_line_number()];
+
+                // nothing after a return runs
+                if let Operation::Ret(value) = operation {
+                    returns.push((copy, value));
+                    inlined[copy].instructions.push(Instruction::new(line, Operation::Bra(continuation.index() as isize)));
+                    break;
+                }
+                inlined[copy].instructions.push(Instruction::new(line, operation));
+            }
+            // an entry block that only took parameters
+            if inlined[copy].instructions.is_empty() {
+                let line = get_new_line(self.next_line);
+                inlined[copy].instructions.push(Instruction::new(line, Operation::Empty));
+            }
+        }
+
+        // a block that returns only goes on to the continuation
+        for edge in callee.bb_graph.edge_references().filter(|edge| !returns.iter().any(|(block, _)| *block == blocks[&edge.source()])) {
+            inlined.add_edge(blocks[&edge.source()], blocks[&edge.target()], *edge.weight());
+        }
+        inlined.add_edge(split, blocks[&callee.get_entry_node()], BasicBlockType::FallThrough);
+        for (block, _) in &returns {
+            inlined.add_edge(*block, continuation, BasicBlockType::Join);
+        }
+
+        // the continuation ends the way the split block did, which now falls into the callee
+        if matches!(g[split].block_type, BasicBlockType::Conditional | BasicBlockType::Exit) {
+            inlined[split].block_type = BasicBlockType::FallThrough;
+        } else {
+            inlined[continuation].block_type = if returns.len() > 1 { BasicBlockType::Join } else { BasicBlockType::FallThrough };
+        }
+
+        // the value of a call is the value returned, a phi when it comes from either of two returns
+        if let Operation::Call(_) = call.operation {
+            let value = match returns.as_slice() {
+                [(_, value)] => *value,
+                [(_, left), (_, right)] => {
+                    let phi = get_new_line(self.next_line);
+                    inlined[continuation].instructions.insert(0, Instruction::new(phi, Operation::Phi(*left, *right)));
+                    phi
+                },
+                _ => unreachable!("calls are only inlined into a callee with one or two returns"),
+            };
+            for block in inlined.node_weights_mut() {
+                for instruction in &mut block.instructions {
+                    instruction.operation.replace_line(call.get_line_number(), value);
+                }
+            }
+        }
+
+        inlined
+    }
+}
+
+/// Replaces calls to small functions by a copy of their body. The arguments of the setPars
+/// take the place of the getPars, the lines of the copy are numbered anew and the value of a
+/// call becomes the returned value, through a phi in the block after the call when there are
+/// two returns. Callees with more than two returns are only inlined at a jsr. Returns how many
+/// calls were inlined.
+pub fn inline_functions(program: &mut Program, config: &InlineConfig) -> usize {
+    // calls name a function by the first line of its entry block
+    let mut entry_lines = HashMap::<LineNumber, String>::new();
+    for (name, function) in &program.functions {
+        if let Some(instruction) = function.get_bb(&function.get_entry_node()).and_then(|block| block.instructions.first()) {
+            entry_lines.insert(instruction.get_line_number(), name.clone());
+        }
+    }
+    let call_graph = get_call_graph(program, &entry_lines);
+
+    // every copy is made from the functions as they were before inlining
+    let callees = program.functions.clone();
+    let mut next_line = program.get_next_line();
+    let mut inliner = Inliner { next_line: &mut next_line };
+
+    let mut function_names: Vec<String> = program.functions.keys().cloned().collect();
+    function_names.sort();
+
+    let mut inlined_count = 0;
+    for function_name in function_names {
+        let mut recursive_inlined = HashMap::<String, usize>::new();
+        loop {
+            let g = &program.get_fn(&function_name).bb_graph;
+            let call_site = find_call_sites(g, &entry_lines).into_iter().find(|call_site| {
+                let callee = &callees[&call_site.callee];
+                let is_call = matches!(g[call_site.block].instructions[call_site.position].operation, Operation::Call(_));
+                let parameters_passed = callee.bb_graph.node_weights()
+                    .flat_map(|block| &block.instructions)
+                    .filter_map(|instruction| get_parameter_index(&instruction.operation))
+                    .all(|index| index < call_site.arguments.len());
+                let recursion_allowed = !is_recursive(&call_graph, &call_site.callee)
+                    || recursive_inlined.get(&call_site.callee).copied().unwrap_or(0) < config.recursion_depth;
+
+                get_size(callee) <= config.size_threshold && parameters_passed && recursion_allowed && (!is_call || get_return_count(callee) <= 2)
+            });
+            let Some(call_site) = call_site else {
+                break;
+            };
+
+            if is_recursive(&call_graph, &call_site.callee) {
+                *recursive_inlined.entry(call_site.callee.clone()).or_default() += 1;
+            }
+            let inlined = inliner.inline(g, &call_site, &callees[&call_site.callee]);
+            program.get_fn_mut(&function_name).bb_graph = inlined;
+            inlined_count += 1;
+        }
+    }
+    inlined_count
+}
+
+#[cfg(test)]
+mod inliner_tests {
+    use super::*;
+    use crate::test_helpers::{get_program, interpret};
+
+    fn count_calls(program: &Program, function_name: &str) -> usize {
+        program.get_fn(function_name).bb_graph.node_weights()
+            .flat_map(|block| &block.instructions)
+            .filter(|instruction| get_call_target(&instruction.operation).is_some())
+            .count()
+    }
+
+    const PROGRAM: &str = "
+main var g;
+function max(a, b); { if a > b then return a else return b fi };
+void function show(x); { call OutputNum(x); let g <- x };
+{
+    call show(call max(call InputNum(), 3));
+    call OutputNum(g + call max(g, 10))
+}.";
+
+    #[test]
+    fn test_inline_calls() {
+        let mut program = get_program(PROGRAM);
+        let expected: Vec<String> = ["1", "7", "12"].iter().map(|input| interpret(&program, input)).collect();
+
+        assert_eq!(inline_functions(&mut program, &InlineConfig::default()), 3);
+        assert_eq!(count_calls(&program, "main"), 0);
+        assert!(program.get_fn("main").bb_graph.node_weights().flat_map(|block| &block.instructions).any(|instruction| matches!(instruction.operation, Operation::Phi(_, _))));
+        assert_eq!(["1", "7", "12"].iter().map(|input| interpret(&program, input)).collect::<Vec<_>>(), expected);
+        assert_eq!(expected, vec!["3 13 ", "7 17 ", "12 24 "]);
+    }
+
+    #[test]
+    fn test_size_threshold() {
+        let mut program = get_program(PROGRAM);
+        let config = InlineConfig { size_threshold: 4, ..InlineConfig::default() };
+
+        // show is small enough, max is not
+        assert_eq!(inline_functions(&mut program, &config), 1);
+        assert_eq!(count_calls(&program, "main"), 2);
+        assert_eq!(interpret(&program, "7"), "7 17 ");
+    }
+
+    #[test]
+    fn test_recursion_guard() {
+        let input = "main function sum(n); { if n > 0 then return n + call sum(n - 1) fi; return 0 }; { call OutputNum(call sum(call InputNum())) }.";
+        let mut program = get_program(input);
+        assert_eq!(inline_functions(&mut program, &InlineConfig::default()), 0);
+
+        let config = InlineConfig { recursion_depth: 2, ..InlineConfig::default() };
+        let inlined = inline_functions(&mut program, &config);
+        assert_eq!(inlined, 4);
+        assert_eq!(count_calls(&program, "main"), 1);
+        assert_eq!(interpret(&program, "5"), "15 ");
+    }
+}
@@ -1,8 +1,10 @@
+use std::cmp::Ordering;
 use std::fmt;
 
 type LineNumber = isize; 
 type FunctionNumber = isize; 
 type BasicBlockNumber = isize; 
+type GlobalOffset = isize; 
 
 #[derive(Clone, PartialEq, Eq, Hash, Copy)]
 pub enum Operation {
@@ -21,6 +23,7 @@ pub enum Operation {
     Bge(LineNumber, BasicBlockNumber),
     Bgt(LineNumber, BasicBlockNumber),
     Jsr(FunctionNumber),
+    Call(FunctionNumber),
     Ret(LineNumber),
     GetPar1,
     GetPar2,
@@ -31,6 +34,8 @@ pub enum Operation {
     Read,
     Write(LineNumber),
     WriteNL,
+    Load(GlobalOffset),
+    Store(LineNumber, GlobalOffset),
     Empty,
     End,
 }
@@ -53,6 +58,7 @@ impl fmt::Debug for Operation {
             Operation::Bge(value1, value2) => write!(f, "bge ({:?}) (BB{})", value1, value2),
             Operation::Bgt(value1, value2) => write!(f, "bgt ({:?}) (BB{})", value1, value2),
             Operation::Jsr(value1) => write!(f, "jsr ({})", value1),
+            Operation::Call(value1) => write!(f, "call ({})", value1),
             Operation::Ret(value1) => write!(f, "ret ({})", value1),
             Operation::GetPar1 => write!(f, "getPar1"),
             Operation::GetPar2 => write!(f, "getPar2"),
@@ -63,9 +69,10 @@ impl fmt::Debug for Operation {
             Operation::Read => write!(f, "read"),
             Operation::Write(value1) => write!(f, "write ({})", value1),
             Operation::WriteNL => write!(f, "writeNL"),
+            Operation::Load(value1) => write!(f, "load (GP{:+})", value1),
+            Operation::Store(value1, value2) => write!(f, "store ({}) (GP{:+})", value1, value2),
             Operation::Empty => write!(f, "<empty>"),
             Operation::End => write!(f, "End"),
-            _ => unreachable!("No other operations exists."),
         }
     }
 }
@@ -84,8 +91,10 @@ impl Operation {
                 v.push(r); 
             },
             Operation::SetPar1(l) |
+            Operation::SetPar2(l) |
             Operation::SetPar3(l) |
-            Operation::SetPar3(l) |
+            Operation::Ret(l) |
+            Operation::Store(l, _) |
             Operation::Write(l) => {
                 v.push(l);
             }
@@ -96,7 +105,108 @@ impl Operation {
         v
     }
 
-    
+    // whether any operand refers to the given line
+    pub fn uses_line(&self, line: isize) -> bool {
+        let mut replaced = *self;
+        replaced.replace_line(line, isize::MIN);
+        replaced != *self
+    }
+
+    // rewrites every operand that refers to the given line
+    pub fn replace_line(&mut self, old_line: isize, new_line: isize) {
+        self.map_lines(|line| if line == old_line { new_line } else { line });
+    }
+
+    // rewrites the block a branch goes to
+    pub fn map_block(&mut self, map: impl Fn(isize) -> isize) {
+        match self {
+            Operation::Bra(block) |
+            Operation::Bne(_, block) |
+            Operation::Beq(_, block) |
+            Operation::Ble(_, block) |
+            Operation::Blt(_, block) |
+            Operation::Bge(_, block) |
+            Operation::Bgt(_, block) => *block = map(*block),
+            _ => {}
+        }
+    }
+
+    // rewrites every line operand at once, so a line that is renamed to another is not renamed again
+    pub fn map_lines(&mut self, map: impl Fn(isize) -> isize) {
+        let replace = |line: &mut isize| *line = map(*line);
+        match self {
+            Operation::Add(l, r) |
+            Operation::Sub(l, r) |
+            Operation::Mul(l, r) |
+            Operation::Div(l, r) |
+            Operation::Cmp(l, r) |
+            Operation::Phi(l, r) => {
+                replace(l);
+                replace(r);
+            },
+            Operation::Bne(l, _) |
+            Operation::Beq(l, _) |
+            Operation::Ble(l, _) |
+            Operation::Blt(l, _) |
+            Operation::Bge(l, _) |
+            Operation::Bgt(l, _) |
+            Operation::SetPar1(l) |
+            Operation::SetPar2(l) |
+            Operation::SetPar3(l) |
+            Operation::Ret(l) |
+            Operation::Store(l, _) |
+            Operation::Write(l) => replace(l),
+            _ => {}
+        }
+    }
+
+    // the block a branch goes to, None for anything that is not a branch
+    pub fn get_branch_target(&self) -> Option<isize> {
+        match *self {
+            Operation::Bra(block) |
+            Operation::Bne(_, block) |
+            Operation::Beq(_, block) |
+            Operation::Ble(_, block) |
+            Operation::Blt(_, block) |
+            Operation::Bge(_, block) |
+            Operation::Bgt(_, block) => Some(block),
+            _ => None,
+        }
+    }
+
+    pub fn is_branch(&self) -> bool {
+        self.get_branch_target().is_some()
+    }
+
+    // the comparison a conditional branch reads, None for anything else
+    pub fn get_branch_comparison(&self) -> Option<isize> {
+        match *self {
+            Operation::Bne(comparison, _) |
+            Operation::Beq(comparison, _) |
+            Operation::Ble(comparison, _) |
+            Operation::Blt(comparison, _) |
+            Operation::Bge(comparison, _) |
+            Operation::Bgt(comparison, _) => Some(comparison),
+            _ => None,
+        }
+    }
+
+    pub fn is_conditional_branch(&self) -> bool {
+        self.get_branch_comparison().is_some()
+    }
+
+    // whether a conditional branch on a comparison with this outcome is taken, None for anything else
+    pub fn is_taken(&self, ordering: Ordering) -> Option<bool> {
+        match self {
+            Operation::Bne(_, _) => Some(ordering != Ordering::Equal),
+            Operation::Beq(_, _) => Some(ordering == Ordering::Equal),
+            Operation::Ble(_, _) => Some(ordering != Ordering::Greater),
+            Operation::Blt(_, _) => Some(ordering == Ordering::Less),
+            Operation::Bge(_, _) => Some(ordering != Ordering::Less),
+            Operation::Bgt(_, _) => Some(ordering == Ordering::Greater),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Clone, PartialEq)]
@@ -135,19 +245,4 @@ impl Instruction {
         Instruction::new(line_number, operation)
     }
 
-    // Method to get the defined variable (if any) from an instruction
-    pub fn get_def(&self) -> Option<isize> {
-        match self.operation {
-            Operation::Add(_, _) |
-            Operation::Sub(_, _) |
-            Operation::Mul(_, _) |
-            Operation::Div(_, _) |
-            Operation::SetPar1(_) |
-            Operation::SetPar2(_) |
-            Operation::SetPar3(_) => Some(self.line_number),
-
-            // Add cases for other operations that define a variable
-            _ => None,
-        }
-    }
 }
@@ -0,0 +1,482 @@
+use crate::basic_block::BasicBlockType;
+use crate::constant_block::get_constant_value;
+use crate::function::Function;
+use crate::instruction::Operation;
+use crate::program::Program;
+use petgraph::graph::{EdgeIndex, NodeIndex};
+use petgraph::visit::EdgeRef;
+use petgraph::Direction::{Incoming, Outgoing};
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{BufRead, Write};
+
+type Word = i32;
+type LineNumber = isize;
+
+pub const DEFAULT_INSTRUCTION_LIMIT: usize = 10_000_000;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum InterpreterError {
+    UndefinedValue { function: String, line: LineNumber },
+    DivisionByZero { function: String, line: LineNumber },
+    UnknownCallTarget { function: String, line: LineNumber },
+    MissingArgument { function: String, line: LineNumber },
+    NoSuccessor { function: String, block: usize },
+    InvalidInput(String),
+    Io(String),
+    InstructionLimitExceeded(usize),
+}
+
+impl fmt::Display for InterpreterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InterpreterError::UndefinedValue { function, line } => write!(f, "({}) read before it is defined in {}", line, function),
+            InterpreterError::DivisionByZero { function, line } => write!(f, "division by zero at ({}) in {}", line, function),
+            InterpreterError::UnknownCallTarget { function, line } => write!(f, "call at ({}) in {} does not jump to a function", line, function),
+            InterpreterError::MissingArgument { function, line } => write!(f, "parameter read at ({}) in {} was not passed", line, function),
+            InterpreterError::NoSuccessor { function, block } => write!(f, "BB{} in {} ends without a successor", block, function),
+            InterpreterError::InvalidInput(input) => write!(f, "expected a number as input, got {:?}", input),
+            InterpreterError::Io(error) => write!(f, "{}", error),
+            InterpreterError::InstructionLimitExceeded(limit) => write!(f, "stopped after {} instructions", limit),
+        }
+    }
+}
+
+// one activation of a function, the values are keyed by the line that defines them
+struct Frame<'a> {
+    function: &'a Function,
+    block: NodeIndex,
+    position: usize,
+    values: HashMap<LineNumber, Word>,
+    arguments: Vec<Word>,
+}
+
+/// Executes the SSA IR of a program directly from its basic block graphs, so its output can be
+/// compared with what the emulator prints for the generated machine code.
+/// Values wrap like 32-bit registers and the I/O operations print the same way the emulator does.
+pub struct Interpreter<'a, R: BufRead, W: Write> {
+    program: &'a Program,
+    // jsr and call name the callee by the first line of its entry block
+    entry_lines: HashMap<LineNumber, &'a Function>,
+    globals: HashMap<isize, Word>,
+    frames: Vec<Frame<'a>>,
+    // set by setPar in the caller, handed to the callee on the next call
+    pending_arguments: Vec<Word>,
+    reader: R,
+    writer: W,
+    instruction_limit: usize,
+}
+
+impl<'a, R: BufRead, W: Write> Interpreter<'a, R, W> {
+    pub fn new(program: &'a Program, reader: R, writer: W) -> Self {
+        let mut entry_lines = HashMap::new();
+        for function in program.functions.values() {
+            let entry_block = function.get_bb(&function.get_entry_node());
+            if let Some(instruction) = entry_block.and_then(|block| block.instructions.first()) {
+                entry_lines.insert(instruction.get_line_number(), function);
+            }
+        }
+
+        Self {
+            program,
+            entry_lines,
+            globals: HashMap::new(),
+            frames: Vec::new(),
+            pending_arguments: Vec::new(),
+            reader,
+            writer,
+            instruction_limit: DEFAULT_INSTRUCTION_LIMIT,
+        }
+    }
+
+    pub fn set_instruction_limit(&mut self, instruction_limit: usize) {
+        self.instruction_limit = instruction_limit;
+    }
+
+    #[cfg(test)]
+    pub fn get_writer(&self) -> &W {
+        &self.writer
+    }
+
+    /// runs main until it ends, returning how many instructions were executed
+    pub fn run(&mut self) -> Result<usize, InterpreterError> {
+        let main = self.program.get_fn("main");
+        self.frames = vec![Frame { function: main, block: main.get_entry_node(), position: 0, values: HashMap::new(), arguments: Vec::new() }];
+
+        let mut executed = 0;
+        while executed < self.instruction_limit {
+            executed += 1;
+            if !self.step()? {
+                self.writer.flush().map_err(|error| InterpreterError::Io(error.to_string()))?;
+                return Ok(executed);
+            }
+        }
+        Err(InterpreterError::InstructionLimitExceeded(self.instruction_limit))
+    }
+
+    /// executes one instruction, or moves on to the next block at the end of one,
+    /// returns false once the program has ended
+    pub fn step(&mut self) -> Result<bool, InterpreterError> {
+        let frame = self.frames.last().unwrap();
+        let function = frame.function;
+        let block = &function.get_graph()[frame.block];
+
+        let Some(instruction) = block.instructions.get(frame.position) else {
+            let successor = self.get_fall_through_successor()?;
+            self.enter_block(successor)?;
+            return Ok(true);
+        };
+        let line = instruction.get_line_number();
+
+        match instruction.operation {
+            Operation::Add(left, right) => {
+                let value = self.get_value(left)?.wrapping_add(self.get_value(right)?);
+                self.define(line, value);
+            },
+            Operation::Sub(left, right) => {
+                let value = self.get_value(left)?.wrapping_sub(self.get_value(right)?);
+                self.define(line, value);
+            },
+            Operation::Mul(left, right) => {
+                let value = self.get_value(left)?.wrapping_mul(self.get_value(right)?);
+                self.define(line, value);
+            },
+            Operation::Div(left, right) => {
+                let divisor = self.get_value(right)?;
+                if divisor == 0 {
+                    return Err(InterpreterError::DivisionByZero { function: self.get_function_name(), line });
+                }
+                let value = self.get_value(left)?.wrapping_div(divisor);
+                self.define(line, value);
+            },
+            // the sign of the difference, which is what the branches test like CMP on DLX
+            Operation::Cmp(left, right) => {
+                let value = self.get_value(left)?.cmp(&self.get_value(right)?) as Word;
+                self.define(line, value);
+            },
+
+            Operation::Bra(target) => {
+                self.enter_block(NodeIndex::new(target as usize))?;
+                return Ok(true);
+            },
+            Operation::Bne(comparison, target) |
+            Operation::Beq(comparison, target) |
+            Operation::Ble(comparison, target) |
+            Operation::Blt(comparison, target) |
+            Operation::Bge(comparison, target) |
+            Operation::Bgt(comparison, target) => {
+                let value = self.get_value(comparison)?;
+                let is_taken = match instruction.operation {
+                    Operation::Bne(_, _) => value != 0,
+                    Operation::Beq(_, _) => value == 0,
+                    Operation::Ble(_, _) => value <= 0,
+                    Operation::Blt(_, _) => value < 0,
+                    Operation::Bge(_, _) => value >= 0,
+                    _ => value > 0,
+                };
+                if is_taken {
+                    self.enter_block(NodeIndex::new(target as usize))?;
+                    return Ok(true);
+                }
+            },
+
+            // the caller moves past the call once the callee returns
+            Operation::Jsr(entry_line) | Operation::Call(entry_line) => {
+                let callee = *self.entry_lines.get(&entry_line)
+                    .ok_or(InterpreterError::UnknownCallTarget { function: self.get_function_name(), line })?;
+                let arguments = std::mem::take(&mut self.pending_arguments);
+                self.frames.push(Frame { function: callee, block: callee.get_entry_node(), position: 0, values: HashMap::new(), arguments });
+                return Ok(true);
+            },
+            Operation::Ret(value) => {
+                let value = self.get_value(value)?;
+                self.frames.pop();
+                let Some(caller) = self.frames.last_mut() else {
+                    return Ok(false);
+                };
+                let call = &caller.function.get_graph()[caller.block].instructions[caller.position];
+                if matches!(call.operation, Operation::Call(_)) {
+                    caller.values.insert(call.get_line_number(), value);
+                }
+            },
+            Operation::GetPar1 => self.get_argument(0, line)?,
+            Operation::GetPar2 => self.get_argument(1, line)?,
+            Operation::GetPar3 => self.get_argument(2, line)?,
+            Operation::SetPar1(value) => self.set_argument(0, line, value)?,
+            Operation::SetPar2(value) => self.set_argument(1, line, value)?,
+            Operation::SetPar3(value) => self.set_argument(2, line, value)?,
+
+            Operation::Read => {
+                let value = self.read_number()?;
+                self.define(line, value);
+            },
+            Operation::Write(value) => {
+                let value = self.get_value(value)?;
+                self.write(&format!("{} ", value))?;
+            },
+            Operation::WriteNL => self.write("\n")?,
+
+            // globals nobody stored to yet read as zero like fresh memory
+            Operation::Load(offset) => {
+                let value = *self.globals.get(&offset).unwrap_or(&0);
+                self.define(line, value);
+            },
+            Operation::Store(value, offset) => {
+                let value = self.get_value(value)?;
+                self.globals.insert(offset, value);
+            },
+
+            Operation::End => return Ok(false),
+            // phis were evaluated when the block was entered
+            Operation::Phi(_, _) | Operation::Const(_) | Operation::Empty => (),
+        }
+
+        self.frames.last_mut().unwrap().position += 1;
+        Ok(true)
+    }
+
+    // moves to another block of the current function, evaluating all of its phis at once
+    // with the operand of the edge the control came in on
+    fn enter_block(&mut self, target: NodeIndex) -> Result<(), InterpreterError> {
+        let frame = self.frames.last().unwrap();
+        let graph = frame.function.get_graph();
+        let predecessor = frame.block;
+
+        // phi operands are in the order the incoming edges were added
+        let mut incoming_edges: Vec<(EdgeIndex, NodeIndex)> = graph.edges_directed(target, Incoming)
+            .map(|edge| (edge.id(), edge.source()))
+            .collect();
+        incoming_edges.sort();
+        let operand_index = incoming_edges.iter().position(|(_, source)| *source == predecessor);
+
+        let mut phi_values = Vec::new();
+        for instruction in &graph[target].instructions {
+            if let Operation::Phi(left, right) = instruction.operation {
+                let operand = if operand_index == Some(0) { left } else { right };
+                phi_values.push((instruction.get_line_number(), self.get_value(operand)?));
+            }
+        }
+
+        let frame = self.frames.last_mut().unwrap();
+        frame.block = target;
+        frame.position = 0;
+        frame.values.extend(phi_values);
+        Ok(())
+    }
+
+    // a block that does not branch continues into its only successor, or into the fall-through
+    // block when it is a conditional one
+    fn get_fall_through_successor(&self) -> Result<NodeIndex, InterpreterError> {
+        let frame = self.frames.last().unwrap();
+        let graph = frame.function.get_graph();
+        let edges: Vec<_> = graph.edges_directed(frame.block, Outgoing).collect();
+
+        let successor = match edges.as_slice() {
+            [edge] => Some(edge.target()),
+            _ => edges.iter().find(|edge| *edge.weight() == BasicBlockType::FallThrough).map(|edge| edge.target()),
+        };
+        successor.ok_or(InterpreterError::NoSuccessor { function: self.get_function_name(), block: frame.block.index() })
+    }
+
+    fn get_value(&self, line: LineNumber) -> Result<Word, InterpreterError> {
+        if let Some(constant) = get_constant_value(line) {
+            return Ok(constant as Word);
+        }
+        let frame = self.frames.last().unwrap();
+        frame.values.get(&line).copied().ok_or(InterpreterError::UndefinedValue { function: self.get_function_name(), line })
+    }
+
+    fn define(&mut self, line: LineNumber, value: Word) {
+        self.frames.last_mut().unwrap().values.insert(line, value);
+    }
+
+    fn get_argument(&mut self, index: usize, line: LineNumber) -> Result<(), InterpreterError> {
+        let argument = self.frames.last().unwrap().arguments.get(index).copied()
+            .ok_or(InterpreterError::MissingArgument { function: self.get_function_name(), line })?;
+        self.define(line, argument);
+        Ok(())
+    }
+
+    fn set_argument(&mut self, index: usize, line: LineNumber, value: LineNumber) -> Result<(), InterpreterError> {
+        let value = self.get_value(value)?;
+        if self.pending_arguments.len() <= index {
+            self.pending_arguments.resize(index + 1, 0);
+        }
+        self.pending_arguments[index] = value;
+        self.define(line, value);
+        Ok(())
+    }
+
+    fn get_function_name(&self) -> String {
+        self.frames.last().unwrap().function.name.clone()
+    }
+
+    fn read_number(&mut self) -> Result<Word, InterpreterError> {
+        let mut line = String::new();
+        loop {
+            line.clear();
+            let read = self.reader.read_line(&mut line).map_err(|error| InterpreterError::Io(error.to_string()))?;
+            if read == 0 {
+                return Err(InterpreterError::InvalidInput(String::new()));
+            }
+            if !line.trim().is_empty() {
+                break;
+            }
+        }
+
+        line.trim().parse::<Word>().map_err(|_| InterpreterError::InvalidInput(line.trim().to_string()))
+    }
+
+    fn write(&mut self, output: &str) -> Result<(), InterpreterError> {
+        self.writer.write_all(output.as_bytes()).map_err(|error| InterpreterError::Io(error.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod interpreter_tests {
+    use super::*;
+    use crate::assembler::get_machine_code_instructions;
+    use crate::code_gen::CodeGeneration;
+    use crate::emulator::Emulator;
+    use crate::parser::Parser;
+
+    fn interpret(input: &str, program_input: &str) -> (Result<usize, InterpreterError>, String) {
+        let mut parser = Parser::new(input.to_string());
+        parser.parse_computation();
+
+        let mut interpreter = Interpreter::new(&parser.internal_program, program_input.as_bytes(), Vec::new());
+        let result = interpreter.run();
+        (result, String::from_utf8(interpreter.get_writer().clone()).unwrap())
+    }
+
+    #[test]
+    fn test_if_else_phis() {
+        let input = "
+main var a, b; {
+    let a <- call InputNum();
+    if a < 10 then
+        let b <- a * 2
+    else
+        let b <- a - 10
+    fi;
+    call OutputNum(b);
+    call OutputNewLine()
+}.";
+        assert_eq!(interpret(input, "4").1, "8 \n");
+        assert_eq!(interpret(input, "25").1, "15 \n");
+    }
+
+    #[test]
+    fn test_loops() {
+        let input = "
+main var i, sum, unchanged; {
+    let i <- 0;
+    let sum <- 0;
+    let unchanged <- 7;
+    while i < call InputNum() do
+        let sum <- sum + i;
+        let i <- i + 1
+    od;
+    call OutputNum(sum);
+    call OutputNum(i);
+    call OutputNum(unchanged)
+}.";
+        assert_eq!(interpret(input, "1\n2\n3\n4\n5\n0\n").1, "10 5 7 ");
+    }
+
+    #[test]
+    fn test_calls_and_globals() {
+        let input = "
+main var counter;
+function add(x, y); { return x + y };
+void function bump(); { let counter <- counter + 1 };
+{
+    let counter <- 1;
+    call bump();
+    call bump();
+    call OutputNum(call add(counter, 10))
+}.";
+        assert_eq!(interpret(input, "").1, "13 ");
+    }
+
+    #[test]
+    fn test_recursion() {
+        let input = "
+main
+function factorial(n); {
+    if n <= 1 then
+        return 1
+    fi;
+    return n * call factorial(n - 1)
+};
+{
+    call OutputNum(call factorial(call InputNum()))
+}.";
+        assert_eq!(interpret(input, "6").1, "720 ");
+    }
+
+    // the output of the whole backend for main, run on the emulator
+    fn emulate(input: &str, program_input: &str) -> String {
+        let mut parser = Parser::new(input.to_string());
+        parser.parse_computation();
+
+        let mut code_generation = CodeGeneration::new(&mut parser.internal_program.get_fn_mut("main").bb_graph);
+        code_generation.generate_code();
+        let machine_code = get_machine_code_instructions(code_generation.get_assembly_instructions()).unwrap();
+        let mut emulator = Emulator::new(&machine_code, program_input.as_bytes(), Vec::new()).unwrap();
+        emulator.run().unwrap();
+        String::from_utf8(emulator.get_writer().clone()).unwrap()
+    }
+
+    // straight-line code through the whole backend prints the same as the IR it came from
+    #[test]
+    fn test_matches_emulator() {
+        let input = "main var a, b; { let a <- call InputNum(); let b <- a * 3 - 4; call OutputNum(b / 2); call OutputNewLine(); call OutputNum(a + b) }.";
+        let (result, output) = interpret(input, "7");
+        assert!(result.is_ok());
+        assert_eq!(output, "8 \n24 ");
+        assert_eq!(output, emulate(input, "7"));
+
+        // constants on the left of operations that do not commute
+        let input = "main var a; { let a <- call InputNum(); call OutputNum(7 - a); call OutputNum(100 / a); call OutputNum(2 - a * 3) }.";
+        assert_eq!(interpret(input, "11").1, "-4 9 -31 ");
+        assert_eq!(emulate(input, "11"), "-4 9 -31 ");
+    }
+
+    #[test]
+    fn test_branches_match_emulator() {
+        let inputs = [
+            "main var a; { let a <- call InputNum(); if a < 10 then call OutputNum(1) else call OutputNum(2) fi; call OutputNum(3) }.",
+            // a phi at the join
+            "main var a, b; { let a <- call InputNum(); if a < 10 then let b <- 10 else let b <- 1 fi; call OutputNum(b) }.",
+            "main var a, b; { let a <- call InputNum(); let b <- a; if a < 10 then let b <- a * 2 fi; call OutputNum(b) }.",
+            // loop phis, a loop in a branch and nested loops
+            "main var a, i, s; { let a <- call InputNum(); let i <- 0; let s <- 0; while i < a do let s <- s + i; let i <- i + 1 od; call OutputNum(s) }.",
+            "main var a, i; { let a <- call InputNum(); let i <- 0; if a > 5 then while i < a do let i <- i + 2 od fi; call OutputNum(i) }.",
+            "main var a, i, j, s; {
+                let a <- call InputNum(); let i <- 0; let s <- 0;
+                while i < a do let j <- 0; while j < i do let s <- s + j; let j <- j + 1 od; let i <- i + 1 od;
+                call OutputNum(s)
+            }.",
+        ];
+        for input in inputs {
+            for program_input in ["3", "12"] {
+                let (result, output) = interpret(input, program_input);
+                assert!(result.is_ok());
+                assert_eq!(output, emulate(input, program_input), "{} with {}", input, program_input);
+            }
+        }
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(interpret("main { call OutputNum(1 / 0) }.", "").0.unwrap_err().to_string(), "division by zero at (1) in main");
+        assert_eq!(interpret("main { call OutputNum(call InputNum()) }.", "x").0, Err(InterpreterError::InvalidInput("x".to_string())));
+
+        let mut parser = Parser::new("main { while 1 < 2 do od }.".to_string());
+        parser.parse_computation();
+        let mut interpreter = Interpreter::new(&parser.internal_program, "".as_bytes(), Vec::new());
+        interpreter.set_instruction_limit(100);
+        assert_eq!(interpreter.run(), Err(InterpreterError::InstructionLimitExceeded(100)));
+    }
+}
@@ -0,0 +1,262 @@
+use crate::assembler::{get_machine_code_instructions, AssembleError};
+use crate::block_layout::{get_profile_weights, layout_blocks};
+use crate::code_gen::{
+    resolve_labels, AssemblyInstruction, AssemblyItem, CodeGeneration, Label, FRAME_POINTER_REGISTER, GLOBAL_POINTER_REGISTER,
+    STACK_POINTER_REGISTER,
+};
+use crate::object_file::{LineEntry, ObjectFile, ObjectSymbol};
+use crate::program::Program;
+use std::collections::HashMap;
+use std::fmt;
+
+type LineNumber = isize;
+
+/// name of the runtime stub every image starts with
+pub const START_SYMBOL: &str = "_start";
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum LinkError {
+    /// a call to a function that has no code, which is also how a missing main shows up
+    UndefinedSymbol { caller: String, name: String },
+    Assemble(AssembleError),
+}
+
+impl fmt::Display for LinkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LinkError::UndefinedSymbol { caller, name } => write!(f, "{} calls {}, which is not defined", caller, name),
+            LinkError::Assemble(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+// the stub sets up the stack right below the globals, calls main and halts when main returns
+fn get_start_stub(global_count: usize) -> Vec<AssemblyItem> {
+    vec![
+        AssemblyItem::Label(Label::Function(START_SYMBOL.to_string())),
+        AssemblyItem::Instruction(AssemblyInstruction::ADDI(STACK_POINTER_REGISTER, GLOBAL_POINTER_REGISTER, -4 * global_count as isize)),
+        AssemblyItem::Instruction(AssemblyInstruction::ADD(FRAME_POINTER_REGISTER, STACK_POINTER_REGISTER, 0)),
+        AssemblyItem::Jump(AssemblyInstruction::JSR(0), Label::Function("main".to_string())),
+        AssemblyItem::Instruction(AssemblyInstruction::RET(0)),
+    ]
+}
+
+/// How often every word of an earlier image of the same program ran, with the line table of that
+/// image, see `block_layout::get_profile_weights`.
+pub struct Profile<'a> {
+    pub line_table: &'a [LineEntry],
+    pub counts: &'a [u64],
+}
+
+/// Generates code for main and every user function, in the order `link` lays them out with the
+/// runtime stub first. Calls are checked to name a function that has code. With a profile the
+/// blocks of every function are placed by how often they ran instead of by static weights.
+pub fn generate_functions(program: &Program, profile: Option<&Profile>) -> Result<Vec<(String, Vec<AssemblyItem>)>, LinkError> {
+    // jsr and call name a function by the first line of its entry block, predefined ones have none
+    let mut function_names = HashMap::<LineNumber, String>::new();
+    for (name, function) in &program.functions {
+        if let Some(instruction) = function.get_bb(&function.get_entry_node()).and_then(|block| block.instructions.first()) {
+            function_names.insert(instruction.get_line_number(), name.clone());
+        }
+    }
+
+    // main comes first, the other functions follow by name so the image does not depend on hashing
+    let mut names: Vec<&String> = function_names.values().collect();
+    names.sort_by_key(|name| (*name != "main", *name));
+
+    let mut functions = vec![(START_SYMBOL.to_string(), get_start_stub(program.globals.len()))];
+    let mut next_line = program.get_next_line();
+    for name in names {
+        // the phi copies for an edge go in a block of their own when the edge is critical
+        let mut function = program.get_fn(name).clone();
+        function.split_critical_edges(&mut next_line);
+        let mut graph = function.bb_graph;
+        let mut code_generation = match profile {
+            Some(profile) => {
+                let order = layout_blocks(&graph, &get_profile_weights(&graph, profile.line_table, profile.counts));
+                CodeGeneration::with_block_order(&mut graph, &order)
+            },
+            None => CodeGeneration::new(&mut graph),
+        };
+        code_generation.set_function_names(function_names.clone());
+        code_generation.generate_code();
+        functions.push((name.clone(), code_generation.get_assembly_items().to_vec()));
+    }
+
+    // every call, the one in the stub included, has to land on a function
+    for (caller, items) in &functions {
+        for item in items {
+            if let AssemblyItem::Jump(_, Label::Function(name)) = item {
+                if !functions.iter().any(|(defined, _)| defined == name) {
+                    return Err(LinkError::UndefinedSymbol { caller: caller.clone(), name: name.clone() });
+                }
+            }
+        }
+    }
+
+    Ok(functions)
+}
+
+/// Generates code for main and every user function and lays it out in one image behind the
+/// runtime stub, which is the entry point. Calls are resolved to the function they name, the
+/// data section holds the globals and the stack starts right below them.
+pub fn link(program: &Program) -> Result<ObjectFile, LinkError> {
+    lay_out(program, generate_functions(program, None)?)
+}
+
+/// Links like `link`, with the blocks of every function placed by a profile of an earlier image.
+pub fn link_with_profile(program: &Program, profile: &Profile) -> Result<ObjectFile, LinkError> {
+    lay_out(program, generate_functions(program, Some(profile))?)
+}
+
+fn lay_out(program: &Program, functions: Vec<(String, Vec<AssemblyItem>)>) -> Result<ObjectFile, LinkError> {
+    // the symbol and line tables follow the same layout resolve_labels uses
+    let mut symbols = Vec::new();
+    let mut line_table = Vec::new();
+    let mut address = 0;
+    for (name, items) in &functions {
+        symbols.push(ObjectSymbol { name: name.clone(), address: address * 4 });
+        for item in items {
+            match item {
+                AssemblyItem::Source(line, _) => line_table.push(LineEntry { address: address * 4, line: *line as i32 }),
+                AssemblyItem::Instruction(_) | AssemblyItem::Jump(_, _) => address += 1,
+                AssemblyItem::Label(_) => (),
+            }
+        }
+    }
+
+    let items: Vec<&[AssemblyItem]> = functions.iter().map(|(_, items)| items.as_slice()).collect();
+    let code = get_machine_code_instructions(resolve_labels(&items)).map_err(LinkError::Assemble)?;
+
+    Ok(ObjectFile {
+        entry_point: 0,
+        code,
+        data: vec![0; program.globals.len()],
+        symbols,
+        line_table: Some(line_table),
+    })
+}
+
+#[cfg(test)]
+mod linker_tests {
+    use super::*;
+    use crate::emulator::Emulator;
+    use crate::parser::Parser;
+
+    fn link_and_run(input: &str, program_input: &str) -> (ObjectFile, String) {
+        let mut parser = Parser::new(input.to_string());
+        parser.parse_computation();
+        let object = link(&parser.internal_program).unwrap();
+
+        // the linked image goes through the file format like a compiled program would
+        let object = ObjectFile::from_bytes(&object.to_bytes()).unwrap();
+        let mut emulator = Emulator::from_object_file(&object, program_input.as_bytes(), Vec::new()).unwrap();
+        emulator.run().unwrap();
+        let output = String::from_utf8(emulator.get_writer().clone()).unwrap();
+        (object, output)
+    }
+
+    #[test]
+    fn test_calls_between_functions() {
+        let input = "
+main var g;
+void function second(); { call OutputNum(2) };
+void function first(); { call OutputNum(1) };
+{
+    let g <- call InputNum();
+    call first();
+    call second();
+    call OutputNum(g)
+}.";
+        let (object, output) = link_and_run(input, "9");
+        assert_eq!(output, "1 2 9 ");
+
+        let symbol_names: Vec<&str> = object.symbols.iter().map(|symbol| symbol.name.as_str()).collect();
+        assert_eq!(symbol_names, vec!["_start", "main", "first", "second"]);
+        assert_eq!(object.entry_point, 0);
+        assert_eq!(object.data, vec![0]);
+        assert_eq!(object.get_symbol("main").unwrap().address, 16);
+    }
+
+    #[test]
+    fn test_arguments() {
+        let input = "
+main var a;
+function sub(x, y, z); { return x - y * z };
+void function show(x); { call OutputNum(x) };
+{
+    let a <- call InputNum();
+    call show(call sub(a, 2, 3));
+    call show(a)
+}.";
+        assert_eq!(link_and_run(input, "20").1, "14 20 ");
+    }
+
+    #[test]
+    fn test_nested_calls() {
+        let input = "
+main
+function inner(x); { return x + 1 };
+function outer(x); { return call inner(x) * 2 };
+function factorial(n); {
+    if n <= 1 then
+        return 1
+    fi;
+    return n * call factorial(n - 1)
+};
+{
+    call OutputNum(call outer(call outer(3)));
+    call OutputNum(call factorial(5))
+}.";
+        assert_eq!(link_and_run(input, "").1, "18 120 ");
+    }
+
+    #[test]
+    fn test_values_live_across_calls() {
+        let input = "
+main var a, b;
+function f(x); var y; { let y <- x + 100; return y + 2 };
+{
+    let a <- call InputNum();
+    let b <- call f(a);
+    call OutputNum(b);
+    call OutputNum(a)
+}.";
+        assert_eq!(link_and_run(input, "5").1, "107 5 ");
+    }
+
+    #[test]
+    fn test_phi_copies_on_critical_edges() {
+        // the branch around the then block goes straight to the join, which has phis
+        let input = "main var a, b, i; { let a <- call InputNum(); let b <- a; if a < 10 then let b <- a + 1 fi; let i <- 0; while i < b do let i <- i + 3 od; call OutputNum(b); call OutputNum(i) }.";
+        assert_eq!(link_and_run(input, "3").1, "4 6 ");
+        assert_eq!(link_and_run(input, "12").1, "12 12 ");
+    }
+
+    #[test]
+    fn test_undefined_main() {
+        let mut parser = Parser::new("main { call OutputNewLine() }.".to_string());
+        parser.parse_computation();
+        parser.internal_program.functions.remove("main");
+
+        let error = link(&parser.internal_program).unwrap_err();
+        assert_eq!(error, LinkError::UndefinedSymbol { caller: START_SYMBOL.to_string(), name: "main".to_string() });
+        assert_eq!(error.to_string(), "_start calls main, which is not defined");
+    }
+
+    #[test]
+    fn test_stack_below_globals() {
+        let input = "main var a, b, c; { let a <- call InputNum(); call OutputNum(a) }.";
+        let mut parser = Parser::new(input.to_string());
+        parser.parse_computation();
+        let object = link(&parser.internal_program).unwrap();
+
+        let mut emulator = Emulator::from_object_file(&object, "5".as_bytes(), Vec::new()).unwrap();
+        emulator.run().unwrap();
+        let global_pointer = emulator.get_register(GLOBAL_POINTER_REGISTER as usize);
+        assert_eq!(emulator.get_register(STACK_POINTER_REGISTER as usize), global_pointer - 12);
+        assert_eq!(emulator.get_register(FRAME_POINTER_REGISTER as usize), global_pointer - 12);
+        assert_eq!(object.data, vec![0, 0, 0]);
+        assert!(object.line_table.unwrap().iter().any(|entry| entry.address == 16));
+    }
+}
@@ -0,0 +1,299 @@
+use crate::diagnostic::Diagnostic;
+use crate::instruction::{Instruction, Operation};
+use crate::program::Program;
+use crate::semantic::{SemanticAnalyzer, SymbolKind};
+use petgraph::graph::NodeIndex;
+use petgraph::Direction::Outgoing;
+use std::collections::HashSet;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Lint {
+    UnusedVariable,
+    UnusedFunction,
+    UnreachableCode,
+    ConstantFalseLoop,
+}
+
+impl Lint {
+    pub const ALL: [Lint; 4] = [Lint::UnusedVariable, Lint::UnusedFunction, Lint::UnreachableCode, Lint::ConstantFalseLoop];
+
+    // the name the driver knows the lint by
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            Lint::UnusedVariable => "unused-variable",
+            Lint::UnusedFunction => "unused-function",
+            Lint::UnreachableCode => "unreachable-code",
+            Lint::ConstantFalseLoop => "constant-false-loop",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Lint> {
+        Lint::ALL.into_iter().find(|lint| lint.get_name() == name)
+    }
+}
+
+/// Which lints run, every lint is enabled unless the driver says otherwise.
+#[derive(Debug, Clone)]
+pub struct LintConfig {
+    enabled: HashSet<Lint>,
+}
+
+impl Default for LintConfig {
+    fn default() -> Self {
+        Self { enabled: Lint::ALL.into_iter().collect() }
+    }
+}
+
+impl LintConfig {
+    pub fn set_enabled(&mut self, lint: Lint, enabled: bool) {
+        if enabled {
+            self.enabled.insert(lint);
+        } else {
+            self.enabled.remove(&lint);
+        }
+    }
+
+    pub fn is_enabled(&self, lint: Lint) -> bool {
+        self.enabled.contains(&lint)
+    }
+}
+
+/// Runs the enabled lints over a program that passed the semantic pass and has been parsed,
+/// returning warnings in source order.
+pub fn run_lints(config: &LintConfig, analyzer: &SemanticAnalyzer, program: &Program) -> Vec<Diagnostic> {
+    let mut lints = Vec::new();
+
+    if config.is_enabled(Lint::UnusedVariable) {
+        lint_unused_variables(analyzer, &mut lints);
+    }
+    if config.is_enabled(Lint::UnusedFunction) {
+        lint_unused_functions(analyzer, &mut lints);
+    }
+    if config.is_enabled(Lint::UnreachableCode) {
+        lint_unreachable_code(program, &mut lints);
+    }
+    if config.is_enabled(Lint::ConstantFalseLoop) {
+        lint_constant_false_loops(analyzer, program, &mut lints);
+    }
+
+    lints.sort_by_key(|lint| (lint.span.line, lint.span.column));
+    lints
+}
+
+fn lint_unused_variables(analyzer: &SemanticAnalyzer, lints: &mut Vec<Diagnostic>) {
+    let symbol_table = &analyzer.symbol_table;
+    let scopes = std::iter::once(&symbol_table.globals).chain(symbol_table.function_scopes.values());
+
+    for symbol in scopes.flat_map(|scope| scope.values()) {
+        if symbol.reads > 0 {
+            continue;
+        }
+        let what = if symbol.kind == SymbolKind::Parameter { "parameter" } else { "variable" };
+        let message = format!("{} {} is never used [{}]", what, symbol.name, Lint::UnusedVariable.get_name());
+        // only predefined names have no span, and they are functions
+        let Some(span) = symbol.span else {
+            continue;
+        };
+        lints.push(Diagnostic::warning(span, message));
+    }
+}
+
+fn lint_unused_functions(analyzer: &SemanticAnalyzer, lints: &mut Vec<Diagnostic>) {
+    let calls = &analyzer.symbol_table.calls;
+    let mut reached = HashSet::<&str>::new();
+    let mut stack = vec!["main"];
+
+    while let Some(function_name) = stack.pop() {
+        if !reached.insert(function_name) {
+            continue;
+        }
+        if let Some(callees) = calls.get(function_name) {
+            stack.extend(callees.iter().map(|callee| callee.as_str()));
+        }
+    }
+
+    for symbol in analyzer.symbol_table.functions.values() {
+        // predefined functions have no span and are never reported
+        if let Some(span) = symbol.span {
+            if !reached.contains(symbol.name.as_str()) {
+                let message = format!("function {} is never called from main [{}]", symbol.name, Lint::UnusedFunction.get_name());
+                lints.push(Diagnostic::warning(span, message));
+            }
+        }
+    }
+}
+
+// Control does not go past a return or the end of the program, so the statements after one in
+// its block, and the blocks only reached through it, never run. Only the first statement of each
+// stretch that cannot be reached is reported.
+fn lint_unreachable_code(program: &Program, lints: &mut Vec<Diagnostic>) {
+    for function in program.functions.values() {
+        let graph = function.get_graph();
+        let get_exit = |block: NodeIndex| graph[block].instructions.iter().position(|instruction| matches!(instruction.operation, Operation::Ret(_) | Operation::End));
+
+        let mut reachable = HashSet::new();
+        let mut stack = vec![function.get_entry_node()];
+        while let Some(block) = stack.pop() {
+            if reachable.insert(block) && get_exit(block).is_none() {
+                stack.extend(graph.neighbors_directed(block, Outgoing));
+            }
+        }
+
+        let mut follows_reachable = true;
+        for (span, block, position) in &function.statement_starts {
+            let is_reachable = reachable.contains(block) && get_exit(*block).is_none_or(|exit| *position <= exit);
+            if follows_reachable && !is_reachable {
+                lints.push(Diagnostic::warning(*span, format!("unreachable code after return [{}]", Lint::UnreachableCode.get_name())));
+            }
+            follows_reachable = is_reachable;
+        }
+    }
+}
+
+// the parser records the header of every while together with the span of the while
+fn lint_constant_false_loops(analyzer: &SemanticAnalyzer, program: &Program, lints: &mut Vec<Diagnostic>) {
+    for (function_name, loop_spans) in &analyzer.loop_spans {
+        let function = program.get_fn(function_name);
+        let graph = function.get_graph();
+
+        for span in loop_spans {
+            let Some(&(_, loop_header)) = function.loop_headers.iter().find(|(header_span, _)| header_span == span) else {
+                continue;
+            };
+            let instructions = &graph[loop_header].instructions;
+            let Some(branch) = instructions.last().map(|instruction| instruction.operation) else {
+                continue;
+            };
+            let Some(comparison_line) = branch.get_branch_comparison() else {
+                continue;
+            };
+            let comparison = instructions.iter().find(|instruction| instruction.get_line_number() == comparison_line);
+
+            // constants live at negative line numbers, the branch leaves the loop when it is taken
+            if let Some(Operation::Cmp(left, right)) = comparison.map(|instruction| instruction.operation) {
+                let (left, right) = (get_entry_value(instructions, left), get_entry_value(instructions, right));
+                if left <= 0 && right <= 0 && branch.is_taken((-left).cmp(&-right)) == Some(true) {
+                    let message = format!("while condition is always false so the loop never runs [{}]", Lint::ConstantFalseLoop.get_name());
+                    lints.push(Diagnostic::warning(*span, message));
+                }
+            }
+        }
+    }
+}
+
+// the first evaluation of the condition sees the value a header phi receives from before the loop
+fn get_entry_value(header_instructions: &[Instruction], line: isize) -> isize {
+    let phi = header_instructions.iter().find(|instruction| instruction.get_line_number() == line);
+    match phi.map(|instruction| instruction.operation) {
+        Some(Operation::Phi(entry_value, _)) => entry_value,
+        _ => line,
+    }
+}
+
+#[cfg(test)]
+mod lint_tests {
+    use super::*;
+    use crate::parser::Parser;
+
+    fn lint(input: &str, config: &LintConfig) -> Vec<String> {
+        let mut analyzer = SemanticAnalyzer::new(input.to_string());
+        analyzer.analyze();
+        let mut parser = Parser::new(input.to_string());
+        parser.parse_computation();
+
+        run_lints(config, &analyzer, &parser.internal_program).iter().map(|lint| lint.to_string()).collect()
+    }
+
+    const INPUT: &str = "
+main var a, unused;
+function f(x, y); {
+    return x;
+    let a <- 1
+};
+void function g(); { call g };
+{
+    let a <- call f(1, 2);
+    while 3 < 2 do
+        let a <- a + 1
+    od;
+    while a < 2 do
+        let a <- a + 1
+    od;
+    call OutputNum(a)
+}.";
+
+    #[test]
+    fn test_all_lints() {
+        assert_eq!(lint(INPUT, &LintConfig::default()), vec![
+            "2:13: warning: variable unused is never used [unused-variable]".to_string(),
+            "3:15: warning: parameter y is never used [unused-variable]".to_string(),
+            "5:5: warning: unreachable code after return [unreachable-code]".to_string(),
+            "7:15: warning: function g is never called from main [unused-function]".to_string(),
+            "10:5: warning: while condition is always false so the loop never runs [constant-false-loop]".to_string(),
+        ]);
+    }
+
+    #[test]
+    fn test_unreachable_blocks() {
+        // the join after two returns is never entered, the code after the loop still runs
+        let input = "main var a;
+function f(x); {
+    if x > 0 then return 1 else return 2 fi;
+    let x <- x + 1;
+    while x > 0 do let x <- x - 1 od;
+    return x
+};
+{
+    let a <- call f(1);
+    while a > 0 do
+        return;
+        let a <- a - 1
+    od;
+    call OutputNum(a)
+}.";
+        assert_eq!(lint(input, &LintConfig::default()), vec![
+            "4:5: warning: unreachable code after return [unreachable-code]".to_string(),
+            "12:9: warning: unreachable code after return [unreachable-code]".to_string(),
+        ]);
+    }
+
+    #[test]
+    fn test_constant_false_loop_through_phi() {
+        let input = "main var i; { let i <- 5; while i < 3 do let i <- i + 1 od; call OutputNum(i) }.";
+        assert_eq!(lint(input, &LintConfig::default()), vec![
+            "1:27: warning: while condition is always false so the loop never runs [constant-false-loop]".to_string(),
+        ]);
+    }
+
+    #[test]
+    fn test_constant_false_nested_loop() {
+        let input = "main var i, j; {
+    let i <- call InputNum();
+    if i > 0 then
+        while i < 10 do
+            while 2 < 1 do let j <- 1 od;
+            let i <- i + 1
+        od
+    fi;
+    while i > 0 do let i <- i - 1 od
+}.";
+        assert_eq!(lint(input, &LintConfig::default()), vec![
+            "1:13: warning: variable j is never used [unused-variable]".to_string(),
+            "5:13: warning: while condition is always false so the loop never runs [constant-false-loop]".to_string(),
+        ]);
+    }
+
+    #[test]
+    fn test_disabled_lints() {
+        let mut config = LintConfig::default();
+        config.set_enabled(Lint::UnusedVariable, false);
+        config.set_enabled(Lint::UnreachableCode, false);
+        config.set_enabled(Lint::ConstantFalseLoop, false);
+
+        assert_eq!(lint(INPUT, &config), vec![
+            "7:15: warning: function g is never called from main [unused-function]".to_string(),
+        ]);
+        assert_eq!(Lint::from_name("unused-function"), Some(Lint::UnusedFunction));
+        assert_eq!(Lint::from_name("unknown"), None);
+    }
+}
@@ -1,10 +1,12 @@
 use crate::basic_block::{BasicBlock, BasicBlockType};
-use crate::instruction::{self, Instruction, Operation};
-use petgraph::data::Build;
+use crate::block_layout::{get_static_weights, layout_blocks};
+use crate::dataflow::{solve, Analysis, Direction, Lattice, UnionSet};
+use crate::instruction::{Instruction, Operation};
 use petgraph::graph::{DiGraph, UnGraph};
-use petgraph::graph::{Node, NodeIndex};
+use petgraph::graph::NodeIndex;
+use petgraph::visit::EdgeRef;
 use petgraph::Direction::{Incoming, Outgoing};
-use std::collections::{HashMap, HashSet, VecDeque};
+use std::collections::{HashMap, HashSet};
 
 type LiveSet = HashSet<isize>;
 type LineNumber = isize;
@@ -14,7 +16,6 @@ type Clusters = Vec<Cluster>;
 pub type InterferenceGraph = UnGraph<LineNumber, ()>;
 pub type BasicBlockGraph = DiGraph<BasicBlock, BasicBlockType>;
 type LineNumSet = HashSet<LineNumber>;
-type UsgCnt = usize;
 
 #[derive(Default)]
 pub struct BlockInfo {
@@ -24,235 +25,291 @@ pub struct BlockInfo {
     pub out_set: LiveSet,
 }
 
+/// The values live on entry to and exit from every block. A block uses the values it reads
+/// before it defines them, phi operands included, which it passes on to the successor they
+/// come from.
 pub fn compute_live_sets(g: &BasicBlockGraph) -> HashMap<NodeIndex, BlockInfo> {
     let mut block_info = HashMap::new();
-
-    // Initialize use and def sets
     for node_index in g.node_indices() {
-        let block = &g[node_index];
-        let mut info = BlockInfo::default();
-
-        info.def_set = get_def_set(&block, g);
-
-        for instruction in &block.instructions {
-            // Determine the use and def sets for each instruction
-            match instruction.get_operation_ref() {
-                Operation::Phi(l, r)
-                | Operation::Cmp(l, r)
-                | Operation::Add(l, r)
-                | Operation::Mul(l, r)
-                | Operation::Div(l, r)
-                | Operation::Sub(l, r) => {
-                    info.use_set.insert(*l);
-                    info.use_set.insert(*r);
-                }
-                Operation::Write(l)
-                | Operation::Ret(l)
-                | Operation::SetPar1(l)
-                | Operation::SetPar2(l)
-                | Operation::SetPar3(l) => {
-                    info.use_set.insert(*l);
-                }
-                _ => {}
-            }
-            // Collect defs (simplified for this example, adjust as needed)
-            // if let Some(def) = instruction.get_def() {
-            //     info.def_set.insert(def);
-            // }
-        }
-
+        let info = BlockInfo {
+            // walking the block back from nothing live leaves what it reads before defining it
+            use_set: walk_block(g, node_index, &LiveSet::new(), |_, _, _| {}),
+            def_set: g[node_index].instructions.iter()
+                .filter(|instruction| defines_value(&instruction.operation))
+                .map(|instruction| instruction.get_line_number())
+                .collect(),
+            ..Default::default()
+        };
         block_info.insert(node_index, info);
     }
 
-    let mut changed = true;
-    while changed {
-        changed = false;
+    let result = solve(g, &Liveness { block_info: &block_info });
+    for (node_index, info) in &mut block_info {
+        info.in_set = result.in_facts[node_index].0.clone();
+        info.out_set = result.out_facts[node_index].0.clone();
+    }
+    block_info
+}
 
-        for node_index in g.node_indices().rev() {
-            // Compute OUT set
-            let mut new_out_set = LiveSet::new();
-            let successors: Vec<NodeIndex> = g.neighbors_directed(node_index, Outgoing).collect();
-            for successor in &successors {
-                let successor_info = &block_info[&successor];
-                new_out_set.extend(&successor_info.in_set);
-            }
+// in = use + (out - def), out is what the successors need
+struct Liveness<'a> {
+    block_info: &'a HashMap<NodeIndex, BlockInfo>,
+}
 
-            // Compute IN set
-            let mut new_in_set = block_info[&node_index].use_set.clone();
-            for &var in &new_out_set {
-                if !block_info[&node_index].def_set.contains(&var) {
-                    new_in_set.insert(var);
-                }
-            }
+impl Analysis for Liveness<'_> {
+    type Fact = UnionSet<LineNumber>;
+    const DIRECTION: Direction = Direction::Backward;
 
-            // Check for changes
-            let info = block_info.get_mut(&node_index).unwrap();
-            if new_out_set != info.out_set || new_in_set != info.in_set {
-                info.out_set = new_out_set;
-                info.in_set = new_in_set;
-                changed = true;
-            }
-        }
+    fn get_boundary(&self, _g: &BasicBlockGraph) -> Self::Fact {
+        UnionSet::top()
     }
 
-    for (b, binfo) in &block_info {
-        println!("{:?}", b);
-        println!("def set: ");
-        for ins in &binfo.def_set {
-            println!("{:?}", ins);
-        }
-        println!("use set: ");
-        for ins in &binfo.use_set {
-            println!("{:?}", ins);
-        }
-        println!("in set: ");
-        for ins in &binfo.in_set {
-            println!("{:?}", ins);
-        }
-
-        println!("out set: ");
-        for ins in &binfo.out_set {
-            println!("{:?}", ins);
-        }
+    fn transfer(&self, _g: &BasicBlockGraph, block: NodeIndex, fact: &Self::Fact) -> Self::Fact {
+        let info = &self.block_info[&block];
+        let mut in_set = info.use_set.clone();
+        in_set.extend(fact.0.iter().filter(|line| !info.def_set.contains(line)));
+        UnionSet(in_set)
     }
-    // for (b, binfo) in &block_info {
-    //     println!("{:?}", b);
-    //     println!("def set: ");
-    //     for ins in &binfo.def_set {
-    //         println!("{:?}", ins);
-    //     }
-    //         println!("use set: ");
-    //     for ins in &binfo.use_set {
-    //         println!("{:?}", ins);
-    //     }
-    //         println!("in set: ");
-    //     for ins in &binfo.in_set {
-    //         println!("{:?}", ins);
-    //
-    //     }
-    //
-    //         println!("out set: ");
-    //     for ins in &binfo.out_set {
-    //         println!("{:?}", ins);
-    //     }
-    //
-    // }
-
-    block_info
 }
-pub type Instructions = Vec<Instruction>;
-fn dead_code_elimination(v: &mut Instructions, inherited_set: &mut LiveSet) {
-    // in this case, the set to eliminate should be the out set and given code elimination block by block
-    // this function should work
-    let v_clone = v.clone();
-    for (idx, instruction) in v_clone.iter().rev().enumerate() {
-        match instruction.get_operation_ref() {
-            Operation::Phi(_, _)
-            | Operation::Add(_, _)
-            | Operation::Mul(_, _)
+
+/// A position in a function's instructions laid out one after the other, blocks in the order
+/// code generation emits them. Every instruction has two points, the even one where it reads
+/// its operands and the odd one after it where it writes its value, so a value that dies at an
+/// instruction does not overlap the value the instruction defines.
+pub type ProgramPoint = usize;
+
+// whether the instruction produces a value that needs a register
+fn defines_value(operation: &Operation) -> bool {
+    matches!(
+        operation,
+        Operation::Add(_, _)
             | Operation::Sub(_, _)
+            | Operation::Mul(_, _)
             | Operation::Div(_, _)
+            | Operation::Cmp(_, _)
+            | Operation::Phi(_, _)
             | Operation::Read
+            | Operation::Load(_)
             | Operation::GetPar1
             | Operation::GetPar2
             | Operation::GetPar3
-            | Operation::SetPar1(_)
-            | Operation::SetPar2(_)
-            | Operation::SetPar3(_) => {
-                if !inherited_set.contains(&instruction.get_line_number()) {
-                    v.remove(v.len() - idx - 1);
+            | Operation::Call(_)
+    )
+}
+
+// the values an instruction reads where it stands, phi operands are read at the end of the
+// predecessor they come from instead
+fn get_used_lines(operation: &Operation) -> LineNumbers {
+    let mut lines = match *operation {
+        Operation::Phi(_, _) => return LineNumbers::new(),
+        Operation::Bne(l, _)
+        | Operation::Beq(l, _)
+        | Operation::Ble(l, _)
+        | Operation::Blt(l, _)
+        | Operation::Bge(l, _)
+        | Operation::Bgt(l, _) => vec![l],
+        _ => operation.get_lines(),
+    };
+    // constants are not kept in registers
+    lines.retain(|line| *line > 0);
+    lines
+}
+
+// the phi operands a block passes to its successors, phi operands are in the order of the
+// incoming edges
+fn get_phi_uses(g: &BasicBlockGraph, block: NodeIndex) -> LiveSet {
+    let mut uses = LiveSet::new();
+    for successor in g.neighbors_directed(block, Outgoing) {
+        let mut incoming: Vec<_> = g.edges_directed(successor, Incoming).collect();
+        incoming.sort_by_key(|edge| edge.id());
+        let Some(position) = incoming.iter().position(|edge| edge.source() == block) else {
+            continue;
+        };
+        for instruction in &g[successor].instructions {
+            if let Operation::Phi(l, r) = instruction.operation {
+                let operand = if position == 0 { l } else { r };
+                if operand > 0 {
+                    uses.insert(operand);
                 }
             }
+        }
+    }
+    uses
+}
 
-            Operation::Cmp(l, r) => {
-                inherited_set.insert(l.clone());
-                inherited_set.insert(r.clone());
-            }
-            Operation::Write(l) | Operation::Ret(l) => {
-                inherited_set.insert(l.clone());
-            }
-            _ => {}
+// Walks a block backwards from what its successors need, handing `record` what is live before
+// and after every instruction. The phis at the start of a block are defined together, each of
+// them sees what is live after the last one. Returns what is live at the start of the block.
+fn walk_block(g: &BasicBlockGraph, block: NodeIndex, live_out: &LiveSet, mut record: impl FnMut(LineNumber, &LiveSet, &LiveSet)) -> LiveSet {
+    let mut live = live_out.clone();
+    live.extend(get_phi_uses(g, block));
+
+    let instructions = &g[block].instructions;
+    let phi_count = instructions.iter().take_while(|instruction| matches!(instruction.operation, Operation::Phi(_, _))).count();
+    for instruction in instructions[phi_count..].iter().rev() {
+        let live_after = live.clone();
+        if defines_value(&instruction.operation) {
+            live.remove(&instruction.get_line_number());
         }
+        live.extend(get_used_lines(&instruction.operation));
+        record(instruction.get_line_number(), &live, &live_after);
     }
+
+    let live_after = live.clone();
+    for instruction in &instructions[..phi_count] {
+        live.remove(&instruction.get_line_number());
+    }
+    for instruction in &instructions[..phi_count] {
+        record(instruction.get_line_number(), &live, &live_after);
+    }
+    live
+}
+
+/// The points a value is live at, as runs of consecutive points. A value that is not live in
+/// the blocks laid out between two of its uses has a hole there.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct LiveInterval {
+    pub ranges: Vec<(ProgramPoint, ProgramPoint)>,
 }
 
-fn get_def_set(b: &BasicBlock, g: &BasicBlockGraph) -> LineNumSet {
-    let mut def_set = LineNumSet::new();
-    let parents: Vec<NodeIndex> = g.neighbors_directed(b.id, Incoming).collect();
-    for (var_name, var_type) in &b.variable_table {
-        for parent in &parents {
-            let par_var_table = &g[*parent].variable_table;
+impl LiveInterval {
+    pub fn covers(&self, point: ProgramPoint) -> bool {
+        self.ranges.iter().any(|(start, end)| (*start..=*end).contains(&point))
+    }
 
-            let par_var = par_var_table.get(var_name);
-            if par_var != None && par_var.unwrap() == var_type {
-                def_set.insert(var_type.get_value());
-            }
+    pub fn overlaps(&self, other: &LiveInterval) -> bool {
+        self.ranges.iter().any(|(start, end)| other.ranges.iter().any(|(other_start, other_end)| start <= other_end && other_start <= end))
+    }
+
+    fn add(&mut self, point: ProgramPoint) {
+        match self.ranges.last_mut() {
+            Some((_, end)) if *end + 1 == point => *end = point,
+            _ => self.ranges.push((point, point)),
+        }
+    }
+}
+
+/// What is live before and after every instruction of a function. Unlike the block sets of
+/// `compute_live_sets` only values that are read later count, a value stops being live at its
+/// last use and phi operands are live on the edge they come in on.
+pub struct InstructionLiveness {
+    live_before: HashMap<LineNumber, LiveSet>,
+    live_after: HashMap<LineNumber, LiveSet>,
+    // the line at every point, and the point of every line
+    lines: LineNumbers,
+    points: HashMap<LineNumber, ProgramPoint>,
+    values: LineNumSet,
+}
+
+impl InstructionLiveness {
+    pub fn new(g: &BasicBlockGraph) -> Self {
+        let block_info = compute_live_sets(g);
+
+        let mut live_before = HashMap::new();
+        let mut live_after = HashMap::new();
+        for block in g.node_indices() {
+            walk_block(g, block, &block_info[&block].out_set, |line, before, after| {
+                live_before.insert(line, before.clone());
+                live_after.insert(line, after.clone());
+            });
         }
+
+        // the blocks without instructions that the layout leaves out have no points
+        let order = layout_blocks(g, &get_static_weights(g));
+        let instructions: Vec<&Instruction> = order.iter().flat_map(|block| &g[*block].instructions).collect();
+        let lines: LineNumbers = instructions.iter().map(|instruction| instruction.get_line_number()).collect();
+        Self {
+            live_before,
+            live_after,
+            points: lines.iter().enumerate().map(|(index, line)| (*line, 2 * index)).collect(),
+            lines,
+            values: instructions.iter()
+                .filter(|instruction| defines_value(&instruction.operation))
+                .map(|instruction| instruction.get_line_number())
+                .collect(),
+        }
+    }
+
+    pub fn get_live_before(&self, line: LineNumber) -> &LiveSet {
+        &self.live_before[&line]
+    }
+
+    pub fn get_live_after(&self, line: LineNumber) -> &LiveSet {
+        &self.live_after[&line]
     }
 
-    for ins in &b.instructions {
-        match ins.operation {
-            Operation::Cmp(_, _) => {
-                def_set.insert(ins.get_line_number());
+    /// the point where the instruction on the line reads its operands, the one after it is where
+    /// it writes its value
+    pub fn get_point(&self, line: LineNumber) -> Option<ProgramPoint> {
+        self.points.get(&line).copied()
+    }
+
+    /// The interval of every value, from the point it is defined at to its last use. A value
+    /// that is never read only covers its definition.
+    pub fn get_intervals(&self) -> HashMap<LineNumber, LiveInterval> {
+        let mut intervals: HashMap<LineNumber, LiveInterval> = HashMap::new();
+        for (index, line) in self.lines.iter().enumerate() {
+            for value in self.get_live_before(*line) {
+                intervals.entry(*value).or_default().add(2 * index);
+            }
+            let mut written = self.get_live_after(*line).clone();
+            if self.values.contains(line) {
+                written.insert(*line);
+            }
+            for value in written {
+                intervals.entry(value).or_default().add(2 * index + 1);
             }
-            _ => {}
         }
+        intervals
     }
-    def_set
 }
 
+pub type Instructions = Vec<Instruction>;
+
+/// Builds the interference graph from the live intervals, two values interfere where their
+/// intervals overlap. A constant, which is put in its register right before the instruction
+/// reading it, interferes with every value whose interval covers that point.
 pub fn get_interference_graph(g: &BasicBlockGraph) -> InterferenceGraph {
-    let block_info_map = compute_live_sets(g);
+    let liveness = InstructionLiveness::new(g);
+    let intervals = liveness.get_intervals();
+    let instructions: Vec<&Instruction> = g.node_weights().flat_map(|block| &block.instructions).collect();
 
-    let all_var_set = collect_all_variables(&block_info_map);
+    // every instruction has a node, code generation looks up a register for each line it defines
+    let mut all_var_set: LineNumSet = liveness.lines.iter().copied().collect();
+    all_var_set.extend(intervals.keys());
+    all_var_set.extend(instructions.iter().flat_map(|instruction| instruction.operation.get_lines()));
 
     let mut ig: InterferenceGraph = InterferenceGraph::new_undirected();
 
     let line_to_nodeidx_map = all_vars_node_defined(&mut ig, &all_var_set);
 
-    for binfo in block_info_map.values() {
-        // draw edges between variables in the outset and make sure that those variables are connected in the live set
-        create_set_edge_additions(
-            &mut ig,
-            &binfo.out_set,
-            &binfo.out_set,
-            &line_to_nodeidx_map,
-        );
-
-        // draw edges between variables in the inset and make sure that those variables are connected in the live set
-        create_set_edge_additions(&mut ig, &binfo.in_set, &binfo.in_set, &line_to_nodeidx_map);
-
-        // create_set_edge_additions(&mut ig, &binfo.def_set, &binfo.in_set, &line_to_nodeidx_map);
-        // create_set_edge_additions(
-        //     &mut ig,
-        //     &binfo.use_set,
-        //     &binfo.use_set,
-        //     &line_to_nodeidx_map,
-        // );
+    for (value, interval) in &intervals {
+        let overlapping: LineNumSet = intervals.iter()
+            .filter(|(other, other_interval)| value < *other && interval.overlaps(other_interval))
+            .map(|(other, _)| *other)
+            .collect();
+        create_set_edge_additions(&mut ig, &LineNumSet::from([*value]), &overlapping, &line_to_nodeidx_map);
     }
 
-    ig
-}
+    for instruction in instructions {
+        let Some(point) = liveness.get_point(instruction.get_line_number()) else {
+            continue;
+        };
+        let constants: LineNumSet = instruction.operation.get_lines().into_iter().filter(|operand| *operand <= 0).collect();
+        let live: LineNumSet = intervals.iter().filter(|(_, interval)| interval.covers(point)).map(|(value, _)| *value).collect();
+        create_set_edge_additions(&mut ig, &constants, &live, &line_to_nodeidx_map);
+    }
 
-fn collect_all_variables(map: &HashMap<NodeIndex, BlockInfo>) -> LineNumSet {
-    let mut inst_num_set = LineNumSet::new();
-    // let mut ig = InterferenceGraph::new_undirected();
-    for (ni, bi) in map {
-        for def_num in &bi.def_set {
-            inst_num_set.insert(*def_num);
-        }
-        for use_num in &bi.use_set {
-            inst_num_set.insert(*use_num);
-        }
-        for in_num in &bi.in_set {
-            inst_num_set.insert(*in_num);
-        }
-        for out_num in &bi.out_set {
-            inst_num_set.insert(*out_num);
-        }
+    // the phis of a block are all written by the same copies on the way in, used or not
+    for block in g.node_weights() {
+        let phis: LineNumSet = block.instructions.iter()
+            .filter(|instruction| matches!(instruction.operation, Operation::Phi(_, _)))
+            .map(|instruction| instruction.get_line_number())
+            .collect();
+        create_set_edge_additions(&mut ig, &phis, &phis, &line_to_nodeidx_map);
     }
 
-    inst_num_set
+    ig
 }
 
 fn all_vars_node_defined(
@@ -279,7 +336,7 @@ fn create_set_edge_additions(
         for line_num2 in set2 {
             let ni1 = line_nodeidx_map.get(line_num1);
             let ni2 = line_nodeidx_map.get(line_num2);
-            if ni1 == None || ni2 == None {
+            if ni1.is_none() || ni2.is_none() {
                 panic!("Can't actually add node as it doesnt exist in the interference graph");
             }
 
@@ -291,179 +348,71 @@ fn create_set_edge_additions(
         }
     }
 }
-fn graph_edge_additions<N>(
-    g: &mut UnGraph<N, ()>,
-    edges_to_add1: &Vec<LineNumber>,
-    edges_to_add2: &Vec<LineNumber>,
-    generic_to_nodeindex: &mut HashMap<LineNumber, NodeIndex>,
-) {
-    for node1 in edges_to_add1 {
-        for node2 in edges_to_add2 {
-            if node1 != node2 {
-                let nodeidx1 = generic_to_nodeindex.get(node1);
-                let nodeidx2 = generic_to_nodeindex.get(node2);
-                g.add_edge(*nodeidx1.unwrap(), *nodeidx2.unwrap(), ());
-            }
-        }
-    }
-}
-
 pub fn get_clusters(g: &BasicBlockGraph) -> Clusters {
     let mut clusters = Clusters::new();
 
-    for ni in g.node_indices().into_iter().rev() {
+    for ni in g.node_indices().rev() {
         let bb_vec = &g[ni].instructions;
 
         for instruction in bb_vec.iter().rev() {
-            match *instruction.get_operation_ref() {
-                Operation::Phi(l, r) => {
-                    let mut new_cluster = Cluster::new();
-                    new_cluster.push(l);
-                    new_cluster.push(r);
-                    new_cluster.push(instruction.get_line_number());
-
-                    clusters.push(new_cluster);
-                }
-                _ => {}
-            }
-        }
-    }
-    clusters
-}
-
-/// bfs through use counts and get the requisite use counts of each line number this will help for choosing the variables
-/// to put into a register if some need to get pushed out
-fn get_use_counts(
-    g: &mut BasicBlockGraph,
-    live_sets: HashMap<NodeIndex, BlockInfo>,
-) -> HashMap<LineNumber, UsgCnt> {
-    let mut res = HashMap::<LineNumber, UsgCnt>::new();
-
-    let curr_level: usize = 0;
-
-    // let curr_node = NodeIndex::new();
-
-    // let curr_bb = &g[NodeIndex::new(g.node_count() - 1)];
-    let start: &NodeIndex<u32> = &NodeIndex::new(g.node_count() - 1);
-
-    let mut frontier: VecDeque<NodeIndex> = VecDeque::new();
-
-    frontier.push_back(*start);
-
-    while frontier.is_empty() == false {
-        let curr_el = frontier.pop_front();
-        if curr_el == None {
-            panic!("WTF");
-        }
-
-        let curr_el = curr_el.unwrap();
+            if let Operation::Phi(l, r) = *instruction.get_operation_ref() {
+                let new_cluster: Cluster = vec![l, r, instruction.get_line_number()];
 
-        let parents = g.neighbors_directed(curr_el, Incoming);
-        for parent in parents {
-            frontier.push_back(parent);
-        }
-
-        let bb_mut = &mut g[curr_el];
-        for instruction in &mut bb_mut.instructions.iter().rev() {
-            let relevant_lines = instruction.get_operation_ref().get_lines();
-            for relevant_line in relevant_lines {
-                let curr = res.get_mut(&relevant_line);
-                if curr != None {
-                    *curr.unwrap() += 1;
-                } else {
-                    res.insert(relevant_line, 1);
-                }
+                clusters.push(new_cluster);
             }
         }
     }
-
-    res
+    clusters
 }
 
-
-
 pub fn get_graph_and_map (g: &InterferenceGraph, cluster_possibilities: &Clusters) -> (UpgradedInterferenceGraph, HashMap<LineNumber, NodeIndex>) {
     let mut remapped = LineNumSet::new(); 
     let (mut upgraded_ig, mut line_to_node_idx) = convert_ig_to_upgraded(g);
 
     for cluster in cluster_possibilities {
-        for (idx, line_num) in cluster.iter().enumerate() {
+        for line_num in cluster.iter() {
             let mut was_remapped: bool = false; 
             // this line not yet remapped
-            // let curr_node = 
-
-            println!("Current line num = {:?}, ni = {:?}", line_num, line_to_node_idx.get(line_num).unwrap());
-            for (idx2, line_num2) in cluster.iter().enumerate() {
-                println!();
+            for line_num2 in cluster.iter() {
                 if line_num != line_num2 && !remapped.contains(line_num) && !remapped.contains(line_num2) {
+                    // constants and values that are never live have no node to merge
+                    let (Some(&curr_ni), Some(&to_del)) = (line_to_node_idx.get(line_num), line_to_node_idx.get(line_num2)) else {
+                        continue;
+                    };
+                    // values that are live at the same time cannot share a register
+                    if curr_ni == to_del || upgraded_ig.contains_edge(curr_ni, to_del) {
+                        continue;
+                    }
+
                     was_remapped = true; 
-                    // remapped.insert(*line_num);
                     remapped.insert(*line_num2);
-                    let ug = upgraded_ig.clone();
-                    let removed_neighbors = ug.neighbors_undirected(*line_to_node_idx.get(line_num2).unwrap());
 
+                    let removed_neighbors: Vec<NodeIndex> = upgraded_ig.neighbors_undirected(to_del).collect();
                     for removed_neighbor in removed_neighbors {
-                        let curr_node = line_to_node_idx.get(line_num).unwrap();
-                        if !upgraded_ig.contains_edge(*curr_node, removed_neighbor) {
-                            upgraded_ig.add_edge(*curr_node, removed_neighbor, ());
+                        if removed_neighbor != curr_ni && !upgraded_ig.contains_edge(curr_ni, removed_neighbor) {
+                            upgraded_ig.add_edge(curr_ni, removed_neighbor, ());
                         }
                     }
-                    let mut curr_ni:Option<NodeIndex> = None; 
-                    {
-                    let curr = line_to_node_idx.get(line_num);
-                    let curr = curr.unwrap();
-                    curr_ni = Some(*curr); 
-                    }
-
-                    let mut to_del : Option<NodeIndex> = None; 
-
-                    {
-                    let for_delete = line_to_node_idx.get_mut(line_num2);
-                    let for_delete = for_delete.unwrap();
-                    to_del = Some(*for_delete);
-                    }
-
-                    println!("Curr saved node = {:?}", curr_ni);
-
-                    // let curr = line_to_node_idx.get(line_num).unwrap(); 
-                    // let node_to_remove = line_to_node_idx.get(line_num2).unwrap();
-                    if curr_ni.unwrap().index() == upgraded_ig.node_count() - 1 && to_del.unwrap().index() != upgraded_ig.node_count() - 1 {
-                        // let to_alter = line_to_node_idx.get_mut(&line_num);
-                        // let to_alter = to_alter.unwrap(); 
-                        // to_alter = &mut for_delete.clone();
-                        println!("Changing this fucking bull shit from {:?} to {:?}", curr_ni.unwrap(), to_del.unwrap());
-
-                        if curr_ni.unwrap().index() > to_del.unwrap().index() {
-
-                        line_to_node_idx.remove(line_num);
-                        line_to_node_idx.insert(*line_num, to_del.unwrap());
+                    let merged_lines = upgraded_ig[to_del].clone();
+
+                    // removing a node moves the last node into the freed index
+                    let last_ni = NodeIndex::new(upgraded_ig.node_count() - 1);
+                    upgraded_ig.remove_node(to_del);
+                    let curr_saved_node = if curr_ni == last_ni { to_del } else { curr_ni };
+
+                    for node_idx in line_to_node_idx.values_mut() {
+                        if *node_idx == to_del {
+                            *node_idx = curr_saved_node;
+                        } else if *node_idx == last_ni {
+                            *node_idx = to_del;
                         }
-
                     }
-                    
-                    
-                    
-                    let curr_saved_node: NodeIndex = *line_to_node_idx.get(line_num).unwrap(); 
-
-                    upgraded_ig.remove_node(to_del.unwrap());
-                    println!("Removing to del: {:?}", to_del.unwrap());
-                    line_to_node_idx.remove(line_num2);
-                    line_to_node_idx.insert(*line_num2, curr_saved_node);
-
-                    println!("Curr saved node = {:?}", curr_saved_node);
-
-                    // if curr_saved_node.index() == upgraded_ig.node_count() {
-                    //     line_to_node_idx.remove(line_num);
-                    //     line_to_node_idx.insert(*line_num, to_del.unwrap()); 
-                    // }
-
 
                     let cluster_to_change = &mut upgraded_ig[curr_saved_node];
-                    cluster_to_change.push(*line_num2);
-
+                    cluster_to_change.extend(merged_lines);
                 }
             }
-            if was_remapped == true {
+            if was_remapped {
                 remapped.insert(*line_num);
             }
         }
@@ -482,39 +431,7 @@ pub type UpgradedInterferenceGraph = UnGraph<Cluster, ()>;
 
    
 pub fn get_upgraded_interference_graph (g: &InterferenceGraph, cluster_possibilities: &Clusters) -> UpgradedInterferenceGraph {
-    let mut remapped = LineNumSet::new(); 
-    let (mut upgraded_ig, mut line_to_node_idx) = convert_ig_to_upgraded(g);
-
-    for cluster in cluster_possibilities {
-        for (idx, line_num) in cluster.iter().enumerate() {
-            // this line not yet remapped
-            for (idx2, line_num2) in cluster.iter().enumerate() {
-                if line_num != line_num2
-                    && !remapped.contains(line_num)
-                    && !remapped.contains(line_num2)
-                {
-                    remapped.insert(*line_num);
-                    remapped.insert(*line_num2);
-                    let removed_neighbors =
-                        upgraded_ig.neighbors_undirected(*line_to_node_idx.get(line_num2).unwrap());
-
-                    upgraded_ig.remove_node(*line_to_node_idx.get(line_num2).unwrap());
-                    line_to_node_idx.remove(line_num2);
-                    let curr_saved_node: NodeIndex = *line_to_node_idx.get(line_num).unwrap();
-                    line_to_node_idx.insert(*line_num2, curr_saved_node);
-
-                    let cluster_to_change = &mut upgraded_ig[curr_saved_node];
-                    cluster_to_change.push(*line_num2);
-                }
-            }
-        }
-    }
-
-    // for cluster in cluster_possibilities {
-
-    // }
-
-    upgraded_ig
+    get_graph_and_map(g, cluster_possibilities).0
 }
 
 fn convert_ig_to_upgraded(
@@ -553,82 +470,6 @@ fn convert_ig_to_upgraded(
     (upgraded_ig, line_to_nodeidx)
 }
 
-fn mark_graph_with_layers_helper(
-    g: &BasicBlockGraph,
-    start_node: NodeIndex,
-    layers_map: &mut HashMap<NodeIndex, Layer>,
-    curr_layer: &mut Layer,
-    lower_layer_idx: Option<NodeIndex>,
-) {
-    let mut frontier: VecDeque<NodeIndex> = VecDeque::new();
-    frontier.push_back(start_node);
-
-    while !frontier.is_empty() {
-        let curr_ni = frontier.pop_front();
-        let curr_ni = curr_ni.unwrap();
-        let curr_bb_type = &g[curr_ni].block_type;
-
-        layers_map.insert(curr_ni, *curr_layer);
-
-        match curr_bb_type {
-            BasicBlockType::Follow => {
-                // go plus and set the starting point to the end
-                let assoc_conditional = *g
-                    .neighbors_directed(curr_ni, Incoming)
-                    .peekable()
-                    .peek()
-                    .unwrap();
-
-                *curr_layer += 1;
-
-                // recursive solution here abuses the fuck out of the fact that a graph is also a one way tree in this case as we
-                // are isolating for the actual loop blocks
-                mark_graph_with_layers_helper(
-                    g,
-                    start_node,
-                    layers_map,
-                    curr_layer,
-                    Some(assoc_conditional),
-                );
-            }
-            _ => {}
-        }
-
-        if lower_layer_idx != None && lower_layer_idx.unwrap() == curr_ni {
-            *curr_layer -= 1;
-            // just continue as normal going upwards here no reason to change anything.
-        }
-    }
-}
-
-type Layer = usize;
-fn mark_graph_with_layers(g: &BasicBlockGraph) -> HashMap<NodeIndex, Layer> {
-    let mut ret_map = HashMap::<NodeIndex, Layer>::new();
-    let mut curr_layer: Layer = 0;
-
-    let start: &NodeIndex<u32> = &NodeIndex::new(g.node_count() - 1);
-
-    let mut frontier: VecDeque<NodeIndex> = VecDeque::new();
-
-    frontier.push_back(*start);
-
-    while frontier.is_empty() == false {
-        let curr_el = frontier.pop_front();
-        if curr_el == None {
-            panic!("WTF");
-        }
-
-        let curr_el = curr_el.unwrap();
-
-        let parents = g.neighbors_directed(curr_el, Incoming);
-        for parent in parents {
-            frontier.push_back(parent);
-        }
-    }
-
-    ret_map
-}
-
 #[cfg(test)]
 mod live_anal_tests {
     use super::*;
@@ -654,10 +495,7 @@ mod live_anal_tests {
 
         let mut parser = Parser::new(input);
 
-        let line_number = parser.parse_computation();
-
-        // Verify that the add operation is correct
-        let instructions = &parser.internal_program.get_curr_block().instructions;
+        parser.parse_computation();
 
         let bbg = &parser.internal_program.get_curr_fn().bb_graph;
         println!("{}", generate_dot_viz("main", &parser.internal_program));
@@ -665,7 +503,7 @@ mod live_anal_tests {
         let graph = get_interference_graph(&parser.internal_program.get_curr_fn().bb_graph);
         println!("{:?}", Dot::with_config(&graph, &[Config::EdgeNoLabel]));
 
-        let cluster_possibilities = get_clusters(&bbg);
+        let cluster_possibilities = get_clusters(bbg);
         let upgraded_ig = get_upgraded_interference_graph(&graph, &cluster_possibilities);
 
         println!(
@@ -673,4 +511,146 @@ mod live_anal_tests {
             Dot::with_config(&upgraded_ig, &[Config::EdgeNoLabel])
         );
     }
+
+    // the fixed point as compute_live_sets found it before it was built on the dataflow solver
+    fn get_live_sets_by_iteration(g: &BasicBlockGraph, block_info: &HashMap<NodeIndex, BlockInfo>) -> HashMap<NodeIndex, (LiveSet, LiveSet)> {
+        let mut sets: HashMap<NodeIndex, (LiveSet, LiveSet)> = g.node_indices().map(|node_index| (node_index, Default::default())).collect();
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for node_index in g.node_indices().rev() {
+                let mut new_out_set = LiveSet::new();
+                for successor in g.neighbors_directed(node_index, Outgoing) {
+                    new_out_set.extend(&sets[&successor].0);
+                }
+                let mut new_in_set = block_info[&node_index].use_set.clone();
+                new_in_set.extend(new_out_set.iter().filter(|var| !block_info[&node_index].def_set.contains(var)));
+                if sets[&node_index] != (new_in_set.clone(), new_out_set.clone()) {
+                    sets.insert(node_index, (new_in_set, new_out_set));
+                    changed = true;
+                }
+            }
+        }
+        sets
+    }
+
+    #[test]
+    fn test_live_sets_match_iteration() {
+        for input in [
+            "main var a, b; { let a <- call InputNum(); if a > 2 then let b <- a + 1 else let b <- a * 2 fi; call OutputNum(b + a) }.",
+            "main var i, j, s; { let i <- 0; let s <- 0; while i < 4 do let j <- 0; while j < i do let s <- s + j; let j <- j + 1 od; let i <- i + 1 od; call OutputNum(s) }.",
+            "main var a; function twice(x); { return x * 2 }; { let a <- call twice(call InputNum()); if a > 4 then call OutputNum(a) fi }.",
+        ] {
+            let mut parser = Parser::new(input.to_string());
+            parser.parse_computation();
+            for function in parser.internal_program.functions.values() {
+                let g = &function.bb_graph;
+                let block_info = compute_live_sets(g);
+                let expected = get_live_sets_by_iteration(g, &block_info);
+                for (node_index, info) in &block_info {
+                    assert_eq!((&info.in_set, &info.out_set), (&expected[node_index].0, &expected[node_index].1), "{} BB{}", function.name, node_index.index());
+                }
+            }
+        }
+    }
+
+    fn get_graph(input: &str) -> BasicBlockGraph {
+        let mut parser = Parser::new(input.to_string());
+        parser.parse_computation();
+        parser.internal_program.get_fn("main").bb_graph.clone()
+    }
+
+    fn find_lines(g: &BasicBlockGraph, matches: impl Fn(&Operation) -> bool) -> LineNumbers {
+        g.node_weights()
+            .flat_map(|block| &block.instructions)
+            .filter(|instruction| matches(&instruction.operation))
+            .map(|instruction| instruction.get_line_number())
+            .collect()
+    }
+
+    #[test]
+    fn test_instruction_liveness() {
+        let g = get_graph("main var a, b, c; { let a <- call InputNum(); let b <- call InputNum(); let c <- a + b; call OutputNum(c); call OutputNum(a) }.");
+        let liveness = InstructionLiveness::new(&g);
+        let [a, b] = find_lines(&g, |operation| *operation == Operation::Read)[..] else { panic!() };
+        let [c] = find_lines(&g, |operation| matches!(operation, Operation::Add(_, _)))[..] else { panic!() };
+        let [write_c, write_a] = find_lines(&g, |operation| matches!(operation, Operation::Write(_)))[..] else { panic!() };
+
+        assert_eq!(liveness.get_live_after(a), &LiveSet::from([a]));
+        assert_eq!(liveness.get_live_before(c), &LiveSet::from([a, b]));
+        // b dies at the addition, a lives on to the last write
+        assert_eq!(liveness.get_live_after(c), &LiveSet::from([a, c]));
+        assert_eq!(liveness.get_live_after(write_c), &LiveSet::from([a]));
+        assert!(liveness.get_live_after(write_a).is_empty());
+
+        // values start where they are written and end where they are last read
+        let intervals = liveness.get_intervals();
+        let point = |line| liveness.get_point(line).unwrap();
+        assert_eq!(intervals[&a].ranges, vec![(point(a) + 1, point(write_a))]);
+        assert_eq!(intervals[&b].ranges, vec![(point(b) + 1, point(c))]);
+        assert_eq!(intervals[&c].ranges, vec![(point(c) + 1, point(write_c))]);
+        assert!(!intervals[&b].overlaps(&intervals[&c]));
+        assert!(!intervals[&b].covers(point(write_c)));
+    }
+
+    #[test]
+    fn test_phi_operands_live_on_edges() {
+        let g = get_graph("main var a, b; { let a <- call InputNum(); if a > 2 then let b <- a + 1 else let b <- a * 2 fi; call OutputNum(b) }.");
+        let liveness = InstructionLiveness::new(&g);
+        let [addition] = find_lines(&g, |operation| matches!(operation, Operation::Add(_, _)))[..] else { panic!() };
+        let [multiplication] = find_lines(&g, |operation| matches!(operation, Operation::Mul(_, _)))[..] else { panic!() };
+        let [phi] = find_lines(&g, |operation| matches!(operation, Operation::Phi(_, _)))[..] else { panic!() };
+
+        // each operand is only live on its own side of the if
+        assert!(!liveness.get_live_before(phi).contains(&addition));
+        assert!(!liveness.get_live_before(phi).contains(&multiplication));
+        assert_eq!(liveness.get_live_after(phi), &LiveSet::from([phi]));
+        let intervals = liveness.get_intervals();
+        assert!(!intervals[&addition].overlaps(&intervals[&multiplication]));
+    }
+
+    #[test]
+    fn test_loop_interval() {
+        let g = get_graph("main var i, a; { let a <- call InputNum(); let i <- 0; while i < a do let i <- i + 1 od; call OutputNum(i) }.");
+        let liveness = InstructionLiveness::new(&g);
+        let [a] = find_lines(&g, |operation| *operation == Operation::Read)[..] else { panic!() };
+        let [increment] = find_lines(&g, |operation| matches!(operation, Operation::Add(_, _)))[..] else { panic!() };
+
+        // a is read in the loop header, so it is live all the way through the body
+        let intervals = liveness.get_intervals();
+        assert!(intervals[&a].covers(liveness.get_point(increment).unwrap()));
+        assert!(liveness.get_live_after(increment).contains(&a));
+    }
+
+    #[test]
+    fn test_points_follow_layout() {
+        let g = get_graph("main var a; { let a <- call InputNum(); if a > 2 then let a <- a - 1 else let a <- a + 3 fi; call OutputNum(a) }.");
+        let order = layout_blocks(&g, &get_static_weights(&g));
+        let index_order: Vec<NodeIndex> = g.node_indices().filter(|block| !g[*block].instructions.is_empty()).collect();
+        assert_ne!(order, index_order);
+
+        // the points count up through the blocks as code generation emits them
+        let liveness = InstructionLiveness::new(&g);
+        let lines: LineNumbers = order.iter().flat_map(|block| &g[*block].instructions).map(|instruction| instruction.get_line_number()).collect();
+        for (index, line) in lines.iter().enumerate() {
+            assert_eq!(liveness.get_point(*line), Some(2 * index));
+        }
+        assert_eq!(liveness.get_point(lines.iter().max().unwrap() + 1), None);
+    }
+
+    #[test]
+    fn test_interference_within_block() {
+        // x is dead before y is read, the block sets had both live in the same block
+        let g = get_graph("main var x, y; { let x <- call InputNum(); call OutputNum(x); let y <- call InputNum(); call OutputNum(y + x) }.");
+        let [x, y] = find_lines(&g, |operation| *operation == Operation::Read)[..] else { panic!() };
+        let graph = get_interference_graph(&g);
+        let node = |line| graph.node_indices().find(|node| graph[*node] == line).unwrap();
+        assert!(graph.contains_edge(node(x), node(y)));
+
+        let g = get_graph("main var x, y; { let x <- call InputNum(); call OutputNum(x); let y <- call InputNum(); call OutputNum(y) }.");
+        let [x, y] = find_lines(&g, |operation| *operation == Operation::Read)[..] else { panic!() };
+        let graph = get_interference_graph(&g);
+        let node = |line| graph.node_indices().find(|node| graph[*node] == line).unwrap();
+        assert!(!graph.contains_edge(node(x), node(y)));
+    }
 }
@@ -0,0 +1,96 @@
+use std::collections::HashSet;
+
+use petgraph::graph::NodeIndex;
+use petgraph::visit::EdgeRef;
+use petgraph::Direction::Incoming;
+
+use crate::dataflow::{solve, Analysis, Direction, IntersectionSet};
+use crate::live_analysis::BasicBlockGraph;
+
+/// A natural loop: the header every iteration starts at, the block whose edge goes back to
+/// it and every block in between, header and latch included.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Loop {
+    pub header: NodeIndex,
+    pub latch: NodeIndex,
+    pub blocks: HashSet<NodeIndex>,
+}
+
+impl Loop {
+    pub fn contains(&self, block: NodeIndex) -> bool {
+        self.blocks.contains(&block)
+    }
+
+    /// the predecessor of the header that is outside the loop, where control enters it
+    pub fn get_preheader(&self, g: &BasicBlockGraph) -> Option<NodeIndex> {
+        g.neighbors_directed(self.header, Incoming).find(|block| !self.contains(*block))
+    }
+}
+
+// the blocks every path from the entry to a block goes through, the block itself included
+struct Dominators;
+
+impl Analysis for Dominators {
+    type Fact = IntersectionSet<NodeIndex>;
+    const DIRECTION: Direction = Direction::Forward;
+
+    fn get_boundary(&self, _g: &BasicBlockGraph) -> Self::Fact {
+        IntersectionSet::Of(HashSet::new())
+    }
+
+    fn transfer(&self, _g: &BasicBlockGraph, block: NodeIndex, fact: &Self::Fact) -> Self::Fact {
+        match fact {
+            IntersectionSet::All => IntersectionSet::All,
+            IntersectionSet::Of(blocks) => IntersectionSet::Of(blocks.iter().copied().chain([block]).collect()),
+        }
+    }
+}
+
+/// Finds the loops from the back edges, the edges that go to a block dominating their source,
+/// outer loops before the loops nested in them.
+pub fn find_loops(g: &BasicBlockGraph) -> Vec<Loop> {
+    let dominators = solve(g, &Dominators).out_facts;
+
+    // blocks the entry does not reach are dominated by everything and have no loops
+    let back_edges = g.edge_references()
+        .filter(|edge| dominators[&edge.source()] != IntersectionSet::All && dominators[&edge.source()].contains(&edge.target()))
+        .map(|edge| (edge.source(), edge.target()));
+
+    // a loop is its header plus everything that reaches the back edge without passing the header
+    let mut loops: Vec<Loop> = back_edges.map(|(latch, header)| {
+        let mut blocks = HashSet::from([header]);
+        let mut work = vec![latch];
+        while let Some(block) = work.pop() {
+            if blocks.insert(block) {
+                work.extend(g.neighbors_directed(block, Incoming));
+            }
+        }
+        Loop { header, latch, blocks }
+    }).collect();
+
+    loops.sort_by(|a, b| b.blocks.len().cmp(&a.blocks.len()).then(a.header.cmp(&b.header)));
+    loops
+}
+
+#[cfg(test)]
+mod loop_analysis_tests {
+    use super::*;
+    use crate::parser::Parser;
+
+    #[test]
+    fn test_nested_loops() {
+        let input = "main var i, j; { let i <- 0; while i < 3 do let j <- 0; while j < 3 do let j <- j + 1 od; let i <- i + 1 od; call OutputNum(i) }.";
+        let mut parser = Parser::new(input.to_string());
+        parser.parse_computation();
+        let graph = &parser.internal_program.get_fn("main").bb_graph;
+
+        let loops = find_loops(graph);
+        assert_eq!(loops.len(), 2);
+        let (outer, inner) = (&loops[0], &loops[1]);
+        assert!(inner.blocks.is_subset(&outer.blocks));
+        assert!(outer.contains(inner.header));
+        assert!(!inner.contains(outer.header));
+        assert_eq!(outer.get_preheader(graph), Some(NodeIndex::new(0)));
+        assert!(graph.contains_edge(inner.latch, inner.header));
+    }
+}
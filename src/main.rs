@@ -7,16 +7,340 @@ mod constant_block;
 mod parser;
 mod dot_viz;
 mod dominator_table;
+mod dataflow;
 mod live_analysis;
-mod cfg_traversal;
+mod loop_analysis;
 mod register_allocation;
 mod code_gen;
+mod block_layout;
+mod strength_reduction;
+mod algebraic_simplification;
+mod value_numbering;
+mod phi_elimination;
+mod cfg_simplification;
+mod inliner;
+mod peephole;
+mod pass_manager;
 mod assembler;
+mod assembly_text;
+mod diagnostic;
+mod semantic;
+mod lint;
+mod emulator;
+mod object_file;
+mod linker;
+mod interpreter;
+#[cfg(test)]
+mod test_helpers;
+
+use crate::assembler::{disassemble, get_machine_code_instructions};
+use crate::assembly_text::{format_assembly, parse_assembly};
+use crate::code_gen::AssemblyItem;
+use crate::dot_viz::generate_dot_viz;
+use crate::emulator::{Emulator, EmulatorError};
+use crate::inliner::InlineConfig;
+use crate::interpreter::Interpreter;
+use crate::linker::{generate_functions, link, link_with_profile, Profile};
+use crate::lint::{Lint, LintConfig, run_lints};
+use crate::object_file::ObjectFile;
+use crate::parser::Parser;
+use crate::pass_manager::{run_passes, PassConfig};
+use crate::peephole::optimize_object;
+use crate::semantic::SemanticAnalyzer;
+use std::io::{BufRead, Write};
+use std::{env, fs, io, process};
 
-// use crate::parser::Parser;
+const USAGE: &str = "usage: tiny-compiler [--enable-lint=<lint>] [--disable-lint=<lint>] [-O] [--inline] [--inline-size=<n>] \
+[--inline-recursion=<n>] [--simplify-cfg] [--simplify-algebra] [--number-values] [--eliminate-phis] [--reduce-strength] \
+[--rewrite-exit-tests] [--peephole] [--pass-statistics] [--profile-input=<file>] [--instruction-limit=<n>] [--interpret] [--run] \
+[--emit-asm] [--disassemble] [--emit-object=<file>] [--run-asm] [--run-object] <source file>";
 
 fn main() {
+    let mut path = None;
+    let mut lint_config = LintConfig::default();
+    let mut pass_config = PassConfig::default();
+    let mut pass_statistics = false;
+    let mut interpret = false;
+    let mut run = false;
+    let mut emit_asm = false;
+    let mut disassemble_image = false;
+    let mut run_asm = false;
+    let mut emit_object = None;
+    let mut run_object = false;
+    let mut profile_input = None;
+    let mut instruction_limit = None;
+
+    for argument in env::args().skip(1) {
+        if let Some(lint_name) = argument.strip_prefix("--enable-lint=") {
+            set_lint(&mut lint_config, lint_name, true);
+        } else if let Some(lint_name) = argument.strip_prefix("--disable-lint=") {
+            set_lint(&mut lint_config, lint_name, false);
+        } else if set_pass(&mut pass_config, &argument) {
+        } else if argument == "--pass-statistics" {
+            pass_statistics = true;
+        } else if argument == "--interpret" {
+            interpret = true;
+        } else if argument == "--run" {
+            run = true;
+        } else if argument == "--emit-asm" {
+            emit_asm = true;
+        } else if argument == "--disassemble" {
+            disassemble_image = true;
+        } else if argument == "--run-asm" {
+            run_asm = true;
+        } else if let Some(object_path) = argument.strip_prefix("--emit-object=") {
+            emit_object = Some(object_path.to_string());
+        } else if argument == "--run-object" {
+            run_object = true;
+        } else if let Some(input_path) = argument.strip_prefix("--profile-input=") {
+            profile_input = Some(input_path.to_string());
+        } else if let Some(limit) = argument.strip_prefix("--instruction-limit=") {
+            instruction_limit = Some(parse_number(&argument, limit));
+        } else if argument.starts_with('-') || path.is_some() {
+            eprintln!("{}", USAGE);
+            process::exit(2);
+        } else {
+            path = Some(argument);
+        }
+    }
+
+    let Some(path) = path else {
+        eprintln!("{}", USAGE);
+        process::exit(2);
+    };
+
+    // the file holds an image written by --emit-object, which is run on stdin and stdout
+    if run_object {
+        let object = fs::File::open(&path).map_err(|error| error.to_string())
+            .and_then(|mut file| ObjectFile::read_from(&mut file).map_err(|error| error.to_string()))
+            .unwrap_or_else(|error| {
+                eprintln!("cannot read {}: {}", path, error);
+                process::exit(2);
+            });
+        run_emulator(&path, Emulator::from_object_file(&object, io::stdin().lock(), io::stdout().lock()), instruction_limit);
+        return;
+    }
+
+    let input = fs::read_to_string(&path).unwrap_or_else(|error| {
+        eprintln!("cannot read {}: {}", path, error);
+        process::exit(2);
+    });
+
+    // the source file holds DLX assembly, which is assembled and run on stdin and stdout
+    if run_asm {
+        let code = parse_assembly(&input).map_err(|error| error.to_string())
+            .and_then(|instructions| get_machine_code_instructions(instructions).map_err(|error| error.to_string()))
+            .unwrap_or_else(|error| {
+                eprintln!("{}: {}", path, error);
+                process::exit(1);
+            });
+        run_emulator(&path, Emulator::new(&code, io::stdin().lock(), io::stdout().lock()), instruction_limit);
+        return;
+    }
+
+    // names are resolved before any IR is built
+    let mut analyzer = SemanticAnalyzer::new(input.clone());
+    analyzer.analyze();
+    for diagnostic in &analyzer.diagnostics {
+        eprintln!("{}:{}", path, diagnostic);
+    }
+    if analyzer.has_errors() {
+        process::exit(1);
+    }
+
+    let mut parser = Parser::new(input);
+    parser.parse_computation();
+    for diagnostic in &parser.diagnostics {
+        eprintln!("{}:{}", path, diagnostic);
+    }
+    if parser.has_errors() {
+        process::exit(1);
+    }
+
+    for lint in run_lints(&lint_config, &analyzer, &parser.internal_program) {
+        eprintln!("{}:{}", path, lint);
+    }
+
+    let statistics = run_passes(&mut parser.internal_program, &pass_config);
+    if pass_statistics {
+        eprint!("{}", statistics);
+    }
+
+    // runs the IR on stdin and stdout instead of printing it
+    if interpret {
+        let mut interpreter = Interpreter::new(&parser.internal_program, io::stdin().lock(), io::stdout().lock());
+        if let Some(instruction_limit) = instruction_limit {
+            interpreter.set_instruction_limit(instruction_limit);
+        }
+        if let Err(error) = interpreter.run() {
+            eprintln!("{}: {}", path, error);
+            process::exit(1);
+        }
+        return;
+    }
+
+    // the program is linked and run on the profile input first, so that every function has its
+    // blocks placed by how often they ran
+    let profiled = profile_input.map(|input_path| get_profile(&path, &input_path, &parser, instruction_limit));
+    let profile = profiled.as_ref().map(|(object, counts)| Profile {
+        line_table: object.line_table.as_deref().unwrap_or_default(),
+        counts,
+    });
+
+    // prints the code of the linked program, as assembly text or as a listing of the image
+    if emit_asm {
+        let functions = generate_functions(&parser.internal_program, profile.as_ref()).unwrap_or_else(|error| {
+            eprintln!("{}: {}", path, error);
+            process::exit(1);
+        });
+        let items: Vec<&[AssemblyItem]> = functions.iter().map(|(_, items)| items.as_slice()).collect();
+        print!("{}", format_assembly(&items));
+        return;
+    }
+    if disassemble_image {
+        let object = get_image(&path, &parser, &pass_config, pass_statistics, profile.as_ref());
+        print!("{}", disassemble(&object.code));
+        return;
+    }
 
+    // writes the linked image for --run-object
+    if let Some(object_path) = emit_object {
+        let object = get_image(&path, &parser, &pass_config, pass_statistics, profile.as_ref());
+        if let Err(error) = fs::File::create(&object_path).and_then(|mut file| object.write_to(&mut file)) {
+            eprintln!("cannot write {}: {}", object_path, error);
+            process::exit(1);
+        }
+        return;
+    }
+
+    // runs the linked image on stdin and stdout
+    if run {
+        let object = get_image(&path, &parser, &pass_config, pass_statistics, profile.as_ref());
+        run_emulator(&path, Emulator::from_object_file(&object, io::stdin().lock(), io::stdout().lock()), instruction_limit);
+        return;
+    }
+
+    let mut function_names: Vec<&String> = analyzer.symbol_table.function_scopes.keys().collect();
+    function_names.sort();
+    for function_name in function_names {
+        println!("{}", generate_dot_viz(function_name, &parser.internal_program));
+    }
+    println!("{}", generate_dot_viz("main", &parser.internal_program));
+}
+
+// runs machine code on stdin and stdout, reporting the error it stops with
+fn run_emulator<R: BufRead, W: Write>(path: &str, emulator: Result<Emulator<R, W>, EmulatorError>, instruction_limit: Option<usize>) {
+    let result = emulator.and_then(|mut emulator| {
+        if let Some(instruction_limit) = instruction_limit {
+            emulator.set_instruction_limit(instruction_limit);
+        }
+        emulator.run()
+    });
+    if let Err(error) = result {
+        eprintln!("{}: {}", path, error);
+        process::exit(1);
+    }
+}
+
+// links the program with static block weights and counts how often every word of the image
+// runs on the contents of the input file, throwing away what the program writes
+fn get_profile(path: &str, input_path: &str, parser: &Parser, instruction_limit: Option<usize>) -> (ObjectFile, Vec<u64>) {
+    let input = fs::read(input_path).unwrap_or_else(|error| {
+        eprintln!("cannot read {}: {}", input_path, error);
+        process::exit(2);
+    });
+    let object = link(&parser.internal_program).unwrap_or_else(|error| {
+        eprintln!("{}: {}", path, error);
+        process::exit(1);
+    });
+
+    let result = Emulator::from_object_file(&object, input.as_slice(), io::sink()).and_then(|mut emulator| {
+        emulator.enable_profiling();
+        if let Some(instruction_limit) = instruction_limit {
+            emulator.set_instruction_limit(instruction_limit);
+        }
+        emulator.run()?;
+        Ok(emulator.get_profile().unwrap_or_default().to_vec())
+    });
+    let counts = result.unwrap_or_else(|error| {
+        eprintln!("{}: profiling on {}: {}", path, input_path, error);
+        process::exit(1);
+    });
+    (object, counts)
+}
+
+// links the program, running the peephole rules over the image when they are enabled
+fn get_image(path: &str, parser: &Parser, pass_config: &PassConfig, pass_statistics: bool, profile: Option<&Profile>) -> ObjectFile {
+    let object = match profile {
+        Some(profile) => link_with_profile(&parser.internal_program, profile),
+        None => link(&parser.internal_program),
+    };
+    let object = object.unwrap_or_else(|error| {
+        eprintln!("{}: {}", path, error);
+        process::exit(1);
+    });
+    if !pass_config.peephole {
+        return object;
+    }
+
+    let (object, statistics) = optimize_object(&object).unwrap_or_else(|error| {
+        eprintln!("{}: {}", path, error);
+        process::exit(1);
+    });
+    if pass_statistics {
+        eprint!("{}", statistics);
+    }
+    object
+}
+
+// switches on the pass an argument names, false if it names none
+fn set_pass(pass_config: &mut PassConfig, argument: &str) -> bool {
+    match argument {
+        // everything that keeps the result the same, with the inline settings given so far
+        "-O" => *pass_config = PassConfig { inline: pass_config.inline.or(Some(InlineConfig::default())), ..PassConfig::all() },
+        "--inline" => pass_config.inline = pass_config.inline.or(Some(InlineConfig::default())),
+        "--simplify-cfg" => pass_config.simplify_cfg = true,
+        "--simplify-algebra" => pass_config.simplify_algebra = true,
+        "--number-values" => pass_config.number_values = true,
+        "--eliminate-phis" => pass_config.eliminate_phis = true,
+        "--reduce-strength" => pass_config.reduce_strength = true,
+        "--rewrite-exit-tests" => pass_config.rewrite_exit_tests = true,
+        "--peephole" => pass_config.peephole = true,
+        _ => {
+            if let Some(size) = argument.strip_prefix("--inline-size=") {
+                pass_config.inline.get_or_insert_with(InlineConfig::default).size_threshold = parse_number(argument, size);
+            } else if let Some(depth) = argument.strip_prefix("--inline-recursion=") {
+                pass_config.inline.get_or_insert_with(InlineConfig::default).recursion_depth = parse_number(argument, depth);
+            } else {
+                return false;
+            }
+        },
+    }
+    true
+}
+
+fn parse_number(argument: &str, number: &str) -> usize {
+    number.parse().unwrap_or_else(|_| {
+        eprintln!("{} expects a number, got {}", argument, number);
+        process::exit(2);
+    })
+}
+
+// "all" switches every lint at once
+fn set_lint(lint_config: &mut LintConfig, lint_name: &str, enabled: bool) {
+    let lints = match (lint_name, Lint::from_name(lint_name)) {
+        ("all", _) => Lint::ALL.to_vec(),
+        (_, Some(lint)) => vec![lint],
+        (_, None) => {
+            let lint_names: Vec<&str> = Lint::ALL.iter().map(|lint| lint.get_name()).collect();
+            eprintln!("unknown lint {}, expected all or one of {}", lint_name, lint_names.join(", "));
+            process::exit(2);
+        },
+    };
+
+    for lint in lints {
+        lint_config.set_enabled(lint, enabled);
+    }
 }
 
 #[cfg(test)]
@@ -51,6 +375,6 @@ mod parser_tests {
 
         // Add assertions as needed to verify the live sets
         // For example, check the IN and OUT sets for the specific basic blocks
-        assert!(live_sets.len() > 0); // Ensure there are live sets computed
+        assert!(!live_sets.is_empty()); // Ensure there are live sets computed
     }
 }
@@ -0,0 +1,245 @@
+use crate::assembler::{MachineCodeInstruction, MachineCodeInstructions};
+use std::fmt;
+use std::io::{self, Read, Write};
+
+/// the first four bytes of every object file
+pub const MAGIC: [u8; 4] = *b"TNYO";
+pub const VERSION: u32 = 1;
+
+// header flag bits
+const HAS_LINE_TABLE: u32 = 1;
+
+/// A function and the byte address its code starts at.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ObjectSymbol {
+    pub name: String,
+    pub address: u32,
+}
+
+/// The SSA line the code from a byte address on was generated from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LineEntry {
+    pub address: u32,
+    pub line: i32,
+}
+
+/// A compiled program as it is stored on disk. All numbers are little endian u32 words:
+/// magic, version, flags, entry point, then the code, data, symbol and line sections, each
+/// starting with its length. The code is loaded at address 0 and the data words sit directly
+/// below the global pointer, the last one at GP-4, which is where the globals live.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ObjectFile {
+    /// byte address execution starts at
+    pub entry_point: u32,
+    pub code: MachineCodeInstructions,
+    pub data: Vec<i32>,
+    pub symbols: Vec<ObjectSymbol>,
+    pub line_table: Option<Vec<LineEntry>>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ObjectFileError {
+    BadMagic([u8; 4]),
+    UnsupportedVersion(u32),
+    Truncated,
+    InvalidSymbolName,
+    Io(String),
+}
+
+impl fmt::Display for ObjectFileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ObjectFileError::BadMagic(magic) => write!(f, "not an object file, it starts with {:02x?}", magic),
+            ObjectFileError::UnsupportedVersion(version) => write!(f, "object file version {} is not supported, expected {}", version, VERSION),
+            ObjectFileError::Truncated => write!(f, "object file ends in the middle of a section"),
+            ObjectFileError::InvalidSymbolName => write!(f, "object file has a symbol name that is not UTF-8"),
+            ObjectFileError::Io(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl ObjectFile {
+    #[cfg(test)]
+    pub fn get_symbol(&self, name: &str) -> Option<&ObjectSymbol> {
+        self.symbols.iter().find(|symbol| symbol.name == name)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        let mut push = |word: u32| bytes.extend_from_slice(&word.to_le_bytes());
+
+        push(VERSION);
+        push(if self.line_table.is_some() { HAS_LINE_TABLE } else { 0 });
+        push(self.entry_point);
+
+        push(self.code.len() as u32);
+        self.code.iter().for_each(|word| push(*word));
+
+        push(self.data.len() as u32);
+        self.data.iter().for_each(|word| push(*word as u32));
+
+        push(self.symbols.len() as u32);
+        for symbol in &self.symbols {
+            push(symbol.address);
+            push(symbol.name.len() as u32);
+            // names are padded with zeros to a whole number of words
+            for chunk in symbol.name.as_bytes().chunks(4) {
+                let mut word = [0; 4];
+                word[..chunk.len()].copy_from_slice(chunk);
+                push(u32::from_le_bytes(word));
+            }
+        }
+
+        if let Some(line_table) = &self.line_table {
+            push(line_table.len() as u32);
+            for entry in line_table {
+                push(entry.address);
+                push(entry.line as u32);
+            }
+        }
+
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ObjectFileError> {
+        let mut words = WordReader { bytes, position: 0 };
+
+        let magic: [u8; 4] = bytes.get(..4).ok_or(ObjectFileError::Truncated)?.try_into().unwrap();
+        if magic != MAGIC {
+            return Err(ObjectFileError::BadMagic(magic));
+        }
+        words.position = 4;
+
+        let version = words.next()?;
+        if version != VERSION {
+            return Err(ObjectFileError::UnsupportedVersion(version));
+        }
+        let flags = words.next()?;
+        let entry_point = words.next()?;
+
+        let code_length = words.next()?;
+        let code = (0..code_length).map(|_| words.next()).collect::<Result<MachineCodeInstructions, _>>()?;
+
+        let data_length = words.next()?;
+        let data = (0..data_length).map(|_| words.next().map(|word| word as i32)).collect::<Result<Vec<_>, _>>()?;
+
+        let symbol_count = words.next()?;
+        let mut symbols = Vec::new();
+        for _ in 0..symbol_count {
+            let address = words.next()?;
+            let name_length = words.next()? as usize;
+            let mut name = Vec::new();
+            for _ in 0..name_length.div_ceil(4) {
+                name.extend_from_slice(&words.next()?.to_le_bytes());
+            }
+            name.truncate(name_length);
+            let name = String::from_utf8(name).map_err(|_| ObjectFileError::InvalidSymbolName)?;
+            symbols.push(ObjectSymbol { name, address });
+        }
+
+        let line_table = if flags & HAS_LINE_TABLE != 0 {
+            let entry_count = words.next()?;
+            let entries = (0..entry_count).map(|_| Ok(LineEntry { address: words.next()?, line: words.next()? as i32 }));
+            Some(entries.collect::<Result<Vec<_>, ObjectFileError>>()?)
+        } else {
+            None
+        };
+
+        Ok(Self { entry_point, code, data, symbols, line_table })
+    }
+
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(&self.to_bytes())
+    }
+
+    pub fn read_from<R: Read>(reader: &mut R) -> Result<Self, ObjectFileError> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).map_err(|error| ObjectFileError::Io(error.to_string()))?;
+        Self::from_bytes(&bytes)
+    }
+}
+
+// reads little endian words one after the other
+struct WordReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl WordReader<'_> {
+    fn next(&mut self) -> Result<MachineCodeInstruction, ObjectFileError> {
+        let word = self.bytes.get(self.position..self.position + 4).ok_or(ObjectFileError::Truncated)?;
+        self.position += 4;
+        Ok(u32::from_le_bytes(word.try_into().unwrap()))
+    }
+}
+
+#[cfg(test)]
+mod object_file_tests {
+    use super::*;
+    use crate::assembler::get_machine_code_instructions;
+    use crate::code_gen::AssemblyInstruction;
+    use crate::emulator::Emulator;
+
+    fn get_object() -> ObjectFile {
+        // prints the global at GP-4, then calls print_twice which prints the global at GP-8
+        let code = get_machine_code_instructions(vec![
+            AssemblyInstruction::LDW(1, 30, -4),
+            AssemblyInstruction::WRD(1),
+            AssemblyInstruction::JSR(20),
+            AssemblyInstruction::WRL,
+            AssemblyInstruction::RET(0),
+            AssemblyInstruction::LDW(2, 30, -8),
+            AssemblyInstruction::WRD(2),
+            AssemblyInstruction::WRD(2),
+            AssemblyInstruction::RET(31),
+        ]).unwrap();
+
+        ObjectFile {
+            entry_point: 0,
+            code,
+            data: vec![-7, 42],
+            symbols: vec![
+                ObjectSymbol { name: "main".to_string(), address: 0 },
+                ObjectSymbol { name: "print_twice".to_string(), address: 20 },
+            ],
+            line_table: Some(vec![LineEntry { address: 0, line: 3 }, LineEntry { address: 20, line: -1 }]),
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let object = get_object();
+        let mut bytes = Vec::new();
+        object.write_to(&mut bytes).unwrap();
+
+        assert_eq!(&bytes[..4], b"TNYO");
+        assert_eq!(ObjectFile::read_from(&mut bytes.as_slice()), Ok(object.clone()));
+        assert_eq!(object.get_symbol("print_twice").map(|symbol| symbol.address), Some(20));
+
+        let without_lines = ObjectFile { line_table: None, ..object };
+        assert_eq!(ObjectFile::from_bytes(&without_lines.to_bytes()), Ok(without_lines));
+    }
+
+    #[test]
+    fn test_run_from_object_file() {
+        let bytes = get_object().to_bytes();
+        let object = ObjectFile::from_bytes(&bytes).unwrap();
+
+        let mut emulator = Emulator::from_object_file(&object, "".as_bytes(), Vec::new()).unwrap();
+        emulator.run().unwrap();
+        assert_eq!(emulator.get_writer().as_slice(), b"42 -7 -7 \n");
+    }
+
+    #[test]
+    fn test_errors() {
+        let bytes = get_object().to_bytes();
+
+        assert_eq!(ObjectFile::from_bytes(b"ELF\x7f"), Err(ObjectFileError::BadMagic(*b"ELF\x7f")));
+        assert_eq!(ObjectFile::from_bytes(&bytes[..bytes.len() - 2]), Err(ObjectFileError::Truncated));
+        assert_eq!(ObjectFile::from_bytes(&bytes[..2]), Err(ObjectFileError::Truncated));
+
+        let mut newer = bytes.clone();
+        newer[4] = 2;
+        assert_eq!(ObjectFile::from_bytes(&newer).unwrap_err().to_string(), "object file version 2 is not supported, expected 1");
+    }
+}
@@ -1,4 +1,5 @@
 use crate::basic_block::VariableType;
+use crate::diagnostic::Diagnostic;
 use crate::tokenizer::{Token, Tokenizer};
 use crate::{
     basic_block::BasicBlockType,
@@ -12,19 +13,26 @@ pub struct Parser {
     tokenizer: Tokenizer,
     pub internal_program: Program,
     line_number: isize,
+    // calls and returns that do not fit the function, parsing goes on past them
+    pub diagnostics: Vec<Diagnostic>,
 }
 
 impl Parser {
     pub fn new(input: String) -> Self {
-        let mut program = Program::new();
+        let program = Program::new();
 
         Self {
             tokenizer: Tokenizer::new(input),
             internal_program: program,
             line_number: 0,
+            diagnostics: Vec::new(),
         }
     }
 
+    pub fn has_errors(&self) -> bool {
+        self.diagnostics.iter().any(|diagnostic| diagnostic.is_error())
+    }
+
     fn parse_var_decl(&mut self) {
         self.match_token(Token::Variable);
         loop {
@@ -51,22 +59,19 @@ impl Parser {
         self.internal_program.add_predefined_functions();
 
         // funcDecl
-        loop {
-            match self.tokenizer.peek_token() {
-                Token::Void | Token::Function => self.parse_func_decl(),
-                _ => break,
-            }
-
+        while let Token::Void | Token::Function = self.tokenizer.peek_token() {
+            self.parse_func_decl();
         }
         
         // go back to main for parsing
         self.internal_program.change_curr_fn_to("main");
+        self.internal_program.share_globals_with_functions();
 
         self.match_token(Token::OpenBrace);
         self.parse_stat_sequence();
+        self.match_token(Token::CloseBrace);
+        self.match_token(Token::EOF);
         if self.internal_program.get_curr_block().is_empty() {
-            self.match_token(Token::CloseBrace);
-            self.match_token(Token::EOF);
             self.emit_instruction(Operation::Empty);
         }
         self.internal_program.add_exit_block();
@@ -77,7 +82,12 @@ impl Parser {
     fn parse_var(&mut self) {
         match self.tokenizer.next_token() {
             Token::Identifier(name) => {
-                self.internal_program.declare_variable_to_curr_block(&name);
+                // variables declared by main are globals
+                if self.internal_program.get_curr_fn_name() == "main" {
+                    self.internal_program.declare_global(&name);
+                } else {
+                    self.internal_program.declare_local(&name);
+                }
             },
             _ => panic!("unexpected error in parse_var"),
         }
@@ -85,19 +95,19 @@ impl Parser {
 
     // Parse an expression (handles addition and subtraction)
     fn parse_expression(&mut self) -> isize {
-        let line_number1 = self.parse_term();
+        let mut line_number1 = self.parse_term();
 
         loop {
             match self.tokenizer.peek_token() {
                 Token::Plus => {
                     self.tokenizer.next_token();
                     let line_number2 = self.parse_term();
-                    return self.emit_instruction(Operation::Add(line_number1, line_number2));
+                    line_number1 = self.emit_instruction(Operation::Add(line_number1, line_number2));
                 },
                 Token::Minus => {
                     self.tokenizer.next_token();
                     let line_number2 = self.parse_term();
-                    return self.emit_instruction(Operation::Sub(line_number1, line_number2));
+                    line_number1 = self.emit_instruction(Operation::Sub(line_number1, line_number2));
                 },
                 _ => break,
             }
@@ -108,19 +118,19 @@ impl Parser {
 
     // Parse a term (handles multiplication and division)
     fn parse_term(&mut self) -> isize {
-        let line_number1 = self.parse_factor();
+        let mut line_number1 = self.parse_factor();
 
         loop {
             match self.tokenizer.peek_token() {
                 Token::Times => {
                     self.tokenizer.next_token();
                     let line_number2 = self.parse_factor();
-                    return self.emit_instruction(Operation::Mul(line_number1, line_number2));
+                    line_number1 = self.emit_instruction(Operation::Mul(line_number1, line_number2));
                 },
                 Token::Divide => {
                     self.tokenizer.next_token();
                     let line_number2 = self.parse_factor();
-                    return self.emit_instruction(Operation::Div(line_number1, line_number2));
+                    line_number1 = self.emit_instruction(Operation::Div(line_number1, line_number2));
                 },
                 _ => break,
             }
@@ -139,7 +149,9 @@ impl Parser {
             Token::Identifier(name) => {
                 match self.internal_program.get_variable(&name) {
                     VariableType::Value(value) => value,
-                    VariableType::NotInit => panic!("parse_factor() is retrieving an uninitialized variable"),
+                    VariableType::InMemory => self.load_global(&name),
+                    // the semantic pass warns about these, they read as zero
+                    VariableType::NotInit => self.internal_program.get_constant(0),
                 }
             },
             Token::OpenParen => {
@@ -148,9 +160,10 @@ impl Parser {
                 result
             },
             Token::FunctionCall => {
+                let span = self.tokenizer.peek_span();
                 let function_name = self.get_func_name_and_verify();
                 if self.is_func_void(&function_name) {
-                    panic!("A void function cannot be used as an expression");
+                    self.diagnostics.push(Diagnostic::error(span, format!("the void function {} cannot be used as an expression", function_name)));
                 }
                 self.parse_func_call()
             },
@@ -172,6 +185,19 @@ impl Parser {
         // this is used for testing, but will eventually be ONLY set_variable
         self.internal_program.declare_variable_to_curr_block(&variable_name);
         self.internal_program.assign_variable_to_curr_block(&variable_name, expr_result);
+
+        // globals are written through so callees and callers always see the latest value
+        if let Some(offset) = self.internal_program.get_global_offset(&variable_name) {
+            self.emit_instruction(Operation::Store(expr_result, offset));
+        }
+    }
+
+    // loads a global from memory and keeps the loaded value until it is invalidated
+    fn load_global(&mut self, variable_name: &String) -> isize {
+        let offset = self.internal_program.get_global_offset(variable_name).unwrap();
+        let line_number = self.emit_instruction(Operation::Load(offset));
+        self.internal_program.assign_variable_to_curr_block(variable_name, line_number);
+        line_number
     }
 
     // Parse a relation 
@@ -185,12 +211,12 @@ impl Parser {
     }
 
     fn parse_operator(&mut self) -> Token {
-        let operator_tokens = vec![Token::Equal, Token::NotEqual, Token::Greater, Token::GreaterEqual, Token::Less, Token::LessEqual];
+        let operator_tokens = [Token::Equal, Token::NotEqual, Token::Greater, Token::GreaterEqual, Token::Less, Token::LessEqual];
 
         let token = self.tokenizer.next_token();
 
         if operator_tokens.contains(&token) {
-            return token;
+            token
         } else {
             panic!("ERROR: {:?} is not a valid operator", token);
         }
@@ -281,9 +307,14 @@ impl Parser {
         }
 
         self.match_token(Token::While);
+        let while_span = self.tokenizer.get_span();
 
         // Start of conditional block
         let conditional_index: NodeIndex = self.internal_program.add_cond_block();
+        self.internal_program.get_curr_fn_mut().loop_headers.push((while_span, conditional_index));
+        // the loop body may change globals, so the header reloads them on every iteration
+        self.internal_program.invalidate_globals();
+        let loop_phis = self.emit_loop_phis();
         let (condition, comparison_operator) = self.parse_relation();
 
         // Emit the branch instruction with a placeholder target
@@ -297,14 +328,15 @@ impl Parser {
 
         // Get the last created block in the fallthrough sequence
         let last_fallthru_index = self.internal_program.get_curr_block_index();
+        // values of globals cached in the body do not carry over the back edge
+        self.internal_program.invalidate_globals();
 
         // Add a follow block and join it with the conditional block
         let follow_index = self.internal_program.add_follow_block(conditional_index);
 
         // Ensure loop continues by branching back to the conditional block
         let last_fallthru_nodeindex = NodeIndex::new(last_fallthru_index);
-        let phi_instructions = self.internal_program.join_blocks_with_target(last_fallthru_nodeindex, conditional_index);
-        self.emit_phi_instructions(phi_instructions, conditional_index);
+        self.complete_loop_phis(loop_phis, conditional_index, last_fallthru_nodeindex);
         self.emit_instruction_in_block(last_fallthru_nodeindex, Operation::Bra(conditional_block_index.index() as isize));
         self.internal_program.get_curr_fn_mut().add_edge(last_fallthru_nodeindex, conditional_index, BasicBlockType::Follow);
 
@@ -351,16 +383,25 @@ impl Parser {
     // Parse a sequence of statements
     fn parse_stat_sequence(&mut self) {
         loop {            
-            match self.tokenizer.peek_token() {
+            let token = self.tokenizer.peek_token();
+            if matches!(token, Token::Let | Token::If | Token::While | Token::Return | Token::FunctionCall) {
+                let span = self.tokenizer.peek_span();
+                let function = self.internal_program.get_curr_fn_mut();
+                let start = (span, function.curr_node, function.get_curr_bb().instructions.len());
+                function.statement_starts.push(start);
+            }
+
+            match token {
                 Token::Let => self.parse_assignment(),
                 Token::If => self.parse_if_statement(),
                 Token::While => self.parse_while_statement(),
                 Token::Return => self.parse_return_statement(),
                 Token::FunctionCall => { 
                     self.tokenizer.next_token();
+                    let span = self.tokenizer.peek_span();
                     let function_name = self.get_func_name_and_verify();
                     if !self.is_func_void(&function_name) {
-                        panic!("A non-void function cannot be used as a statement");
+                        self.diagnostics.push(Diagnostic::error(span, format!("the non-void function {} cannot be used as a statement", function_name)));
                     }
                     self.parse_func_call(); 
                 },
@@ -379,20 +420,33 @@ impl Parser {
     // Parse a return statement
     fn parse_return_statement(&mut self) {
         self.match_token(Token::Return);
+        let span = self.tokenizer.get_span();
+
+        let has_value = !matches!(self.tokenizer.peek_token(), Token::Semicolon | Token::CloseBrace | Token::Fi | Token::Else | Token::Od);
+        let is_void = self.internal_program.get_curr_fn().is_void;
 
-        if self.internal_program.get_curr_fn().is_void {
-            panic!("A void function should not return");
+        if is_void && has_value {
+            self.diagnostics.push(Diagnostic::error(span, "a void function cannot return a value".to_string()));
+        }
+        if !is_void && !has_value {
+            self.diagnostics.push(Diagnostic::error(span, "a non-void function must return a value".to_string()));
         }
 
-        if self.tokenizer.peek_token() != Token::Semicolon {
-            let expr_result = self.parse_expression();
-            self.emit_instruction(Operation::Ret(expr_result));
-        } else {
-            self.emit_instruction(Operation::Ret(0));
+        // returning from main ends the program
+        if self.internal_program.get_curr_fn_name() == "main" {
+            self.emit_instruction(Operation::End);
+            return;
         }
+
+        let return_value = if has_value {
+            self.parse_expression()
+        } else {
+            self.internal_program.get_constant(0)
+        };
+        self.emit_instruction(Operation::Ret(return_value));
     }
 
-    fn is_func_void(&mut self, function_name: &String) -> bool {
+    fn is_func_void(&mut self, function_name: &str) -> bool {
         self.internal_program.get_fn(function_name).is_void
     }
 
@@ -415,27 +469,14 @@ impl Parser {
         self.tokenizer.next_token();
 
         let num_of_parameters = self.internal_program.get_number_of_parameters_of(&function_name);
+        let mut arguments = Vec::<isize>::new();
 
         if num_of_parameters == 0 {
             if self.tokenizer.peek_token() == Token::OpenParen {
                 self.match_token(Token::OpenParen);
                 self.match_token(Token::CloseParen);
             }
-
-            // predefined functions
-            if function_name == "InputNum".to_string() {
-                let input_num = self.internal_program.input_num();
-                self.internal_program.add_constant(input_num);
-                return self.internal_program.get_constant(input_num);
-            } else if function_name == "OutputNewLine".to_string() {
-                self.internal_program.output_new_line();
-
-                return 0;
-            }
-
-
         } else {
-            let mut arguments = Vec::<isize>::new();
             self.match_token(Token::OpenParen);
             if self.tokenizer.peek_token() == Token::CloseParen {
                 panic!("Calling a function with arguments with none");
@@ -455,47 +496,48 @@ impl Parser {
                     _ => break,
                 }
             }
-            if arguments.len() != self.internal_program.get_number_of_parameters_of(&function_name) {
+            if arguments.len() != num_of_parameters {
                 panic!("Number of parameters does not match arguments"); 
             }
             self.match_token(Token::CloseParen);
-            
-            if function_name == "OutputNum".to_string() {
-                self.internal_program.output_num(arguments[0]);
-                return 0;
-            }
+        }
 
-            // emit setpar...?
-            for i in 0..arguments.len() {
-                let operation = match i {
-                    0 => Operation::SetPar1(arguments[0]),
-                    1 => Operation::SetPar2(arguments[1]),
-                    2 => Operation::SetPar3(arguments[2]),
-                    _ => unreachable!("Should not reach here since max is 3 parameters"),
-                };
+        // predefined functions lower straight to their I/O operations
+        match function_name.as_str() {
+            "InputNum" => return self.emit_instruction(Operation::Read),
+            "OutputNum" => return self.emit_instruction(Operation::Write(arguments[0])),
+            "OutputNewLine" => return self.emit_instruction(Operation::WriteNL),
+            _ => (),
+        }
 
-                self.emit_instruction(operation);
-            }
-            // if arguments.len() >= 1 {
-            //     self.emit_instruction(Operation::SetPar1(arguments[0]));
-            //     if arguments.len() >= 2 {
-            //         self.emit_instruction(Operation::SetPar2(arguments[1]));
-            //         if arguments.len() == 3 {
-            //             self.emit_instruction(Operation::SetPar3(arguments[2]));
-            //         }
-            //     }
-            // }
-            
+        for (i, argument) in arguments.iter().enumerate() {
+            let operation = match i {
+                0 => Operation::SetPar1(*argument),
+                1 => Operation::SetPar2(*argument),
+                2 => Operation::SetPar3(*argument),
+                _ => unreachable!("Should not reach here since max is 3 parameters"),
+            };
+
+            self.emit_instruction(operation);
+        }
+
+        // a function calling itself before anything else is emitted still needs a first line to jump to
+        if self.internal_program.get_fn(&function_name).get_bb(&NodeIndex::from(0)).unwrap().is_empty() {
+            self.emit_instruction(Operation::Empty);
         }
 
         let first_instruction_of_calling_function = self.internal_program.get_fn(&function_name).get_bb(&NodeIndex::from(0)).unwrap().get_first_instruction_line_number();
-        self.emit_instruction(Operation::Jsr(first_instruction_of_calling_function));
 
-        // return the last return instruction if the function is non-void
-        let calling_function_graph = self.internal_program.get_fn(&function_name).get_graph();
-        let return_instruction = self.internal_program.get_fn(&function_name).get_bb(&calling_function_graph.node_indices().max().unwrap()).unwrap().get_last_instruction_line_number();
+        // a non-void call defines its own value in the caller, which the callee's ret feeds
+        let line_number = if self.is_func_void(&function_name) {
+            self.emit_instruction(Operation::Jsr(first_instruction_of_calling_function))
+        } else {
+            self.emit_instruction(Operation::Call(first_instruction_of_calling_function))
+        };
 
-        return_instruction
+        // the callee may have stored to any global
+        self.internal_program.invalidate_globals();
+        line_number
     }
 
     fn parse_func_decl(&mut self) {
@@ -529,7 +571,7 @@ impl Parser {
                     self.tokenizer.next_token();
                     // add to vec of strings then add to the variable table
                     self.internal_program.insert_new_parameter_to_curr_function(parameter_name.clone());
-                    self.internal_program.declare_variable_to_curr_block(&parameter_name);
+                    self.internal_program.declare_local(&parameter_name);
 
                     if self.internal_program.get_number_of_parameters_of_curr_fn() > 3 {
                         panic!("A function should not have more than 3 parameters");
@@ -556,7 +598,7 @@ impl Parser {
             let line_number = self.emit_instruction(operation);
             let parameter_name = &self.internal_program.get_curr_fn().parameters[i].clone();
 
-            self.internal_program.assign_variable_to_curr_block(&parameter_name, line_number);
+            self.internal_program.assign_variable_to_curr_block(parameter_name, line_number);
         }
         
         // LOLCODE
@@ -588,15 +630,27 @@ impl Parser {
 
         self.match_token(Token::OpenBrace);
         self.parse_stat_sequence();
-        self.match_token(Token::CloseParen);
+        self.match_token(Token::CloseBrace);
+
+        let function = self.internal_program.get_curr_fn();
+        if function.is_void {
+            // falling off the end of a void function returns to the caller
+            if !function.get_curr_bb().ends_with_return() {
+                let return_value = self.internal_program.get_constant(0);
+                self.emit_instruction(Operation::Ret(return_value));
+            }
+        } else if !function.returns_on_all_paths() {
+            // reported at the closing brace, which is where the paths fall off the end
+            let message = format!("not every path of the non-void function {} returns a value", function.name);
+            self.diagnostics.push(Diagnostic::error(self.tokenizer.get_span(), message));
+        }
     }
 
     fn match_token(&mut self, token_to_match: Token) {
         // advances regardless of token, should always match, else syntax error
         let token = self.tokenizer.next_token();
-        match token {
-            token_to_match => (),
-            _ => panic!("ERROR: Unexpected token, expected {:?}, instead got {:?}", token_to_match, token),
+        if token != token_to_match {
+            panic!("ERROR: Unexpected token, expected {:?}, instead got {:?}", token_to_match, token);
         }
     }
 
@@ -655,6 +709,40 @@ impl Parser {
         self.line_number
     }
 
+    // Every variable kept in a register gets a phi in the loop header before the condition is parsed,
+    // so the condition and the body read the value of the current iteration. The back edge operand
+    // is not known yet and points at the phi itself until the loop is completed.
+    fn emit_loop_phis(&mut self) -> Vec<(String, isize, VariableType)> {
+        let mut variables: Vec<(String, VariableType)> = self.internal_program.get_curr_block().variable_table.iter()
+            .filter(|(_, value)| **value != VariableType::InMemory)
+            .map(|(variable, value)| (variable.clone(), *value))
+            .collect();
+        variables.sort_by(|left, right| left.0.cmp(&right.0));
+
+        let mut loop_phis = Vec::new();
+        for (variable, entry_value) in variables {
+            let phi_line = self.line_number + 1;
+            self.emit_instruction(Operation::Phi(entry_value.get_value(), phi_line));
+            self.internal_program.assign_variable_to_curr_block(&variable, phi_line);
+            loop_phis.push((variable, phi_line, entry_value));
+        }
+        loop_phis
+    }
+
+    // Fills in the back edge operand of each loop phi, phis of variables the body never assigns are removed
+    fn complete_loop_phis(&mut self, loop_phis: Vec<(String, isize, VariableType)>, conditional_index: NodeIndex, last_body_index: NodeIndex) {
+        for (variable, phi_line, entry_value) in loop_phis {
+            let body_value = self.internal_program.get_curr_fn().get_bb(&last_body_index).unwrap().get_variable(&variable);
+            match body_value {
+                VariableType::Value(value) if value != phi_line => {
+                    let conditional_block = self.internal_program.get_curr_fn_mut().get_bb_mut(&conditional_index).unwrap();
+                    conditional_block.modify_instruction(phi_line, Operation::Phi(entry_value.get_value(), value));
+                },
+                _ => self.internal_program.get_curr_fn_mut().remove_trivial_phi(conditional_index, phi_line, entry_value),
+            }
+        }
+    }
+
     fn emit_phi_instructions(&mut self, phi_instructions: Vec<(Operation, String)>, block_index: NodeIndex) {
         for (operation, variable) in phi_instructions {
             let line_num = self.emit_instruction_on_top(block_index, operation);
@@ -722,6 +810,230 @@ mod parser_tests{
     }
 
 
+    #[test]
+    fn test_call_value_defined_in_caller() {
+        let input = 
+        "main 
+        var a; 
+    
+        function max(x, y); {
+            if x > y then
+                return x;
+            fi;
+            return y;
+        };
+
+        {
+            let a <- call max(call InputNum(), 2);
+            call OutputNum(a);
+        }.
+        ".to_string();
+        let mut parser = Parser::new(input);
+
+        parser.parse_computation();
+
+        // both returns of max stay in max
+        let max_instructions: Vec<Operation> = parser.internal_program.get_fn("max").bb_graph.node_weights()
+            .flat_map(|block| block.instructions.iter().map(|instruction| instruction.operation))
+            .collect();
+        assert_eq!(max_instructions.iter().filter(|operation| matches!(operation, Operation::Ret(_))).count(), 2);
+
+        // the value of the call is the call instruction in main, and it is what gets written
+        let main_instructions: Vec<Instruction> = parser.internal_program.get_fn("main").bb_graph.node_weights()
+            .flat_map(|block| block.instructions.clone())
+            .collect();
+        let call = main_instructions.iter().find(|instruction| matches!(instruction.operation, Operation::Call(_))).unwrap();
+        assert!(main_instructions.iter().any(|instruction| instruction.operation == Operation::Write(call.get_line_number())));
+        assert!(main_instructions.iter().any(|instruction| instruction.operation == Operation::Read));
+    }
+
+    // the messages of the errors the parser reported, with where they were found
+    fn get_errors(parser: &Parser) -> Vec<String> {
+        parser.diagnostics.iter().filter(|diagnostic| diagnostic.is_error()).map(|diagnostic| diagnostic.to_string()).collect()
+    }
+
+    #[test]
+    fn test_missing_return_on_some_path() {
+        let input = 
+        "main var a;
+        function f(x); {
+            if x > 0 then
+                return 1;
+            fi;
+        };
+        {
+            let a <- call f(1);
+        }.
+        ".to_string();
+        let mut parser = Parser::new(input);
+
+        parser.parse_computation();
+        assert_eq!(get_errors(&parser), vec!["6:9: error: not every path of the non-void function f returns a value"]);
+    }
+
+    #[test]
+    fn test_return_only_inside_loop() {
+        let input = 
+        "main var a;
+        function f(x); {
+            while x > 0 do
+                return 1;
+            od;
+        };
+        {
+            let a <- call f(1);
+        }.
+        ".to_string();
+        let mut parser = Parser::new(input);
+
+        parser.parse_computation();
+        assert!(parser.has_errors());
+        assert_eq!(get_errors(&parser), vec!["6:9: error: not every path of the non-void function f returns a value"]);
+    }
+
+    #[test]
+    fn test_void_function_returns() {
+        let input = 
+        "main var a;
+        void function f(x); {
+            if x > 0 then
+                return;
+            fi;
+            call OutputNum(x);
+        };
+        {
+            call f(1);
+        }.
+        ".to_string();
+        let mut parser = Parser::new(input);
+
+        parser.parse_computation();
+
+        // the early return plus the implicit one at the end of the body
+        let returns = parser.internal_program.get_fn("f").bb_graph.node_weights()
+            .flat_map(|block| block.instructions.iter())
+            .filter(|instruction| matches!(instruction.operation, Operation::Ret(_)))
+            .count();
+        assert_eq!(returns, 2);
+    }
+
+    #[test]
+    fn test_void_function_returning_value() {
+        let input = "main void function f(); { return 1; }; { call f(); }.".to_string();
+        let mut parser = Parser::new(input);
+
+        parser.parse_computation();
+        assert_eq!(get_errors(&parser), vec!["1:27: error: a void function cannot return a value"]);
+    }
+
+    #[test]
+    fn test_calls_that_do_not_fit() {
+        let input = "main var a; void function f(); { }; function g(); { return }; { let a <- call f(); call g() }.".to_string();
+        let mut parser = Parser::new(input);
+
+        parser.parse_computation();
+        assert_eq!(get_errors(&parser), vec![
+            "1:53: error: a non-void function must return a value",
+            "1:79: error: the void function f cannot be used as an expression",
+            "1:89: error: the non-void function g cannot be used as a statement",
+        ]);
+    }
+
+    #[test]
+    fn test_uninitialized_reads_as_zero() {
+        let input = 
+        "main var a, b;
+        {
+            if call InputNum() > 0 then
+                let a <- 5
+            fi;
+            call OutputNum(a);
+            call OutputNum(b)
+        }.
+        ".to_string();
+        let mut parser = Parser::new(input);
+
+        parser.parse_computation();
+
+        let operations: Vec<Operation> = parser.internal_program.get_fn("main").bb_graph.node_weights()
+            .flat_map(|block| block.instructions.iter().map(|instruction| instruction.operation))
+            .collect();
+        assert!(parser.internal_program.get_constant_table().contains_key(&0));
+        assert!(operations.iter().any(|operation| matches!(operation, Operation::Phi(-5, 0) | Operation::Phi(0, -5))));
+        assert!(operations.contains(&Operation::Write(0)));
+    }
+
+    #[test]
+    fn test_globals_in_functions() {
+        let input = 
+        "main var g, h;
+        void function bump(); {
+            let g <- g + 1;
+        };
+        function shadow(g); {
+            return g;
+        };
+        {
+            let g <- 5;
+            let h <- 7;
+            call bump();
+            call OutputNum(g);
+            call OutputNum(call shadow(h));
+        }.
+        ".to_string();
+        let mut parser = Parser::new(input);
+
+        parser.parse_computation();
+
+        let operations_of = |function_name: &str| -> Vec<Operation> {
+            parser.internal_program.get_fn(function_name).bb_graph.node_weights()
+                .flat_map(|block| block.instructions.iter().map(|instruction| instruction.operation))
+                .collect()
+        };
+
+        // bump loads g and writes the sum back
+        let bump_operations = operations_of("bump");
+        assert!(bump_operations.contains(&Operation::Load(-4)));
+        assert!(bump_operations.iter().any(|operation| matches!(operation, Operation::Store(_, -4))));
+
+        // the parameter shadows g, so shadow never touches memory
+        let shadow_operations = operations_of("shadow");
+        assert!(!shadow_operations.iter().any(|operation| matches!(operation, Operation::Load(_) | Operation::Store(_, _))));
+
+        // main stores g for bump and reloads it after the call, h stays out of memory
+        let main_operations = operations_of("main");
+        assert!(main_operations.contains(&Operation::Store(-5, -4)));
+        let jsr_position = main_operations.iter().position(|operation| matches!(operation, Operation::Jsr(_))).unwrap();
+        assert_eq!(main_operations[jsr_position + 1], Operation::Load(-4));
+        assert!(!main_operations.iter().any(|operation| matches!(operation, Operation::Store(_, -8) | Operation::Load(-8))));
+    }
+
+    #[test]
+    fn test_globals_reloaded_in_loop() {
+        let input = 
+        "main var g;
+        void function bump(); {
+            let g <- g + 1;
+        };
+        {
+            let g <- 0;
+            while g < 10 do
+                call bump();
+            od;
+            call OutputNum(g);
+        }.
+        ".to_string();
+        let mut parser = Parser::new(input);
+
+        parser.parse_computation();
+
+        // the loop header compares against a value loaded on every iteration
+        let main_function = parser.internal_program.get_fn("main");
+        let header = main_function.bb_graph.node_weights().find(|block| block.block_type == BasicBlockType::Conditional).unwrap();
+        assert_eq!(header.instructions[0].operation, Operation::Load(-4));
+        assert_eq!(header.instructions[1].operation, Operation::Cmp(header.instructions[0].get_line_number(), -10));
+    }
+
     #[test]
     fn test_parse_operator() {
         let input = "1+1.".to_string(); // this doesnt matter, im testing the parse_operation fn
@@ -745,12 +1057,8 @@ mod parser_tests{
         ".to_string();
         let mut parser = Parser::new(input);
 
-        let line_number = parser.parse_computation();
-
-        // Verify that the add operation is correct
-        let instructions = &parser.internal_program.get_curr_block().instructions;
+        parser.parse_computation();
 
-        let graph = &parser.internal_program.get_curr_fn().bb_graph;
         println!("{}", generate_dot_viz("main", &parser.internal_program));
 
     }
@@ -767,12 +1075,8 @@ mod parser_tests{
         ".to_string();
         let mut parser = Parser::new(input);
 
-        let line_number = parser.parse_computation();
-
-        // Verify that the add operation is correct
-        let instructions = &parser.internal_program.get_curr_block().instructions;
+        parser.parse_computation();
 
-        let graph = &parser.internal_program.get_curr_fn().bb_graph;
         println!("{}", generate_dot_viz("main", &parser.internal_program));
 
     }
@@ -782,12 +1086,8 @@ mod parser_tests{
         let input = "main var a, b; {let a <- 1 + 53; let b <- 1 + 53;}.".to_string();
         let mut parser = Parser::new(input);
 
-        let line_number = parser.parse_computation();
-
-        // Verify that the add operation is correct
-        let instructions = &parser.internal_program.get_curr_block().instructions;
+        parser.parse_computation();
 
-        let graph = &parser.internal_program.get_curr_fn().bb_graph;
         println!("{}", generate_dot_viz("main", &parser.internal_program));
 
     }
@@ -812,7 +1112,7 @@ mod parser_tests{
         let input = "2*3.".to_string();
         let mut parser = Parser::new(input);
 
-        let line_number = parser.parse_expression();
+        let _line_number = parser.parse_expression();
 
         // Verify that the mul operation is correct
         let instructions = &parser.internal_program.get_curr_block().instructions;
@@ -888,7 +1188,7 @@ mod parser_tests{
 
         // Verify that the if statement creates the correct basic blocks and instructions
         let graph = &parser.internal_program.get_curr_fn().bb_graph;
-        let number_of_blocks = graph.node_count();
+        let _number_of_blocks = graph.node_count();
 
         // println!("{:?}", Dot::with_config(&graph, &[Config::EdgeNoLabel]));
         println!("{}", generate_dot_viz("main", &parser.internal_program));
@@ -907,7 +1207,6 @@ mod parser_tests{
         parser.parse_computation();
 
         // Verify that the if statement creates the correct basic blocks and instructions
-        let graph = &parser.internal_program.get_curr_fn().bb_graph;
 
         // println!("{:?}", Dot::with_config(&graph, &[Config::EdgeNoLabel]));
         println!("{}", generate_dot_viz("main", &parser.internal_program));
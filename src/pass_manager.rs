@@ -0,0 +1,195 @@
+use std::fmt;
+
+use crate::algebraic_simplification::simplify_algebra;
+use crate::cfg_simplification::{simplify_cfg, CfgSimplificationStatistics};
+use crate::inliner::{inline_functions, InlineConfig};
+use crate::phi_elimination::{eliminate_phis, PhiEliminationStatistics};
+use crate::program::Program;
+use crate::strength_reduction::{reduce_strength, StrengthReductionStatistics};
+use crate::value_numbering::number_values;
+
+/// Which passes run over the IR before code generation. Peephole optimization runs over the
+/// linked image instead, see [`optimize_object`](crate::peephole::optimize_object).
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct PassConfig {
+    /// inlines calls to small callees when set
+    pub inline: Option<InlineConfig>,
+    pub simplify_cfg: bool,
+    pub simplify_algebra: bool,
+    pub number_values: bool,
+    pub eliminate_phis: bool,
+    pub reduce_strength: bool,
+    /// lets strength reduction move loop exit tests, which may change the result on overflow
+    pub rewrite_exit_tests: bool,
+    pub peephole: bool,
+}
+
+impl PassConfig {
+    /// Every pass that keeps the result of the program the same.
+    pub fn all() -> Self {
+        Self {
+            inline: Some(InlineConfig::default()),
+            simplify_cfg: true,
+            simplify_algebra: true,
+            number_values: true,
+            eliminate_phis: true,
+            reduce_strength: true,
+            rewrite_exit_tests: false,
+            peephole: true,
+        }
+    }
+}
+
+/// What every pass that ran did.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct PassStatistics {
+    pub inlined_calls: usize,
+    pub cfg_simplification: CfgSimplificationStatistics,
+    pub simplified_instructions: usize,
+    pub numbered_values: usize,
+    pub phi_elimination: PhiEliminationStatistics,
+    pub strength_reduction: StrengthReductionStatistics,
+}
+
+impl fmt::Display for PassStatistics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "inlined calls: {}", self.inlined_calls)?;
+        writeln!(f, "merged blocks: {}", self.cfg_simplification.merged_blocks)?;
+        writeln!(f, "removed empty blocks: {}", self.cfg_simplification.removed_empty_blocks)?;
+        writeln!(f, "removed unreachable blocks: {}", self.cfg_simplification.removed_unreachable_blocks)?;
+        writeln!(f, "simplified instructions: {}", self.simplified_instructions)?;
+        writeln!(f, "removed redundant values: {}", self.numbered_values)?;
+        writeln!(f, "removed phis: {}", self.phi_elimination.removed_phis)?;
+        writeln!(f, "collapsed blocks: {}", self.phi_elimination.collapsed_blocks)?;
+        writeln!(f, "reduced multiplications: {}", self.strength_reduction.reduced_multiplications)?;
+        writeln!(f, "rewritten exit tests: {}", self.strength_reduction.rewritten_exit_tests)
+    }
+}
+
+/// Runs the enabled passes over every function. Inlining goes first so the other passes see
+/// the inlined code, the blocks it leaves behind are merged before values are simplified and
+/// numbered, and strength reduction runs last on loops that are already cleaned up.
+pub fn run_passes(program: &mut Program, config: &PassConfig) -> PassStatistics {
+    let mut statistics = PassStatistics::default();
+
+    if let Some(inline_config) = &config.inline {
+        statistics.inlined_calls = inline_functions(program, inline_config);
+    }
+    if config.simplify_cfg {
+        statistics.cfg_simplification = simplify_cfg(program);
+    }
+    if config.simplify_algebra {
+        statistics.simplified_instructions = simplify_algebra(program);
+    }
+    if config.number_values {
+        statistics.numbered_values = number_values(program);
+    }
+    if config.eliminate_phis {
+        statistics.phi_elimination = eliminate_phis(program);
+    }
+    if config.reduce_strength {
+        statistics.strength_reduction = reduce_strength(program, config.rewrite_exit_tests);
+    }
+
+    statistics
+}
+
+#[cfg(test)]
+mod pass_manager_tests {
+    use super::*;
+    use crate::emulator::Emulator;
+    use crate::linker::link;
+    use crate::peephole::optimize_object;
+    use crate::test_helpers::{get_program, interpret};
+
+    // links the program, with the peephole rules run over the image when they are enabled
+    fn emulate(program: &Program, config: &PassConfig, input: &str) -> String {
+        let mut object = link(program).unwrap();
+        if config.peephole {
+            object = optimize_object(&object).unwrap().0;
+        }
+        let mut emulator = Emulator::from_object_file(&object, input.as_bytes(), Vec::new()).unwrap();
+        emulator.run().unwrap();
+        String::from_utf8(emulator.get_writer().clone()).unwrap()
+    }
+
+    const PROGRAMS: [&str; 4] = [
+        "
+main var n, i, s;
+function square(x); { return x * x };
+{
+    let n <- call InputNum();
+    let i <- 0;
+    let s <- 0;
+    while i < n do
+        let s <- s + call square(i) + i * 4;
+        let i <- i + 1
+    od;
+    call OutputNum(s)
+}.",
+        "
+main var a, b, c;
+function max(x, y); { if x > y then return x else return y fi };
+{
+    let a <- call InputNum();
+    let b <- a * 1 + 0;
+    let c <- call max(a + 2, b * 3);
+    if a > 5 then let c <- c + a + b else let c <- c - 1 fi;
+    call OutputNum(c);
+    call OutputNum(a + b)
+}.",
+        "
+main var g, i;
+void function show(x); { call OutputNum(x); let g <- g + x };
+{
+    let g <- 0;
+    let i <- call InputNum();
+    while i > 0 do
+        call show(i);
+        let i <- i - 1
+    od;
+    call OutputNum(g)
+}.",
+        "
+main var a, i, j, s;
+{
+    let a <- call InputNum();
+    let i <- 0;
+    let s <- 0;
+    while i < a do
+        let j <- 0;
+        while j < i do
+            let s <- s + j * 2;
+            let j <- j + 1
+        od;
+        let i <- i + 1
+    od;
+    call OutputNum(s)
+}.",
+    ];
+
+    #[test]
+    fn test_all_passes_keep_results() {
+        for input in PROGRAMS {
+            let mut program = get_program(input);
+            let expected: Vec<String> = ["0", "3", "12"].iter().map(|program_input| interpret(&program, program_input)).collect();
+
+            let config = PassConfig::all();
+            let statistics = run_passes(&mut program, &config);
+            assert!(statistics.inlined_calls > 0 || !input.contains("function"), "{}", input);
+
+            for (program_input, expected) in ["0", "3", "12"].iter().zip(&expected) {
+                assert_eq!(interpret(&program, program_input), *expected, "{}", input);
+                assert_eq!(emulate(&program, &config, program_input), *expected, "{}", input);
+            }
+        }
+    }
+
+    #[test]
+    fn test_disabled_passes_leave_program() {
+        let mut program = get_program(PROGRAMS[0]);
+        let before = format!("{:?}", program.get_fn("main").bb_graph);
+        assert_eq!(run_passes(&mut program, &PassConfig::default()), PassStatistics::default());
+        assert_eq!(format!("{:?}", program.get_fn("main").bb_graph), before);
+    }
+}
@@ -0,0 +1,326 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+
+use crate::assembler::{decode, get_branch_target, get_machine_code_instructions, AssembleError, MachineCodeInstruction};
+use crate::code_gen::{fits_in_immediate, AssemblyInstruction, AssemblyInstructions};
+use crate::object_file::{LineEntry, ObjectFile, ObjectSymbol};
+
+type Constant = isize;
+
+/// A rewrite of `window` instructions in a row, which returns what to put in their place or
+/// None if the instructions do not match.
+pub struct Rule {
+    pub name: &'static str,
+    window: usize,
+    rewrite: fn(&[AssemblyInstruction]) -> Option<Vec<AssemblyInstruction>>,
+}
+
+/// The rules in the order they are tried at every position.
+pub const RULES: &[Rule] = &[
+    Rule { name: "fold-constant-addi", window: 2, rewrite: fold_constant_addi },
+    Rule { name: "fold-constant-cmp", window: 2, rewrite: fold_constant_cmp },
+    Rule { name: "self-move", window: 1, rewrite: remove_self_move },
+    Rule { name: "muli-to-lshi", window: 1, rewrite: muli_to_lshi },
+    Rule { name: "branch-to-next", window: 1, rewrite: remove_branch_to_next },
+];
+
+/// How often every rule fired.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct PeepholeStatistics {
+    fired: BTreeMap<&'static str, usize>,
+}
+
+impl PeepholeStatistics {
+    pub fn get(&self, rule_name: &str) -> usize {
+        self.fired.get(rule_name).copied().unwrap_or(0)
+    }
+
+    pub fn total(&self) -> usize {
+        self.fired.values().sum()
+    }
+}
+
+impl fmt::Display for PeepholeStatistics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for rule in RULES {
+            writeln!(f, "{}: {}", rule.name, self.get(rule.name))?;
+        }
+        writeln!(f, "peephole rewrites: {}", self.total())
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum PeepholeError {
+    /// a word of the image that is not an instruction, by its byte address
+    IllegalInstruction { address: u32, word: MachineCodeInstruction },
+    Assemble(AssembleError),
+}
+
+impl fmt::Display for PeepholeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PeepholeError::IllegalInstruction { address, word } => write!(f, "illegal instruction {:#010x} at {}", word, address),
+            PeepholeError::Assemble(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+// ADDI and SUBI as adding a constant, (a, b, c) for a = b + c
+fn get_added_constant(instruction: &AssemblyInstruction) -> Option<(u8, u8, Constant)> {
+    match *instruction {
+        AssemblyInstruction::ADDI(a, b, c) => Some((a, b, c)),
+        AssemblyInstruction::SUBI(a, b, c) => Some((a, b, -c)),
+        _ => None,
+    }
+}
+
+// a = b + c1; a = a + c2 is a = b + (c1 + c2)
+fn fold_constant_addi(instructions: &[AssemblyInstruction]) -> Option<Vec<AssemblyInstruction>> {
+    let (a, b, c1) = get_added_constant(&instructions[0])?;
+    let (second_a, second_b, c2) = get_added_constant(&instructions[1])?;
+    let sum = c1 + c2;
+    (second_a == a && second_b == a && fits_in_immediate(sum)).then(|| vec![AssemblyInstruction::ADDI(a, b, sum)])
+}
+
+// a constant compared with a constant is known
+fn fold_constant_cmp(instructions: &[AssemblyInstruction]) -> Option<Vec<AssemblyInstruction>> {
+    match (instructions[0], instructions[1]) {
+        (AssemblyInstruction::ADDI(a, 0, c1), AssemblyInstruction::CMPI(second_a, second_b, c2)) if second_a == a && second_b == a => {
+            Some(vec![AssemblyInstruction::ADDI(a, 0, c1.cmp(&c2) as Constant)])
+        },
+        _ => None,
+    }
+}
+
+// instructions that leave their register as it was
+fn remove_self_move(instructions: &[AssemblyInstruction]) -> Option<Vec<AssemblyInstruction>> {
+    match instructions[0] {
+        AssemblyInstruction::ADD(a, b, 0)
+        | AssemblyInstruction::ADD(a, 0, b)
+        | AssemblyInstruction::SUB(a, b, 0)
+        | AssemblyInstruction::OR(a, b, 0)
+        | AssemblyInstruction::OR(a, 0, b)
+        | AssemblyInstruction::ADDI(a, b, 0)
+        | AssemblyInstruction::SUBI(a, b, 0)
+        | AssemblyInstruction::ORI(a, b, 0)
+        | AssemblyInstruction::LSHI(a, b, 0)
+        | AssemblyInstruction::ASHI(a, b, 0)
+        | AssemblyInstruction::MULI(a, b, 1) if a == b => Some(vec![]),
+        _ => None,
+    }
+}
+
+// a left shift wraps the same way the multiplication does
+fn muli_to_lshi(instructions: &[AssemblyInstruction]) -> Option<Vec<AssemblyInstruction>> {
+    match instructions[0] {
+        AssemblyInstruction::MULI(a, b, c) if c > 1 && (c as usize).is_power_of_two() => {
+            Some(vec![AssemblyInstruction::LSHI(a, b, c.trailing_zeros() as Constant)])
+        },
+        _ => None,
+    }
+}
+
+// taken or not, control ends up at the next instruction
+fn remove_branch_to_next(instructions: &[AssemblyInstruction]) -> Option<Vec<AssemblyInstruction>> {
+    match instructions[0] {
+        AssemblyInstruction::BEQ(_, 1)
+        | AssemblyInstruction::BNE(_, 1)
+        | AssemblyInstruction::BLT(_, 1)
+        | AssemblyInstruction::BGE(_, 1)
+        | AssemblyInstruction::BLE(_, 1)
+        | AssemblyInstruction::BGT(_, 1) => Some(vec![]),
+        _ => None,
+    }
+}
+
+// the word address a branch or JSR goes to
+fn get_target_index(address: usize, instruction: &AssemblyInstruction) -> Option<usize> {
+    get_branch_target(address, instruction).map(|target| (target / 4) as usize)
+}
+
+// one pass of every rule over the code, with where every old address ended up, None if nothing changed
+fn rewrite_once(instructions: &mut AssemblyInstructions, statistics: &mut PeepholeStatistics) -> Option<Vec<usize>> {
+    // only the first instruction of a window may be jumped to, or the rewrite would change what the jump runs
+    let targets: HashSet<usize> = instructions.iter().enumerate()
+        .filter_map(|(address, instruction)| get_target_index(address, instruction))
+        .collect();
+
+    let mut rewritten = AssemblyInstructions::new();
+    // where every old address ended up, and where every new instruction came from
+    let mut new_addresses = Vec::with_capacity(instructions.len() + 1);
+    let mut old_addresses = Vec::new();
+
+    let mut address = 0;
+    'instructions: while address < instructions.len() {
+        for rule in RULES {
+            let end = address + rule.window;
+            if end > instructions.len() || (address + 1..end).any(|inner| targets.contains(&inner)) {
+                continue;
+            }
+            if let Some(replacement) = (rule.rewrite)(&instructions[address..end]) {
+                *statistics.fired.entry(rule.name).or_default() += 1;
+                new_addresses.extend((address..end).map(|_| rewritten.len()));
+                old_addresses.extend(replacement.iter().map(|_| address));
+                rewritten.extend(replacement);
+                address = end;
+                continue 'instructions;
+            }
+        }
+        new_addresses.push(rewritten.len());
+        old_addresses.push(address);
+        rewritten.push(instructions[address]);
+        address += 1;
+    }
+    new_addresses.push(rewritten.len());
+
+    if rewritten == *instructions {
+        return None;
+    }
+
+    // jumps move along with the code they point at
+    for (address, old_address) in old_addresses.into_iter().enumerate() {
+        let instruction = rewritten[address];
+        if let Some(target) = get_target_index(old_address, &instruction).and_then(|target| new_addresses.get(target)) {
+            rewritten[address] = instruction.with_target(address, *target);
+        }
+    }
+
+    *instructions = rewritten;
+    Some(new_addresses)
+}
+
+/// Rewrites the code with the rules until none of them matches any more, keeping every branch
+/// and JSR pointed at the same instruction. Returns the new code, where every old address and
+/// the end of the code ended up, and how often each rule fired.
+pub fn optimize(instructions: &[AssemblyInstruction]) -> (AssemblyInstructions, Vec<usize>, PeepholeStatistics) {
+    let mut instructions = instructions.to_vec();
+    let mut addresses: Vec<usize> = (0..=instructions.len()).collect();
+    let mut statistics = PeepholeStatistics::default();
+    while let Some(new_addresses) = rewrite_once(&mut instructions, &mut statistics) {
+        for address in &mut addresses {
+            *address = new_addresses[*address];
+        }
+    }
+    (instructions, addresses, statistics)
+}
+
+/// Runs the rules over the code of a linked image. The entry point, the symbols and the line
+/// table move along with the code they point at.
+pub fn optimize_object(object: &ObjectFile) -> Result<(ObjectFile, PeepholeStatistics), PeepholeError> {
+    let instructions = object.code.iter().enumerate()
+        .map(|(index, word)| decode(*word).ok_or(PeepholeError::IllegalInstruction { address: index as u32 * 4, word: *word }))
+        .collect::<Result<AssemblyInstructions, _>>()?;
+    let (instructions, addresses, statistics) = optimize(&instructions);
+    let move_address = |address: u32| addresses[address as usize / 4] as u32 * 4;
+
+    let optimized = ObjectFile {
+        entry_point: move_address(object.entry_point),
+        code: get_machine_code_instructions(instructions).map_err(PeepholeError::Assemble)?,
+        data: object.data.clone(),
+        symbols: object.symbols.iter().map(|symbol| ObjectSymbol { name: symbol.name.clone(), address: move_address(symbol.address) }).collect(),
+        line_table: object.line_table.as_ref().map(|line_table| {
+            line_table.iter().map(|entry| LineEntry { address: move_address(entry.address), line: entry.line }).collect()
+        }),
+    };
+    Ok((optimized, statistics))
+}
+
+#[cfg(test)]
+mod peephole_tests {
+    use super::*;
+    use crate::assembler::get_machine_code_instructions;
+    use crate::code_gen::CodeGeneration;
+    use crate::emulator::Emulator;
+    use crate::parser::Parser;
+    use AssemblyInstruction::*;
+
+    fn run(instructions: &[AssemblyInstruction], input: &str) -> String {
+        let machine_code = get_machine_code_instructions(instructions.to_vec()).unwrap();
+        let mut emulator = Emulator::new(&machine_code, input.as_bytes(), Vec::new()).unwrap();
+        emulator.run().unwrap();
+        String::from_utf8(emulator.get_writer().clone()).unwrap()
+    }
+
+    #[test]
+    fn test_rules() {
+        let (optimized, _, statistics) = optimize(&[
+            ADDI(1, 0, 3),
+            ADDI(1, 1, 4),
+            SUBI(1, 1, 2),
+            ADDI(2, 0, 5),
+            CMPI(2, 2, 9),
+            MULI(3, 1, 8),
+            ADD(4, 4, 0),
+            WRD(1),
+            WRD(2),
+            WRD(3),
+            RET(0),
+        ]);
+
+        assert_eq!(optimized, vec![ADDI(1, 0, 5), ADDI(2, 0, -1), LSHI(3, 1, 3), WRD(1), WRD(2), WRD(3), RET(0)]);
+        assert_eq!(statistics.get("fold-constant-addi"), 2);
+        assert_eq!(statistics.get("fold-constant-cmp"), 1);
+        assert_eq!(statistics.get("muli-to-lshi"), 1);
+        assert_eq!(statistics.get("self-move"), 1);
+        assert_eq!(statistics.total(), 5);
+        assert_eq!(run(&optimized, ""), "5 -1 40 ");
+    }
+
+    #[test]
+    fn test_branches_follow_code() {
+        let code = [
+            RDD(1),
+            ADDI(2, 0, 0),
+            BLE(1, 6),
+            ADD(2, 2, 1),
+            ADD(3, 3, 0),
+            SUBI(1, 1, 1),
+            BEQ(0, -4),
+            BEQ(0, 1),
+            WRD(2),
+            RET(0),
+        ];
+        let (optimized, _, statistics) = optimize(&code);
+
+        assert_eq!(optimized, vec![RDD(1), ADDI(2, 0, 0), BLE(1, 4), ADD(2, 2, 1), SUBI(1, 1, 1), BEQ(0, -3), WRD(2), RET(0)]);
+        assert_eq!(statistics.get("branch-to-next"), 1);
+        assert_eq!(run(&optimized, "4"), run(&code, "4"));
+    }
+
+    #[test]
+    fn test_jump_target_not_folded() {
+        // the second ADDI is where the loop comes back to, so it cannot be merged with the first
+        let code = [ADDI(1, 0, 3), ADDI(1, 1, -1), BGT(1, -1), WRD(1), RET(0)];
+        let (optimized, _, statistics) = optimize(&code);
+        assert_eq!(optimized, code.to_vec());
+        assert_eq!(statistics.total(), 0);
+    }
+
+    #[test]
+    fn test_generated_code() {
+        let input = "main var a; { let a <- call InputNum(); call OutputNum(1 + 2); call OutputNum(a * 4) }.";
+        let mut parser = Parser::new(input.to_string());
+        parser.parse_computation();
+        let mut code_generation = CodeGeneration::new(&mut parser.internal_program.get_fn_mut("main").bb_graph);
+        code_generation.generate_code();
+        let code = code_generation.get_assembly_instructions();
+
+        let (optimized, _, statistics) = optimize(&code);
+        assert!(optimized.len() < code.len());
+        assert!(statistics.get("fold-constant-addi") >= 1);
+        assert_eq!(statistics.get("muli-to-lshi"), 1);
+        assert_eq!(run(&optimized, "5"), run(&code, "5"));
+        assert_eq!(run(&optimized, "5"), "3 20 ");
+    }
+
+    #[test]
+    fn test_illegal_word_in_object() {
+        let mut code = get_machine_code_instructions(vec![ADDI(1, 0, 3), WRD(1), RET(0)]).unwrap();
+        code.insert(2, u32::MAX);
+        let object = ObjectFile { entry_point: 0, code, data: Vec::new(), symbols: Vec::new(), line_table: None };
+
+        let error = optimize_object(&object).unwrap_err();
+        assert_eq!(error, PeepholeError::IllegalInstruction { address: 8, word: u32::MAX });
+        assert_eq!(error.to_string(), "illegal instruction 0xffffffff at 8");
+    }
+}
@@ -0,0 +1,147 @@
+use std::collections::HashSet;
+
+use crate::cfg_simplification::remove_empty_joins;
+use crate::function::{find_line, get_operation, remove_instruction};
+use crate::instruction::Operation;
+use crate::live_analysis::BasicBlockGraph;
+use crate::program::Program;
+
+type LineNumber = isize;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct PhiEliminationStatistics {
+    pub removed_phis: usize,
+    pub collapsed_blocks: usize,
+}
+
+fn get_phi_lines(g: &BasicBlockGraph) -> Vec<LineNumber> {
+    g.node_weights()
+        .flat_map(|block| &block.instructions)
+        .filter(|instruction| matches!(instruction.operation, Operation::Phi(_, _)))
+        .map(|instruction| instruction.get_line_number())
+        .collect()
+}
+
+// The phis reachable from one through their operands. When everything else they read is a
+// single value they can only ever hold that value, which covers phi(x, x), phi(x, self) and
+// loops of phis that only pass one value around.
+fn get_redundant_phis(g: &BasicBlockGraph, line: LineNumber) -> Option<(HashSet<LineNumber>, LineNumber)> {
+    let mut phis = HashSet::new();
+    let mut values = HashSet::new();
+    let mut work = vec![line];
+    while let Some(line) = work.pop() {
+        match get_operation(g, line) {
+            Some(Operation::Phi(left, right)) => {
+                if phis.insert(line) {
+                    work.extend([left, right]);
+                }
+            },
+            _ => {
+                values.insert(line);
+                if values.len() > 1 {
+                    return None;
+                }
+            },
+        }
+    }
+    values.into_iter().next().map(|value| (phis, value))
+}
+
+// an earlier phi in the same block with the same operands
+fn get_repeated_phi(g: &BasicBlockGraph, line: LineNumber) -> Option<(HashSet<LineNumber>, LineNumber)> {
+    let (block, position) = find_line(g, line)?;
+    let operation = g[block].instructions[position].operation;
+    g[block].instructions[..position].iter()
+        .find(|instruction| instruction.operation == operation)
+        .map(|instruction| (HashSet::from([line]), instruction.get_line_number()))
+}
+
+// Replaces the phis by the value they hold. Returns the phis that used them, which may have
+// become redundant in turn.
+fn remove_phis(g: &mut BasicBlockGraph, phis: &HashSet<LineNumber>, value: LineNumber) -> Vec<LineNumber> {
+    for line in phis {
+        let (block, position) = find_line(g, *line).unwrap();
+        remove_instruction(g, block, position);
+    }
+
+    let mut users = Vec::new();
+    for instruction in g.node_weights_mut().flat_map(|block| &mut block.instructions) {
+        let operation = instruction.operation;
+        instruction.operation.map_lines(|line| if phis.contains(&line) { value } else { line });
+        if instruction.operation != operation && matches!(operation, Operation::Phi(_, _)) {
+            users.push(instruction.get_line_number());
+        }
+    }
+    users
+}
+
+/// Removes phis that always have the same value, because both operands are the same line, one
+/// of them is the phi itself or they only pass that value around between each other, and phis
+/// that repeat an earlier phi of their block. Their users read the value instead, which can make
+/// phis using them redundant in turn. Join blocks that are
+/// left with nothing to do are then taken out of the graph.
+pub fn eliminate_phis(program: &mut Program) -> PhiEliminationStatistics {
+    let mut statistics = PhiEliminationStatistics::default();
+    for function in program.functions.values_mut() {
+        let g = &mut function.bb_graph;
+
+        let mut work = get_phi_lines(g);
+        while let Some(line) = work.pop() {
+            if let Some((phis, value)) = get_redundant_phis(g, line).or_else(|| get_repeated_phi(g, line)) {
+                statistics.removed_phis += phis.len();
+                work.extend(remove_phis(g, &phis, value));
+            }
+        }
+
+        statistics.collapsed_blocks += remove_empty_joins(g);
+    }
+    statistics
+}
+
+#[cfg(test)]
+mod phi_elimination_tests {
+    use super::*;
+    use crate::test_helpers::{get_program, interpret};
+
+    #[test]
+    fn test_trivial_loop_phi() {
+        // b is the same before and inside the loop, so its loop phi has the same line twice
+        let input = "main var i, a, b; { let i <- 0; let a <- call InputNum(); let b <- a; while i < 3 do let b <- a; let i <- i + 1 od; call OutputNum(b) }.";
+        let mut program = get_program(input);
+        let expected = interpret(&program, "4");
+
+        let statistics = eliminate_phis(&mut program);
+        assert_eq!(statistics.removed_phis, 1);
+        assert_eq!(get_phi_lines(&program.get_fn("main").bb_graph).len(), 1);
+        assert_eq!(interpret(&program, "4"), expected);
+        assert_eq!(expected, "4 ");
+    }
+
+    #[test]
+    fn test_transitive_phis() {
+        // the phi in the loop header and the one after the if only hand a to each other
+        let input = "main var i, a, b; { let i <- 0; let a <- call InputNum(); let b <- a; while i < 3 do if i > 1 then let b <- a fi; let i <- i + 1 od; call OutputNum(b) }.";
+        let mut program = get_program(input);
+        let expected = interpret(&program, "6");
+
+        assert_eq!(eliminate_phis(&mut program).removed_phis, 2);
+        assert_eq!(get_phi_lines(&program.get_fn("main").bb_graph).len(), 1);
+        assert_eq!(interpret(&program, "6"), expected);
+        assert_eq!(expected, "6 ");
+    }
+
+    #[test]
+    fn test_empty_join_collapsed() {
+        // once the phis of b are gone the inner join only branches to the outer one
+        let input = "main var i, a, b; { let i <- 0; let a <- call InputNum(); let b <- a; while i < 3 do if a > 2 then if a > 5 then let b <- a fi fi; let i <- i + 1 od; call OutputNum(b) }.";
+        let mut program = get_program(input);
+        let expected: Vec<String> = ["1", "4", "7"].iter().map(|input| interpret(&program, input)).collect();
+        let block_count = program.get_fn("main").bb_graph.node_count();
+
+        let statistics = eliminate_phis(&mut program);
+        assert_eq!(statistics, PhiEliminationStatistics { removed_phis: 3, collapsed_blocks: 1 });
+        assert_eq!(program.get_fn("main").bb_graph.node_count(), block_count - 1);
+        assert_eq!(["1", "4", "7"].iter().map(|input| interpret(&program, input)).collect::<Vec<_>>(), expected);
+        assert_eq!(expected, vec!["1 ", "4 ", "7 "]);
+    }
+}
@@ -1,19 +1,21 @@
 use crate::{
     basic_block::{BasicBlock, BasicBlockType, VariableType},
     constant_block::ConstantBlock,
-    function::Function,
+    function::{get_next_line, Function},
     instruction::{Operation, Instruction},
 };
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use petgraph::graph::NodeIndex;
-use std::io;
 
 #[derive(Debug)]
 pub struct Program {
     pub functions: HashMap<String, Function>,
     pub current_function: String,
     pub constant_block: ConstantBlock,
+    // globals declared by main, mapped to their offset from the global pointer
+    pub globals: HashMap<String, isize>,
+    pub globals_used_in_functions: HashSet<String>,
 }
 
 impl Program {
@@ -26,11 +28,20 @@ impl Program {
             // current_function: 0,
             current_function: "main".to_string(),
             constant_block: ConstantBlock::new(),
+            globals: HashMap::new(),
+            globals_used_in_functions: HashSet::new(),
         }
     }
 
     pub fn add_function(&mut self, name: &str, is_void: bool) {
-        let new_fn = Function::new(name.to_string(), is_void);
+        let mut new_fn = Function::new(name.to_string(), is_void);
+
+        // every global is reached through memory inside a function
+        new_fn.globals = self.globals.clone();
+        for global_name in self.globals.keys() {
+            new_fn.get_curr_bb_mut().variable_table.insert(global_name.clone(), VariableType::InMemory);
+        }
+
         self.functions.insert(name.to_string(), new_fn);
         self.current_function = name.to_string();
     }
@@ -54,11 +65,11 @@ impl Program {
     pub fn verify_function(&self, name: &str) {
         // ignore predefined functions
         match name {
-            "InputNum" | "OutputNum" | "OutputNewLine" => return (),
+            "InputNum" | "OutputNum" | "OutputNewLine" => return,
             _ => (),
         }
 
-        if !self.functions.contains_key(&name.to_string()) {
+        if !self.functions.contains_key(name) {
             panic!("{} is not declared as a function", name);
         }
     }
@@ -72,9 +83,6 @@ impl Program {
     }
 
 
-    pub fn get_number_of_functions(&self) -> usize {
-        self.functions.len()
-    }
 
     pub fn get_curr_fn_name(&self) -> String {
         self.current_function.clone()
@@ -89,7 +97,7 @@ impl Program {
     }
 
     pub fn get_curr_fn(&self) -> &Function {
-        &self.functions.get(&self.current_function).unwrap()
+        self.functions.get(&self.current_function).unwrap()
     }
 
     pub fn get_curr_fn_mut(&mut self) -> &mut Function {
@@ -97,7 +105,7 @@ impl Program {
     }
     
     pub fn get_fn(&self, fn_name: &str) -> &Function {
-        &self.functions.get(fn_name).unwrap()
+        self.functions.get(fn_name).unwrap()
     }
     
     pub fn get_fn_mut(&mut self, fn_name: &str) -> &mut Function {
@@ -128,9 +136,6 @@ impl Program {
         self.get_curr_fn_mut().add_join_block(left_parent, right_parent)
     }
     
-    pub fn add_join_block_from_one(&mut self) -> NodeIndex {
-        self.get_curr_fn_mut().add_node_to_curr(BasicBlockType::Join)
-    }
 
     pub fn add_cond_block(&mut self) -> NodeIndex {
         self.get_curr_fn_mut().add_node_to_curr(BasicBlockType::Conditional)
@@ -140,12 +145,6 @@ impl Program {
         self.get_curr_fn_mut().add_node_to_index(node_index, BasicBlockType::Follow)
     }
 
-    // Wrapper for join_with_target function in Function
-    pub fn join_blocks_with_target(&mut self, source_index: NodeIndex, target_index: NodeIndex) -> Vec<(Operation, String)> {
-        self.get_curr_fn_mut().join_with_target(source_index, target_index)
-    }
-
-    ///returns the basic block that you are altering in mutable form
     pub fn add_instruction_to_curr_block(&mut self, instruction_to_add: Instruction) {
         let curr_block = self.get_curr_fn_mut().get_curr_bb_mut();
         curr_block.add_instruction(instruction_to_add);
@@ -173,67 +172,90 @@ impl Program {
         // check if its in dominator table
         if self.is_operation_in_dom_table(operation) {
             // returns instruction line number if the same operation already exists
-            return Some(self.get_dom_line_number(&operation));
+            return Some(self.get_dom_line_number(operation));
         }
 
         // else, if the operation does not exist in the table
         // then add operation to dominator table
-        self.insert_operation_to_dom_table(&operation, line_number + 1);
+        self.insert_operation_to_dom_table(operation, line_number + 1);
 
         None
     }
 
     pub fn assign_variable_to_curr_block(&mut self, var_name: &String, line_number: isize) {
-        self.get_curr_block_mut().assign_variable(&var_name, line_number);
+        self.get_curr_block_mut().assign_variable(var_name, line_number);
     }
 
     pub fn assign_variable_to_any_block(&mut self, block_index: NodeIndex, var_name: &String, line_number: isize) {
-        self.get_curr_fn_mut().get_bb_mut(&block_index).unwrap().assign_variable(&var_name, line_number);
+        self.get_curr_fn_mut().get_bb_mut(&block_index).unwrap().assign_variable(var_name, line_number);
     }
 
     pub fn declare_variable_to_curr_block(&mut self, var_name: &String) {
-        self.get_curr_block_mut().declare_variable(&var_name);
+        // the variable reads as the zero constant until it is assigned, possibly through a phi
+        self.add_constant(0);
+        self.get_curr_block_mut().declare_variable(var_name);
     }
 
     pub fn get_variable(&mut self, variable: &String) -> VariableType {
         self.get_curr_block().get_variable(variable)
     }
 
+    // globals are laid out one word apart below the global pointer
+    pub fn declare_global(&mut self, var_name: &String) {
+        let offset = -4 * (self.globals.len() as isize + 1);
+        self.globals.insert(var_name.clone(), offset);
+        self.declare_variable_to_curr_block(var_name);
+    }
 
-    pub fn add_constant(&mut self, constant: isize) {
-        self.constant_block.add_constant(constant);
+    // a local or parameter with the same name shadows the global
+    pub fn declare_local(&mut self, var_name: &String) {
+        self.get_curr_fn_mut().globals.remove(var_name);
+        self.declare_variable_to_curr_block(var_name);
     }
 
-    pub fn get_constant(&mut self, constant: isize) -> isize {
-        self.constant_block.get_constant(constant)
+    /// returns the offset of a global the current function reaches through memory
+    pub fn get_global_offset(&mut self, var_name: &String) -> Option<isize> {
+        let offset = self.get_curr_fn().globals.get(var_name).copied();
+        if offset.is_some() && self.current_function != "main" {
+            self.globals_used_in_functions.insert(var_name.clone());
+        }
+        offset
     }
 
-    pub fn get_constant_table(&self) -> &HashMap<isize, Instruction> {
-        self.constant_block.get_constant_table()
+    /// forgets the values of globals in the current block so the next use reloads them
+    pub fn invalidate_globals(&mut self) {
+        let global_names: Vec<String> = self.get_curr_fn().globals.keys().cloned().collect();
+        for global_name in global_names {
+            self.get_curr_block_mut().variable_table.insert(global_name, VariableType::InMemory);
+        }
     }
 
-    pub fn input_num(&self) -> isize {
-        let mut input = String::new();
-        println!("Enter an expression: ");
-        
-        io::stdin()
-            .read_line(&mut input)
-            .expect("Failed to read the line.");
+    /// main keeps globals in registers unless a function also touches them
+    pub fn share_globals_with_functions(&mut self) {
+        let shared_globals: Vec<String> = self.globals_used_in_functions.iter().cloned().collect();
+        for global_name in shared_globals {
+            let offset = self.globals[&global_name];
+            self.get_fn_mut("main").globals.insert(global_name.clone(), offset);
+            self.get_fn_mut("main").get_curr_bb_mut().variable_table.insert(global_name, VariableType::InMemory);
+        }
+    }
 
-        let input = input.trim();
 
-        match input.parse::<isize>() {
-            Ok(number) => number,
-            Err(_) => panic!("You printed an invalid positive integer"),
-        }
+    pub fn add_constant(&mut self, constant: isize) {
+        self.constant_block.add_constant(constant);
     }
 
-    pub fn output_num(&self, num: isize) {
-        println!("OutputNum: {}", num * -1);
+    pub fn get_constant(&mut self, constant: isize) -> isize {
+        self.constant_block.get_constant(constant)
     }
-    
-    pub fn output_new_line(&self) {
-        println!("\n");
+
+    /// The first line after every line of every function, new instructions are numbered from it.
+    pub fn get_next_line(&self) -> isize {
+        self.functions.values().map(|function| get_next_line(&function.bb_graph)).max().unwrap_or(1)
+    }
+
+    pub fn get_constant_table(&self) -> &HashMap<isize, Instruction> {
+        self.constant_block.get_constant_table()
     }
 }
 
@@ -242,11 +264,13 @@ mod program_test {
     use super::*;
 
     #[test]
-    fn test_stuff() {
-
-        let program = Program::new();
-        program.output_num(program.input_num());
-        program.output_new_line();
-        program.output_num(program.input_num());
+    fn test_predefined_functions() {
+        let mut program = Program::new();
+        program.add_predefined_functions();
+
+        assert!(!program.get_fn("InputNum").is_void);
+        assert!(program.get_fn("OutputNum").is_void);
+        assert!(program.get_fn("OutputNewLine").is_void);
+        assert_eq!(program.get_number_of_parameters_of("OutputNum"), 1);
     }
 }
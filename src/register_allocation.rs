@@ -1,7 +1,6 @@
 use petgraph::graph::NodeIndex;
 use std::collections::HashMap;
-use petgraph::graph::{Graph, UnGraph};
-use petgraph::Undirected;
+use petgraph::graph::UnGraph;
 
 type Cluster = LineNumbers;
 type LineNumbers = Vec<LineNumber>;
@@ -51,7 +50,7 @@ pub fn backtrack<N, E>(
     if graph.neighbors(node).all(|neighbor| {
         color_mapping
             .get(&neighbor)
-            .map_or(true, |&neighbor_color| neighbor_color != current_color)
+            .is_none_or(|&neighbor_color| neighbor_color != current_color)
     }) {
         // color is valid, assign it
         color_mapping.insert(node, current_color);
@@ -102,16 +101,13 @@ mod graph_test {
 
         let mut parser = Parser::new(input);
 
-        let line_number = parser.parse_computation();
-
-        // Verify that the add operation is correct
-        let instructions = &parser.internal_program.get_curr_block().instructions;
+        parser.parse_computation();
 
         let bbg = &parser.internal_program.get_curr_fn().bb_graph;
 
         let graph = get_interference_graph(&parser.internal_program.get_curr_fn().bb_graph);
 
-        let cluster_possibilities = get_clusters(&bbg);
+        let cluster_possibilities = get_clusters(bbg);
         let upgraded_ig = get_upgraded_interference_graph(&graph, &cluster_possibilities);
         println!("{:?}", Dot::with_config(&upgraded_ig, &[Config::EdgeNoLabel]));
         println!("{:?}", Dot::with_config(&graph, &[Config::EdgeNoLabel]));
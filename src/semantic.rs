@@ -0,0 +1,666 @@
+use crate::diagnostic::Diagnostic;
+use crate::tokenizer::{Span, Token, Tokenizer};
+use std::collections::{HashMap, HashSet};
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum SymbolKind {
+    Global,
+    Parameter,
+    Local,
+    Function { is_void: bool, parameter_count: usize },
+}
+
+#[derive(Debug, Clone)]
+pub struct Symbol {
+    pub name: String,
+    pub kind: SymbolKind,
+    // predefined functions have no declaration in the source
+    pub span: Option<Span>,
+    // how many times a variable is read
+    pub reads: usize,
+}
+
+impl Symbol {
+    pub fn new(name: String, kind: SymbolKind, span: Option<Span>) -> Self {
+        Self { name, kind, span, reads: 0 }
+    }
+}
+
+pub type Scope = HashMap<String, Symbol>;
+
+/// Functions and globals live in the outermost scopes, every function body opens one more
+/// scope for its parameters and locals which is kept around once the body has been checked.
+#[derive(Debug)]
+pub struct SymbolTable {
+    pub functions: Scope,
+    pub globals: Scope,
+    pub function_scopes: HashMap<String, Scope>,
+    // the functions each function calls, main included
+    pub calls: HashMap<String, HashSet<String>>,
+    // the function whose body is being checked, None while checking main
+    current_function: Option<(String, Scope)>,
+}
+
+impl SymbolTable {
+    pub fn new() -> Self {
+        let mut functions = Scope::new();
+        for (name, is_void, parameter_count) in [("InputNum", false, 0), ("OutputNum", true, 1), ("OutputNewLine", true, 0)] {
+            let kind = SymbolKind::Function { is_void, parameter_count };
+            functions.insert(name.to_string(), Symbol::new(name.to_string(), kind, None));
+        }
+
+        Self {
+            functions,
+            globals: Scope::new(),
+            function_scopes: HashMap::new(),
+            calls: HashMap::new(),
+            current_function: None,
+        }
+    }
+
+    pub fn get_curr_fn_name(&self) -> String {
+        match &self.current_function {
+            Some((name, _)) => name.clone(),
+            None => "main".to_string(),
+        }
+    }
+
+    pub fn enter_function(&mut self, name: &str) {
+        self.current_function = Some((name.to_string(), Scope::new()));
+    }
+
+    pub fn exit_function(&mut self) {
+        if let Some((name, scope)) = self.current_function.take() {
+            self.function_scopes.insert(name, scope);
+        }
+    }
+
+    /// declares a function, returning the earlier declaration if the name is taken
+    pub fn declare_function(&mut self, symbol: Symbol) -> Result<(), Symbol> {
+        declare_in(&mut self.functions, symbol)
+    }
+
+    /// declares a variable in the innermost scope, returning the earlier declaration if the name is taken
+    pub fn declare_variable(&mut self, symbol: Symbol) -> Result<(), Symbol> {
+        match &mut self.current_function {
+            Some((_, scope)) => declare_in(scope, symbol),
+            None => declare_in(&mut self.globals, symbol),
+        }
+    }
+
+    pub fn resolve_variable(&self, name: &str) -> Option<&Symbol> {
+        self.current_function.as_ref()
+            .and_then(|(_, scope)| scope.get(name))
+            .or_else(|| self.globals.get(name))
+    }
+
+    pub fn resolve_variable_mut(&mut self, name: &str) -> Option<&mut Symbol> {
+        match &mut self.current_function {
+            Some((_, scope)) if scope.contains_key(name) => scope.get_mut(name),
+            _ => self.globals.get_mut(name),
+        }
+    }
+
+    pub fn resolve_function(&self, name: &str) -> Option<&Symbol> {
+        self.functions.get(name)
+    }
+}
+
+fn declare_in(scope: &mut Scope, symbol: Symbol) -> Result<(), Symbol> {
+    if let Some(previous) = scope.get(&symbol.name) {
+        return Err(previous.clone());
+    }
+    scope.insert(symbol.name.clone(), symbol);
+    Ok(())
+}
+
+// variables definitely assigned on the path being checked, None once the path has returned
+type Assigned = Option<HashSet<String>>;
+
+/// Walks the tokens of a whole program before any IR is built, resolving every name
+/// against the symbol table. Syntax errors are still left to panic the way the parser does.
+pub struct SemanticAnalyzer {
+    input: String,
+    tokenizer: Tokenizer,
+    pub symbol_table: SymbolTable,
+    pub diagnostics: Vec<Diagnostic>,
+    // where each while starts, per function and in source order
+    pub loop_spans: HashMap<String, Vec<Span>>,
+    assigned: Assigned,
+    // the globals each function assigns in its own body, main included
+    global_assignments: HashMap<String, HashSet<String>>,
+    // the globals each function may assign, counting what its callees assign
+    may_assign: HashMap<String, HashSet<String>>,
+}
+
+impl SemanticAnalyzer {
+    pub fn new(input: String) -> Self {
+        Self {
+            tokenizer: Tokenizer::new(input.clone()),
+            input,
+            symbol_table: SymbolTable::new(),
+            diagnostics: Vec::new(),
+            loop_spans: HashMap::new(),
+            assigned: Some(HashSet::new()),
+            global_assignments: HashMap::new(),
+            may_assign: HashMap::new(),
+        }
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.diagnostics.iter().any(|diagnostic| diagnostic.is_error())
+    }
+
+    /// Checks the whole program. Calls and function bodies need to know which globals the rest of
+    /// the program may assign, so a first pass over the same input collects the assignments.
+    pub fn analyze(&mut self) {
+        let mut first_pass = SemanticAnalyzer::new(self.input.clone());
+        first_pass.check_computation();
+        self.may_assign = first_pass.get_may_assign();
+
+        self.check_computation();
+    }
+
+    // what each function assigns itself together with what the functions it calls may assign
+    fn get_may_assign(&self) -> HashMap<String, HashSet<String>> {
+        let mut may_assign = self.global_assignments.clone();
+        loop {
+            let mut changed = false;
+            for (caller, callees) in &self.symbol_table.calls {
+                let assigned: HashSet<String> = callees.iter().filter_map(|callee| may_assign.get(callee)).flatten().cloned().collect();
+                let caller_assigned = may_assign.entry(caller.clone()).or_default();
+                let count = caller_assigned.len();
+                caller_assigned.extend(assigned);
+                changed |= caller_assigned.len() != count;
+            }
+            if !changed {
+                return may_assign;
+            }
+        }
+    }
+
+    fn check_computation(&mut self) {
+        self.match_token(Token::Main);
+
+        if self.tokenizer.peek_token() == Token::Variable {
+            self.check_var_decl(SymbolKind::Global);
+        }
+
+        while let Token::Void | Token::Function = self.tokenizer.peek_token() {
+            self.check_func_decl();
+        }
+
+        // globals start out unassigned in main
+        self.assigned = Some(HashSet::new());
+        self.match_token(Token::OpenBrace);
+        self.check_stat_sequence();
+        self.match_token(Token::CloseBrace);
+        self.match_token(Token::EOF);
+    }
+
+    fn check_var_decl(&mut self, kind: SymbolKind) {
+        self.match_token(Token::Variable);
+        loop {
+            let (name, span) = self.expect_identifier();
+            self.declare_variable(name, kind, span);
+
+            match self.tokenizer.next_token() {
+                Token::Comma => (),
+                Token::Semicolon => break,
+                token => panic!("ERROR: Unexpected token in variable declaration: {:?}", token),
+            }
+        }
+    }
+
+    fn declare_variable(&mut self, name: String, kind: SymbolKind, span: Span) {
+        if kind != SymbolKind::Global && self.symbol_table.globals.contains_key(&name) {
+            let what = if kind == SymbolKind::Parameter { "parameter" } else { "local" };
+            self.diagnostics.push(Diagnostic::warning(span, format!("{} {} shadows a global", what, name)));
+        }
+
+        match kind {
+            SymbolKind::Parameter => self.mark_assigned(&name),
+            _ => self.mark_unassigned(&name),
+        }
+
+        let symbol = Symbol::new(name.clone(), kind, Some(span));
+        if let Err(previous) = self.symbol_table.declare_variable(symbol) {
+            self.report_duplicate(&name, span, &previous);
+        }
+    }
+
+    fn report_duplicate(&mut self, name: &str, span: Span, previous: &Symbol) {
+        let message = match previous.span {
+            Some(previous_span) => format!("{} is already declared at {}", name, previous_span),
+            None => format!("{} is already declared as a predefined function", name),
+        };
+        self.diagnostics.push(Diagnostic::error(span, message));
+    }
+
+    fn check_func_decl(&mut self) {
+        let is_void = self.tokenizer.peek_token() == Token::Void;
+        if is_void {
+            self.tokenizer.next_token();
+        }
+        self.match_token(Token::Function);
+        let (name, span) = self.expect_identifier();
+
+        self.symbol_table.enter_function(&name);
+        // a function cannot know where it is called from, so a global that anything in the program
+        // may assign starts out assigned
+        self.assigned = Some(self.may_assign.values().flatten().cloned().collect());
+        let parameter_count = self.check_formal_param();
+        self.match_token(Token::Semicolon);
+
+        // declared before the body so that it can call itself
+        let symbol = Symbol::new(name.clone(), SymbolKind::Function { is_void, parameter_count }, Some(span));
+        if let Err(previous) = self.symbol_table.declare_function(symbol) {
+            self.report_duplicate(&name, span, &previous);
+        }
+
+        if self.tokenizer.peek_token() == Token::Variable {
+            self.check_var_decl(SymbolKind::Local);
+        }
+        self.match_token(Token::OpenBrace);
+        self.check_stat_sequence();
+        self.match_token(Token::CloseBrace);
+        self.match_token(Token::Semicolon);
+
+        self.symbol_table.exit_function();
+    }
+
+    fn check_formal_param(&mut self) -> usize {
+        self.match_token(Token::OpenParen);
+        let mut parameter_count = 0;
+
+        if let Token::Identifier(_) = self.tokenizer.peek_token() {
+            loop {
+                let (name, span) = self.expect_identifier();
+                self.declare_variable(name, SymbolKind::Parameter, span);
+                parameter_count += 1;
+
+                if self.tokenizer.peek_token() != Token::Comma {
+                    break;
+                }
+                self.tokenizer.next_token();
+            }
+        }
+
+        self.match_token(Token::CloseParen);
+        parameter_count
+    }
+
+    fn check_stat_sequence(&mut self) {
+        loop {
+            match self.tokenizer.peek_token() {
+                Token::Let => self.check_assignment(),
+                Token::If => self.check_if_statement(),
+                Token::While => self.check_while_statement(),
+                Token::Return => self.check_return_statement(),
+                Token::FunctionCall => self.check_func_call(),
+                _ => break,
+            }
+
+            match self.tokenizer.peek_token() {
+                Token::Semicolon => {
+                    self.tokenizer.next_token();
+                },
+                _ => break,
+            }
+        }
+    }
+
+    fn check_assignment(&mut self) {
+        self.match_token(Token::Let);
+        let (name, span) = self.expect_identifier();
+        self.resolve_variable(&name, span);
+        self.match_token(Token::Assignment);
+        self.check_expression();
+        self.mark_assigned(&name);
+
+        if self.symbol_table.resolve_variable(&name).is_some_and(|symbol| symbol.kind == SymbolKind::Global) {
+            let function_name = self.symbol_table.get_curr_fn_name();
+            self.global_assignments.entry(function_name).or_default().insert(name);
+        }
+    }
+
+    fn check_if_statement(&mut self) {
+        self.match_token(Token::If);
+        self.check_relation();
+        self.match_token(Token::Then);
+
+        let assigned_before = self.assigned.clone();
+        self.check_stat_sequence();
+        let assigned_after_then = std::mem::replace(&mut self.assigned, assigned_before);
+        if self.tokenizer.peek_token() == Token::Else {
+            self.tokenizer.next_token();
+            self.check_stat_sequence();
+        }
+
+        // only what both paths assign is assigned after the join
+        self.assigned = match (assigned_after_then, self.assigned.take()) {
+            (Some(then_assigned), Some(else_assigned)) => Some(then_assigned.intersection(&else_assigned).cloned().collect()),
+            (then_assigned, else_assigned) => then_assigned.or(else_assigned),
+        };
+        self.match_token(Token::Fi);
+    }
+
+    fn check_while_statement(&mut self) {
+        self.match_token(Token::While);
+        let function_name = self.symbol_table.get_curr_fn_name();
+        self.loop_spans.entry(function_name).or_default().push(self.tokenizer.get_span());
+        self.check_relation();
+        self.match_token(Token::Do);
+
+        // the body may not run at all, so nothing it assigns counts after the loop
+        let assigned_before = self.assigned.clone();
+        self.check_stat_sequence();
+        self.assigned = assigned_before;
+        self.match_token(Token::Od);
+    }
+
+    fn check_return_statement(&mut self) {
+        self.match_token(Token::Return);
+        if !matches!(self.tokenizer.peek_token(), Token::Semicolon | Token::CloseBrace | Token::Fi | Token::Else | Token::Od) {
+            self.check_expression();
+        }
+        self.assigned = None;
+    }
+
+    fn check_relation(&mut self) {
+        self.check_expression();
+        match self.tokenizer.next_token() {
+            Token::Equal | Token::NotEqual | Token::Greater | Token::GreaterEqual | Token::Less | Token::LessEqual => (),
+            token => panic!("ERROR: {:?} is not a valid operator", token),
+        }
+        self.check_expression();
+    }
+
+    fn check_expression(&mut self) {
+        self.check_term();
+        while let Token::Plus | Token::Minus = self.tokenizer.peek_token() {
+            self.tokenizer.next_token();
+            self.check_term();
+        }
+    }
+
+    fn check_term(&mut self) {
+        self.check_factor();
+        while let Token::Times | Token::Divide = self.tokenizer.peek_token() {
+            self.tokenizer.next_token();
+            self.check_factor();
+        }
+    }
+
+    fn check_factor(&mut self) {
+        match self.tokenizer.peek_token() {
+            Token::Number(_) => {
+                self.tokenizer.next_token();
+            },
+            Token::Identifier(_) => {
+                let (name, span) = self.expect_identifier();
+                if let Some(symbol) = self.symbol_table.resolve_variable_mut(&name) {
+                    symbol.reads += 1;
+                }
+                if self.resolve_variable(&name, span) && !self.is_assigned(&name) {
+                    self.diagnostics.push(Diagnostic::warning(span, format!("{} may be used before it is assigned and reads as 0", name)));
+                }
+            },
+            Token::OpenParen => {
+                self.tokenizer.next_token();
+                self.check_expression();
+                self.match_token(Token::CloseParen);
+            },
+            Token::FunctionCall => self.check_func_call(),
+            token => panic!("Syntax error in factor: {:?}", token),
+        }
+    }
+
+    fn check_func_call(&mut self) {
+        self.match_token(Token::FunctionCall);
+        let (name, span) = self.expect_identifier();
+        if self.symbol_table.resolve_function(&name).is_none() {
+            self.diagnostics.push(Diagnostic::error(span, format!("call to unknown function {}", name)));
+        }
+        let caller = self.symbol_table.get_curr_fn_name();
+        self.symbol_table.calls.entry(caller).or_default().insert(name.clone());
+
+        if self.tokenizer.peek_token() == Token::OpenParen {
+            self.tokenizer.next_token();
+            if self.tokenizer.peek_token() != Token::CloseParen {
+                loop {
+                    self.check_expression();
+                    if self.tokenizer.peek_token() != Token::Comma {
+                        break;
+                    }
+                    self.tokenizer.next_token();
+                }
+            }
+            self.match_token(Token::CloseParen);
+        }
+
+        // the globals the callee may have assigned, unless a local hides them
+        let globals: Vec<String> = self.may_assign.get(&name).into_iter().flatten().cloned().collect();
+        for global in globals {
+            if self.symbol_table.resolve_variable(&global).is_some_and(|symbol| symbol.kind == SymbolKind::Global) {
+                self.mark_assigned(&global);
+            }
+        }
+    }
+
+    fn resolve_variable(&mut self, name: &str, span: Span) -> bool {
+        if self.symbol_table.resolve_variable(name).is_none() {
+            self.diagnostics.push(Diagnostic::error(span, format!("undeclared variable {}", name)));
+            return false;
+        }
+        true
+    }
+
+    fn is_assigned(&self, name: &str) -> bool {
+        self.assigned.as_ref().is_none_or(|assigned| assigned.contains(name))
+    }
+
+    fn mark_assigned(&mut self, name: &str) {
+        if let Some(assigned) = &mut self.assigned {
+            assigned.insert(name.to_string());
+        }
+    }
+
+    fn mark_unassigned(&mut self, name: &str) {
+        if let Some(assigned) = &mut self.assigned {
+            assigned.remove(name);
+        }
+    }
+
+    fn expect_identifier(&mut self) -> (String, Span) {
+        match self.tokenizer.next_token() {
+            Token::Identifier(name) => (name, self.tokenizer.get_span()),
+            token => panic!("ERROR: Expected an identifier, instead got {:?}", token),
+        }
+    }
+
+    fn match_token(&mut self, token_to_match: Token) {
+        let token = self.tokenizer.next_token();
+        if token != token_to_match {
+            panic!("ERROR: Unexpected token, expected {:?}, instead got {:?}", token_to_match, token);
+        }
+    }
+}
+
+#[cfg(test)]
+mod semantic_tests {
+    use super::*;
+
+    fn analyze(input: &str) -> SemanticAnalyzer {
+        let mut analyzer = SemanticAnalyzer::new(input.to_string());
+        analyzer.analyze();
+        analyzer
+    }
+
+    fn messages(analyzer: &SemanticAnalyzer) -> Vec<String> {
+        analyzer.diagnostics.iter().map(|diagnostic| diagnostic.to_string()).collect()
+    }
+
+    #[test]
+    fn test_valid_program() {
+        let analyzer = analyze("
+main var a;
+function f(x); var y; {
+    let y <- x + a;
+    return call f(y)
+};
+{
+    let a <- call InputNum();
+    call OutputNum(call f(a))
+}.");
+
+        assert!(analyzer.diagnostics.is_empty(), "{:?}", messages(&analyzer));
+        assert!(analyzer.symbol_table.globals.contains_key("a"));
+        assert_eq!(analyzer.symbol_table.function_scopes["f"]["x"].kind, SymbolKind::Parameter);
+        assert_eq!(analyzer.symbol_table.function_scopes["f"]["y"].kind, SymbolKind::Local);
+        assert_eq!(analyzer.symbol_table.functions["f"].kind, SymbolKind::Function { is_void: false, parameter_count: 1 });
+    }
+
+    #[test]
+    fn test_undeclared_variables() {
+        let analyzer = analyze("
+main var a;
+function f(); {
+    return b
+};
+{
+    let c <- 1
+}.");
+
+        assert!(analyzer.has_errors());
+        assert_eq!(messages(&analyzer), vec![
+            "4:12: error: undeclared variable b".to_string(),
+            "7:9: error: undeclared variable c".to_string(),
+        ]);
+    }
+
+    #[test]
+    fn test_locals_do_not_leak() {
+        let analyzer = analyze("
+main
+void function f(); var x; {
+    let x <- 1
+};
+{
+    let x <- 2
+}.");
+
+        assert_eq!(messages(&analyzer), vec!["7:9: error: undeclared variable x".to_string()]);
+    }
+
+    #[test]
+    fn test_duplicate_declarations() {
+        let analyzer = analyze("
+main var a, a;
+function f(x, x); { return x };
+void function f(); var InputNum; { };
+void function InputNum(); { };
+{ }.");
+
+        assert_eq!(messages(&analyzer), vec![
+            "2:13: error: a is already declared at 2:10".to_string(),
+            "3:15: error: x is already declared at 3:12".to_string(),
+            "4:15: error: f is already declared at 3:10".to_string(),
+            "5:15: error: InputNum is already declared as a predefined function".to_string(),
+        ]);
+    }
+
+    #[test]
+    fn test_shadowed_globals() {
+        let analyzer = analyze("
+main var a, b;
+function f(a); var b; { let b <- a; return a + b };
+{ }.");
+
+        assert!(!analyzer.has_errors());
+        assert_eq!(messages(&analyzer), vec![
+            "3:12: warning: parameter a shadows a global".to_string(),
+            "3:20: warning: local b shadows a global".to_string(),
+        ]);
+    }
+
+    #[test]
+    fn test_unknown_function() {
+        let analyzer = analyze("
+main var a;
+{
+    let a <- call g(1);
+    call h
+}.");
+
+        assert_eq!(messages(&analyzer), vec![
+            "4:19: error: call to unknown function g".to_string(),
+            "5:10: error: call to unknown function h".to_string(),
+        ]);
+    }
+
+    #[test]
+    fn test_uninitialized_reads() {
+        let analyzer = analyze("
+main var a, b, c, d;
+{
+    if a > 0 then
+        let b <- 1;
+        let c <- 1
+    else
+        let b <- 2
+    fi;
+    while b > 0 do
+        call OutputNum(d);
+        let d <- 1
+    od;
+    call OutputNum(b + c + d)
+}.");
+
+        assert!(!analyzer.has_errors());
+        assert_eq!(messages(&analyzer), vec![
+            "4:8: warning: a may be used before it is assigned and reads as 0".to_string(),
+            "11:24: warning: d may be used before it is assigned and reads as 0".to_string(),
+            "14:24: warning: c may be used before it is assigned and reads as 0".to_string(),
+            "14:28: warning: d may be used before it is assigned and reads as 0".to_string(),
+        ]);
+    }
+
+    #[test]
+    fn test_assignment_through_returns_and_calls() {
+        let analyzer = analyze("
+main var g;
+function f(x); var y; {
+    if x > 0 then
+        return g
+    else
+        let y <- x
+    fi;
+    return y
+};
+void function init(); { let g <- 1 };
+void function start(); { call init() };
+{
+    call start();
+    call OutputNum(g)
+}.");
+
+        assert!(analyzer.diagnostics.is_empty(), "{:?}", messages(&analyzer));
+
+        // only the globals a callee may assign count as assigned after the call, and a global
+        // nothing assigns is unassigned in every function
+        let analyzer = analyze("
+main var g, h;
+function f(); { return h };
+void function init(); { let g <- 1 };
+{
+    call init();
+    call OutputNum(g + h)
+}.");
+
+        assert_eq!(messages(&analyzer), vec![
+            "3:24: warning: h may be used before it is assigned and reads as 0".to_string(),
+            "7:24: warning: h may be used before it is assigned and reads as 0".to_string(),
+        ]);
+    }
+}
@@ -0,0 +1,284 @@
+use petgraph::graph::NodeIndex;
+
+use crate::constant_block::{get_constant_value, ConstantBlock};
+use crate::function::{find_line, get_new_line, get_operation, remove_instruction, replace_uses};
+use crate::instruction::{Instruction, Operation};
+use crate::live_analysis::BasicBlockGraph;
+use crate::loop_analysis::{find_loops, Loop};
+use crate::program::Program;
+
+type LineNumber = isize;
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct StrengthReductionStatistics {
+    /// multiplications of an induction variable replaced by a derived variable
+    pub reduced_multiplications: usize,
+    /// loop exit tests moved over to a derived variable
+    pub rewritten_exit_tests: usize,
+    /// induction variables that were only kept alive by their exit test
+    pub removed_induction_variables: usize,
+}
+
+// a header phi that starts at `initial` and goes up by `step` on every iteration
+struct InductionVariable {
+    line: LineNumber,
+    initial: LineNumber,
+    step: isize,
+    // the line computing the value for the next iteration
+    update: LineNumber,
+}
+
+// a header phi tracking an induction variable times a constant
+struct DerivedVariable {
+    line: LineNumber,
+    factor: isize,
+}
+
+struct Rewriter<'a> {
+    g: &'a mut BasicBlockGraph,
+    constants: &'a mut ConstantBlock,
+    next_line: &'a mut LineNumber,
+}
+
+impl Rewriter<'_> {
+    fn is_loop_invariant(&self, found: &Loop, line: LineNumber) -> bool {
+        line <= 0 || find_line(self.g, line).is_some_and(|(block, _)| !found.contains(block))
+    }
+
+    fn count_uses(&self, line: LineNumber) -> usize {
+        self.g.node_weights().flat_map(|block| &block.instructions).filter(|instruction| instruction.operation.uses_line(line)).count()
+    }
+
+    fn remove(&mut self, line: LineNumber) {
+        let (block, position) = find_line(self.g, line).unwrap();
+        remove_instruction(self.g, block, position);
+    }
+
+    // the preheader ends by falling into the header or branching to it, the new instruction goes before that
+    fn insert_in_preheader(&mut self, preheader: NodeIndex, operation: Operation) -> LineNumber {
+        let line = get_new_line(self.next_line);
+        let instructions = &mut self.g[preheader].instructions;
+        let position = instructions.iter().rposition(|instruction| !instruction.operation.is_branch()).map_or(0, |position| position + 1);
+        instructions.insert(position, Instruction::new(line, operation));
+        line
+    }
+
+    // value * factor, folded when the value is a constant
+    fn multiply_before_loop(&mut self, found: &Loop, value: LineNumber, factor: isize) -> Option<LineNumber> {
+        match get_constant_value(value) {
+            Some(constant) => constant.checked_mul(factor).map(|product| self.constants.get_constant(product)),
+            None => {
+                let preheader = found.get_preheader(self.g)?;
+                let factor = self.constants.get_constant(factor);
+                Some(self.insert_in_preheader(preheader, Operation::Mul(value, factor)))
+            },
+        }
+    }
+
+    // adding a negative step subtracts, as constants are never negative
+    fn get_step_operation(&mut self, value: LineNumber, step: isize) -> Operation {
+        if step >= 0 {
+            Operation::Add(value, self.constants.get_constant(step))
+        } else {
+            Operation::Sub(value, self.constants.get_constant(-step))
+        }
+    }
+
+    fn find_induction_variables(&self, found: &Loop) -> Vec<InductionVariable> {
+        let mut induction_variables = Vec::new();
+        for phi in &self.g[found.header].instructions {
+            let Operation::Phi(initial, update) = phi.operation else {
+                continue;
+            };
+            let line = phi.get_line_number();
+            if !find_line(self.g, update).is_some_and(|(block, _)| found.contains(block)) {
+                continue;
+            }
+            let step = match get_operation(self.g, update) {
+                Some(Operation::Add(left, right)) if left == line => get_constant_value(right),
+                Some(Operation::Add(left, right)) if right == line => get_constant_value(left),
+                Some(Operation::Sub(left, right)) if left == line => get_constant_value(right).map(|constant| -constant),
+                _ => None,
+            };
+            if let Some(step) = step {
+                induction_variables.push(InductionVariable { line, initial, step, update });
+            }
+        }
+        induction_variables
+    }
+
+    // i * k inside the loop becomes a phi that starts at initial * k and goes up by step * k
+    fn reduce_loop(&mut self, found: &Loop, rewrite_exit_tests: bool, statistics: &mut StrengthReductionStatistics) {
+        for induction_variable in self.find_induction_variables(found) {
+            let mut derived_variables = Vec::<DerivedVariable>::new();
+
+            let mut blocks: Vec<NodeIndex> = found.blocks.iter().copied().collect();
+            blocks.sort();
+            let multiplications: Vec<(LineNumber, isize)> = blocks.iter()
+                .flat_map(|block| &self.g[*block].instructions)
+                .filter_map(|instruction| match instruction.operation {
+                    Operation::Mul(left, right) if left == induction_variable.line => get_constant_value(right).map(|factor| (instruction.get_line_number(), factor)),
+                    Operation::Mul(left, right) if right == induction_variable.line => get_constant_value(left).map(|factor| (instruction.get_line_number(), factor)),
+                    _ => None,
+                })
+                .filter(|(_, factor)| *factor > 1)
+                .collect();
+
+            for (multiplication, factor) in multiplications {
+                let derived = match derived_variables.iter().find(|derived| derived.factor == factor) {
+                    Some(derived) => derived.line,
+                    None => {
+                        let Some(step) = induction_variable.step.checked_mul(factor) else {
+                            continue;
+                        };
+                        let Some(initial) = self.multiply_before_loop(found, induction_variable.initial, factor) else {
+                            continue;
+                        };
+                        let line = get_new_line(self.next_line);
+                        let update = get_new_line(self.next_line);
+
+                        let header = &mut self.g[found.header].instructions;
+                        let phi_count = header.iter().take_while(|instruction| matches!(instruction.operation, Operation::Phi(_, _))).count();
+                        header.insert(phi_count, Instruction::new(line, Operation::Phi(initial, update)));
+
+                        let step = self.get_step_operation(line, step);
+                        let (block, position) = find_line(self.g, induction_variable.update).unwrap();
+                        self.g[block].instructions.insert(position + 1, Instruction::new(update, step));
+
+                        derived_variables.push(DerivedVariable { line, factor });
+                        line
+                    },
+                };
+                self.remove(multiplication);
+                replace_uses(self.g, multiplication, derived);
+                statistics.reduced_multiplications += 1;
+            }
+
+            if rewrite_exit_tests {
+                if let Some(derived) = derived_variables.first() {
+                    self.rewrite_exit_test(found, &induction_variable, derived, statistics);
+                }
+            }
+        }
+    }
+
+    // i < n is i * k < n * k for a positive k, as long as neither side wraps
+    fn rewrite_exit_test(&mut self, found: &Loop, induction_variable: &InductionVariable, derived: &DerivedVariable, statistics: &mut StrengthReductionStatistics) {
+        let comparison = self.g[found.header].instructions.iter().find_map(|instruction| match instruction.operation {
+            Operation::Cmp(left, right) if left == induction_variable.line => Some((instruction.get_line_number(), right, true)),
+            Operation::Cmp(left, right) if right == induction_variable.line => Some((instruction.get_line_number(), left, false)),
+            _ => None,
+        });
+        let Some((comparison, bound, induction_variable_on_left)) = comparison else {
+            return;
+        };
+        if !self.is_loop_invariant(found, bound) {
+            return;
+        }
+        let Some(bound) = self.multiply_before_loop(found, bound, derived.factor) else {
+            return;
+        };
+
+        let (block, position) = find_line(self.g, comparison).unwrap();
+        self.g[block].instructions[position].operation = if induction_variable_on_left {
+            Operation::Cmp(derived.line, bound)
+        } else {
+            Operation::Cmp(bound, derived.line)
+        };
+        statistics.rewritten_exit_tests += 1;
+
+        // the phi and its update only feed each other now
+        if self.count_uses(induction_variable.line) == 1 && self.count_uses(induction_variable.update) == 1 {
+            self.remove(induction_variable.line);
+            self.remove(induction_variable.update);
+            statistics.removed_induction_variables += 1;
+        }
+    }
+}
+
+/// Finds the basic induction variables of every loop, header phis that go up or down by a
+/// constant on every iteration, and replaces their multiplications by a constant with phis
+/// of their own that are updated by addition. With `rewrite_exit_tests` a loop test on the
+/// induction variable is moved over to the derived one, which may change the result when
+/// the derived variable wraps around.
+pub fn reduce_strength(program: &mut Program, rewrite_exit_tests: bool) -> StrengthReductionStatistics {
+    let mut next_line = program.get_next_line();
+
+    let mut statistics = StrengthReductionStatistics::default();
+    let mut function_names: Vec<String> = program.functions.keys().cloned().collect();
+    function_names.sort();
+
+    for function_name in function_names {
+        let function = program.functions.get_mut(&function_name).unwrap();
+        let loops = find_loops(&function.bb_graph);
+        let mut rewriter = Rewriter { g: &mut function.bb_graph, constants: &mut program.constant_block, next_line: &mut next_line };
+        for found in &loops {
+            rewriter.reduce_loop(found, rewrite_exit_tests, &mut statistics);
+        }
+    }
+
+    statistics
+}
+
+#[cfg(test)]
+mod strength_reduction_tests {
+    use super::*;
+    use crate::test_helpers::{get_program, interpret};
+
+    fn count_multiplications(program: &Program) -> usize {
+        program.get_fn("main").bb_graph.node_weights()
+            .flat_map(|block| &block.instructions)
+            .filter(|instruction| matches!(instruction.operation, Operation::Mul(_, _)))
+            .count()
+    }
+
+    const SUM: &str = "main var i, n, s; { let n <- call InputNum(); let i <- 0; let s <- 0; while i < n do let s <- s + i * 4; let i <- i + 1 od; call OutputNum(s) }.";
+
+    #[test]
+    fn test_multiplication_replaced() {
+        let mut program = get_program(SUM);
+        let expected = interpret(&program, "5");
+
+        let statistics = reduce_strength(&mut program, false);
+        assert_eq!(statistics.reduced_multiplications, 1);
+        assert_eq!(statistics.rewritten_exit_tests, 0);
+        assert_eq!(count_multiplications(&program), 0);
+        assert_eq!(interpret(&program, "5"), expected);
+        assert_eq!(expected, "40 ");
+    }
+
+    #[test]
+    fn test_exit_test_rewritten() {
+        let mut program = get_program(SUM);
+        let statistics = reduce_strength(&mut program, true);
+
+        assert_eq!(statistics, StrengthReductionStatistics { reduced_multiplications: 1, rewritten_exit_tests: 1, removed_induction_variables: 1 });
+        // n * 4 is computed once before the loop
+        assert_eq!(count_multiplications(&program), 1);
+        for (input, output) in [("5", "40 "), ("0", "0 "), ("1", "0 ")] {
+            assert_eq!(interpret(&program, input), output);
+        }
+    }
+
+    #[test]
+    fn test_counting_down_and_used_after_loop() {
+        let input = "main var i, s; { let i <- 10; let s <- 0; while i > 0 do let s <- s + 3 * i; let i <- i - 2 od; call OutputNum(s); call OutputNum(i) }.";
+        let mut program = get_program(input);
+        let expected = interpret(&program, "");
+
+        let statistics = reduce_strength(&mut program, true);
+        assert_eq!(statistics.reduced_multiplications, 1);
+        assert_eq!(statistics.rewritten_exit_tests, 1);
+        // i is printed after the loop, so it stays
+        assert_eq!(statistics.removed_induction_variables, 0);
+        assert_eq!(interpret(&program, ""), expected);
+        assert_eq!(expected, "90 0 ");
+    }
+
+    #[test]
+    fn test_not_an_induction_variable() {
+        let input = "main var i, s; { let i <- 1; let s <- 0; while i < 100 do let s <- s + i * 4; let i <- i * 2 od; call OutputNum(s) }.";
+        let mut program = get_program(input);
+        assert_eq!(reduce_strength(&mut program, true), StrengthReductionStatistics::default());
+    }
+}
@@ -0,0 +1,27 @@
+use crate::emulator::Emulator;
+use crate::interpreter::Interpreter;
+use crate::linker::link;
+use crate::parser::Parser;
+use crate::program::Program;
+
+/// Parses a whole program into its IR.
+pub fn get_program(input: &str) -> Program {
+    let mut parser = Parser::new(input.to_string());
+    parser.parse_computation();
+    parser.internal_program
+}
+
+/// Runs the IR of a program on the given input and returns what it printed.
+pub fn interpret(program: &Program, input: &str) -> String {
+    let mut interpreter = Interpreter::new(program, input.as_bytes(), Vec::new());
+    interpreter.run().unwrap();
+    String::from_utf8(interpreter.get_writer().clone()).unwrap()
+}
+
+/// Links a program and runs it in the emulator on the given input, returning what it printed.
+pub fn emulate(program: &Program, input: &str) -> String {
+    let object = link(program).unwrap();
+    let mut emulator = Emulator::from_object_file(&object, input.as_bytes(), Vec::new()).unwrap();
+    emulator.run().unwrap();
+    String::from_utf8(emulator.get_writer().clone()).unwrap()
+}
@@ -1,4 +1,7 @@
+use std::fmt;
+
 /// Token types representing different elements of a simple programming language.
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Debug, PartialEq, Clone)]
 pub enum Token {
     Number(isize),
@@ -38,10 +41,24 @@ pub enum Token {
 }
 
 
+/// A position in the source, both line and column start at 1.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub struct Span {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
 /// A tokenizer that converts a string input into a series of tokens.
 pub struct Tokenizer {
     input: Vec<u8>,
     pos: usize,
+    token_start: usize,
 }
 
 impl Tokenizer {
@@ -50,6 +67,7 @@ impl Tokenizer {
         Self {
             input: input.into_bytes(),
             pos: 0,
+            token_start: 0,
         }
     }
 
@@ -88,7 +106,7 @@ impl Tokenizer {
 
     /// Tokenize a sequence of digits into a Number token.
     fn tokenize_number(&mut self) -> Token {
-        let number_str = self.consume_while(|c| c.is_digit(10));
+        let number_str = self.consume_while(|c| c.is_ascii_digit());
         Token::Number(number_str.parse::<isize>().unwrap())
     }
 
@@ -160,15 +178,41 @@ impl Tokenizer {
     /// Peeks the next token from the input, without advancing the tokenizer.
     pub fn peek_token(&mut self) -> Token {
         let previous_pos= self.pos;
+        let previous_token_start = self.token_start;
         let token = self.next_token();
         self.pos = previous_pos;
+        self.token_start = previous_token_start;
 
         token
     }
 
+    /// Returns where the most recently consumed token starts.
+    pub fn get_span(&self) -> Span {
+        let before_token = &self.input[..self.token_start];
+        let line_start = before_token.iter().rposition(|&c| c == b'\n').map_or(0, |newline| newline + 1);
+
+        Span {
+            line: before_token.iter().filter(|&&c| c == b'\n').count() + 1,
+            column: self.token_start - line_start + 1,
+        }
+    }
+
+    /// Returns where the next token starts, without advancing the tokenizer.
+    pub fn peek_span(&mut self) -> Span {
+        let previous_pos = self.pos;
+        let previous_token_start = self.token_start;
+        self.next_token();
+        let span = self.get_span();
+        self.pos = previous_pos;
+        self.token_start = previous_token_start;
+
+        span
+    }
+
     /// Retrieve the next token from the input, advancing the tokenizer.
     pub fn next_token(&mut self) -> Token {
         self.consume_whitespace();
+        self.token_start = self.pos;
 
         let token = match self.peek_char() {
             '+' => Token::Plus,
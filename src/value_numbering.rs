@@ -0,0 +1,253 @@
+use std::collections::HashMap;
+
+use petgraph::algo::dominators::{simple_fast, Dominators};
+use petgraph::graph::NodeIndex;
+use petgraph::visit::{DfsPostOrder, EdgeRef};
+use petgraph::Direction::Incoming;
+
+use crate::function::{get_new_line, remove_instruction, replace_uses};
+use crate::instruction::{Instruction, Operation};
+use crate::live_analysis::BasicBlockGraph;
+use crate::program::Program;
+
+type LineNumber = isize;
+// a value is numbered by the first line found to compute it
+type ValueNumber = isize;
+// the line holding every value that is available, by value number
+type Available = HashMap<ValueNumber, LineNumber>;
+
+struct ValueNumbering<'a> {
+    g: &'a mut BasicBlockGraph,
+    dominators: Dominators<NodeIndex>,
+    next_line: &'a mut LineNumber,
+    numbers: HashMap<LineNumber, ValueNumber>,
+    expressions: HashMap<Operation, ValueNumber>,
+    // phis are only the same value when they are in the same block
+    phis: HashMap<(NodeIndex, ValueNumber, ValueNumber), ValueNumber>,
+    available_out: HashMap<NodeIndex, Available>,
+    // phis made for values computed on both sides of a join
+    inserted_phis: HashMap<(NodeIndex, ValueNumber), LineNumber>,
+    removed: usize,
+}
+
+// the blocks reachable from the entry, every block after its dominators and after the
+// predecessors it has outside of loops
+fn get_reverse_postorder(g: &BasicBlockGraph) -> Vec<NodeIndex> {
+    let mut order = Vec::new();
+    let mut dfs = DfsPostOrder::new(g, NodeIndex::new(0));
+    while let Some(block) = dfs.next(g) {
+        order.push(block);
+    }
+    order.reverse();
+    order
+}
+
+impl ValueNumbering<'_> {
+    fn get_number(&self, line: LineNumber) -> ValueNumber {
+        // constants and lines defined later, on a loop back edge, are their own value
+        self.numbers.get(&line).copied().unwrap_or(line)
+    }
+
+    // the operation over value numbers, the operands of commutative ones in a fixed order
+    fn get_expression(&self, operation: Operation) -> Option<Operation> {
+        let expression = match operation {
+            Operation::Add(l, r) => Operation::Add(self.get_number(l).min(self.get_number(r)), self.get_number(l).max(self.get_number(r))),
+            Operation::Mul(l, r) => Operation::Mul(self.get_number(l).min(self.get_number(r)), self.get_number(l).max(self.get_number(r))),
+            Operation::Sub(l, r) => Operation::Sub(self.get_number(l), self.get_number(r)),
+            Operation::Div(l, r) => Operation::Div(self.get_number(l), self.get_number(r)),
+            Operation::Cmp(l, r) => Operation::Cmp(self.get_number(l), self.get_number(r)),
+            _ => return None,
+        };
+        Some(expression)
+    }
+
+    fn remove(&mut self, block: NodeIndex, line: LineNumber) {
+        let position = self.g[block].instructions.iter().position(|instruction| instruction.get_line_number() == line).unwrap();
+        remove_instruction(self.g, block, position);
+        self.removed += 1;
+    }
+
+    // A value that is not available in a block may still have been computed on both sides of a
+    // join dominating it, a phi there merges the two.
+    fn get_merged_value(&mut self, block: NodeIndex, value: ValueNumber) -> Option<LineNumber> {
+        let mut join = Some(block);
+        while let Some(current) = join {
+            if let Some(phi) = self.inserted_phis.get(&(current, value)) {
+                return Some(*phi);
+            }
+
+            // phi operands are in the order of the incoming edges
+            let mut incoming: Vec<_> = self.g.edges_directed(current, Incoming).collect();
+            incoming.sort_by_key(|edge| edge.id());
+            let operands: Vec<Option<LineNumber>> = incoming.iter()
+                .map(|edge| self.available_out.get(&edge.source()).and_then(|available| available.get(&value)).copied())
+                .collect();
+            if let [Some(left), Some(right)] = operands[..] {
+                let phi = get_new_line(self.next_line);
+                self.g[current].instructions.insert(0, Instruction::new(phi, Operation::Phi(left, right)));
+                self.numbers.insert(phi, value);
+                self.inserted_phis.insert((current, value), phi);
+                return Some(phi);
+            }
+            join = self.dominators.immediate_dominator(current);
+        }
+        None
+    }
+
+    fn number_block(&mut self, block: NodeIndex) {
+        let mut available = self.dominators.immediate_dominator(block)
+            .and_then(|dominator| self.available_out.get(&dominator))
+            .cloned()
+            .unwrap_or_default();
+
+        let lines: Vec<(LineNumber, Operation)> = self.g[block].instructions.iter()
+            .map(|instruction| (instruction.get_line_number(), instruction.operation))
+            .collect();
+        for (line, operation) in lines {
+            // the earlier line with the same value, when there is one this line is redundant
+            let leader = match operation {
+                Operation::Phi(l, r) if self.get_number(l) == self.get_number(r) => {
+                    let value = self.get_number(l);
+                    self.numbers.insert(line, value);
+                    // both operands are the same value, the phi is that value where it is available
+                    available.get(&value).copied()
+                },
+                Operation::Phi(l, r) => {
+                    let key = (block, self.get_number(l), self.get_number(r));
+                    let value = *self.phis.entry(key).or_insert(line);
+                    self.numbers.insert(line, value);
+                    available.get(&value).copied()
+                },
+                _ => {
+                    let Some(expression) = self.get_expression(operation) else {
+                        continue;
+                    };
+                    let value = *self.expressions.entry(expression).or_insert(line);
+                    self.numbers.insert(line, value);
+                    match available.get(&value) {
+                        Some(leader) => Some(*leader),
+                        None => self.get_merged_value(block, value),
+                    }
+                },
+            };
+
+            match leader {
+                Some(leader) if leader != line => {
+                    replace_uses(self.g, line, leader);
+                    self.remove(block, line);
+                },
+                _ => {
+                    available.insert(self.numbers[&line], line);
+                },
+            }
+        }
+        self.available_out.insert(block, available);
+    }
+}
+
+/// Global value numbering. Every arithmetic instruction, comparison and phi gets a number that
+/// is the same for instructions computing the same value, also in blocks that do not dominate
+/// each other, and a phi of two operands with the same number has that number as well. An
+/// instruction whose value is already available in a dominating line is removed, and a value
+/// that was computed on both sides of a join is merged by a phi there before it is computed
+/// again. Returns how many instructions were removed.
+pub fn number_values(program: &mut Program) -> usize {
+    let mut next_line = program.get_next_line();
+
+    let mut function_names: Vec<String> = program.functions.keys().cloned().collect();
+    function_names.sort();
+
+    let mut removed = 0;
+    for function_name in function_names {
+        let g = &mut program.get_fn_mut(&function_name).bb_graph;
+        if g.node_count() == 0 {
+            continue;
+        }
+        let dominators = simple_fast(&*g, NodeIndex::new(0));
+        let order = get_reverse_postorder(g);
+        let mut numbering = ValueNumbering {
+            g,
+            dominators,
+            next_line: &mut next_line,
+            numbers: HashMap::new(),
+            expressions: HashMap::new(),
+            phis: HashMap::new(),
+            available_out: HashMap::new(),
+            inserted_phis: HashMap::new(),
+            removed: 0,
+        };
+        for block in order {
+            numbering.number_block(block);
+        }
+        removed += numbering.removed;
+    }
+    removed
+}
+
+#[cfg(test)]
+mod value_numbering_tests {
+    use super::*;
+    use crate::test_helpers::{get_program, interpret};
+
+    fn get_operations(program: &Program) -> Vec<Operation> {
+        program.get_fn("main").bb_graph.node_weights()
+            .flat_map(|block| &block.instructions)
+            .map(|instruction| instruction.operation)
+            .collect()
+    }
+
+    #[test]
+    fn test_phi_of_same_value() {
+        // both sides of the if compute a + b, so c is a + b after it
+        let input = "main var a, b, c; { let a <- call InputNum(); let b <- call InputNum(); if a > b then let c <- a + b else let c <- b + a fi; call OutputNum(c); call OutputNum(a + b) }.";
+        let mut program = get_program(input);
+        let expected: Vec<String> = ["1\n2", "4\n3"].iter().map(|input| interpret(&program, input)).collect();
+
+        assert_eq!(number_values(&mut program), 1);
+        let additions = get_operations(&program).into_iter().filter(|operation| matches!(operation, Operation::Add(_, _))).count();
+        assert_eq!(additions, 2);
+        assert_eq!(["1\n2", "4\n3"].iter().map(|input| interpret(&program, input)).collect::<Vec<_>>(), expected);
+        assert_eq!(expected, vec!["3 3 ", "7 7 "]);
+    }
+
+    #[test]
+    fn test_phi_of_same_line() {
+        // the parser only reuses a * 3 when it is written the same way, so c has a phi of two lines
+        let input = "main var a, c; { let a <- call InputNum(); let c <- 3 * a; if a > 2 then let c <- a * 3 fi; call OutputNum(c) }.";
+        let mut program = get_program(input);
+        let expected = interpret(&program, "5");
+
+        assert_eq!(number_values(&mut program), 2);
+        let operations = get_operations(&program);
+        assert!(operations.iter().all(|operation| !matches!(operation, Operation::Phi(_, _))));
+        assert_eq!(operations.iter().filter(|operation| matches!(operation, Operation::Mul(_, _))).count(), 1);
+        assert_eq!(interpret(&program, "5"), expected);
+        assert_eq!(expected, "15 ");
+    }
+
+    #[test]
+    fn test_merge_at_join() {
+        // a * b is computed on both sides but not kept in a variable, a phi is made for it
+        let input = "main var a, b; { let a <- call InputNum(); let b <- call InputNum(); if a < b then call OutputNum(a * b) else call OutputNum(b * a + 1) fi; call OutputNum(a * b) }.";
+        let mut program = get_program(input);
+        let expected: Vec<String> = ["2\n5", "5\n2"].iter().map(|input| interpret(&program, input)).collect();
+
+        assert_eq!(number_values(&mut program), 1);
+        let operations = get_operations(&program);
+        assert_eq!(operations.iter().filter(|operation| matches!(operation, Operation::Mul(_, _))).count(), 2);
+        assert_eq!(operations.iter().filter(|operation| matches!(operation, Operation::Phi(_, _))).count(), 1);
+        assert_eq!(["2\n5", "5\n2"].iter().map(|input| interpret(&program, input)).collect::<Vec<_>>(), expected);
+        assert_eq!(expected, vec!["10 10 ", "11 10 "]);
+    }
+
+    #[test]
+    fn test_loop() {
+        let input = "main var i, s, t; { let i <- 0; let s <- 0; let t <- 0; while i < 4 do let s <- s + i * 2; let t <- t + i * 2; let i <- i + 1 od; call OutputNum(s); call OutputNum(t) }.";
+        let mut program = get_program(input);
+        let expected = interpret(&program, "");
+
+        number_values(&mut program);
+        assert_eq!(interpret(&program, ""), expected);
+        assert_eq!(expected, "12 12 ");
+    }
+}